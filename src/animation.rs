@@ -0,0 +1,379 @@
+//! Zoom videos: keyframed scenes, interpolated and rendered to a numbered PNG sequence.
+//!
+//! A keyframes file is a list of scene files like a bookmarks file, each one starting with a `[[keyframe]]` line
+//! and the `time` it's reached at in seconds. The other keys before the scene describe the way to the next one:
+//!
+//! ```text
+//! [[keyframe]]
+//! time = 0
+//! easing = "ease-in-out"      # linear, ease-in, ease-out or ease-in-out [default: linear]
+//! blend_exponent = true       # interpolate these, instead of keeping them until the next keyframe
+//! blend_julia = true          # [default: true]
+//! blend_palette_speed = true
+//! version = 1
+//!
+//! [camera]
+//! ...
+//! ```
+//!
+//! The camera is always interpolated, with the zoom changing at a constant rate on a logarithmic scale, and the
+//! maximum number of iterations follows along linearly. Everything else is taken from the earlier keyframe.
+
+use std::{fs, io, path::Path};
+
+use eframe::wgpu;
+
+use crate::bigfloat::BigFloat;
+use crate::floatexp::FloatExp;
+use crate::scene::{self, ParseSceneError, Scene};
+use crate::vector2::Vector2Big;
+use crate::{Application, CameraInfo};
+
+/// The line every keyframe in a keyframes file starts with
+const SEPARATOR: &str = "[[keyframe]]";
+
+/// How the way between two keyframes speeds up and slows down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    /// Start slow
+    EaseIn,
+    /// End slow
+    EaseOut,
+    /// Start and end slow
+    EaseInOut,
+}
+
+impl Easing {
+    /// The names of the easing curves in keyframe files
+    pub const NAMES: [(&'static str, Self); 4] = [
+        ("linear", Self::Linear),
+        ("ease-in", Self::EaseIn),
+        ("ease-out", Self::EaseOut),
+        ("ease-in-out", Self::EaseInOut),
+    ];
+
+    /// How far along the way the curve is at the fraction `t` of the time, both from 0 to 1
+    #[must_use]
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t,
+            Self::EaseOut => t * (2.0 - t),
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// A scene to pass through at a point in time
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    /// The time the scene is reached, in seconds from the start of the animation
+    pub time: f64,
+    pub scene: Scene,
+    /// The easing of the way to the next keyframe
+    pub easing: Easing,
+    /// Whether the way to the next keyframe interpolates these, instead of keeping the values of this keyframe
+    pub blend_exponent: bool,
+    pub blend_julia: bool,
+    pub blend_palette_speed: bool,
+}
+
+impl Keyframe {
+    /// A keyframe at `time` that moves linearly and interpolates everything it can, like the defaults of a file
+    #[must_use]
+    pub fn new(time: f64, scene: Scene) -> Self {
+        Self {
+            time,
+            scene,
+            easing: Easing::Linear,
+            blend_exponent: true,
+            blend_julia: true,
+            blend_palette_speed: true,
+        }
+    }
+
+    /// The scene at the fraction `t` of the way from this keyframe to `next`, after easing
+    fn interpolate(&self, next: &Self, t: f64) -> Scene {
+        let (a, b) = (&self.scene, &next.scene);
+        let mut scene = a.clone();
+        scene.camera = interpolate_camera(&a.camera, &b.camera, t);
+        scene.max_iter = lerp(a.max_iter as f64, b.max_iter as f64, t).round() as i32;
+        if self.blend_exponent {
+            scene.exponent = lerp(a.exponent as f64, b.exponent as f64, t) as f32;
+        }
+        if self.blend_julia {
+            scene.julia_pos = a.julia_pos.lerp(b.julia_pos, t);
+        }
+        if self.blend_palette_speed {
+            scene.palette_speed = lerp(a.palette_speed as f64, b.palette_speed as f64, t) as f32;
+        }
+        scene
+    }
+}
+
+/// Keyframes sorted by time, there is always at least one
+#[derive(Debug, Clone)]
+pub struct Animation {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Animation {
+    /// An animation through `keyframes`, [`None`] if there are none, a time isn't finite or the times don't go up
+    #[must_use]
+    pub fn new(keyframes: Vec<Keyframe>) -> Option<Self> {
+        let finite = keyframes.iter().all(|keyframe| keyframe.time.is_finite());
+        let increasing = keyframes.windows(2).all(|pair| pair[0].time < pair[1].time);
+        (!keyframes.is_empty() && finite && increasing).then_some(Self { keyframes })
+    }
+
+    #[must_use]
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// The time from the first keyframe to the last, in seconds
+    #[must_use]
+    pub fn duration(&self) -> f64 {
+        self.keyframes[self.keyframes.len() - 1].time - self.keyframes[0].time
+    }
+
+    /// The number of frames at `fps` frames per second, including the first and last keyframes.
+    /// [`None`] if `fps` isn't a positive number or there are too many frames to count
+    #[must_use]
+    pub fn frame_count(&self, fps: f64) -> Option<u32> {
+        if !fps.is_finite() || fps <= 0.0 {
+            return None;
+        }
+        // Rounded, so a duration that is a whole number of frames doesn't lose the last one to rounding errors
+        let frames = (self.duration() * fps).round();
+        if frames < f64::from(u32::MAX) {
+            (frames as u32).checked_add(1)
+        } else {
+            None
+        }
+    }
+
+    /// The scene at `time` seconds from the start of the animation
+    #[must_use]
+    pub fn scene_at(&self, time: f64) -> Scene {
+        let time = time + self.keyframes[0].time;
+        let next = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 {
+            return self.keyframes[0].scene.clone();
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].scene.clone();
+        }
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = (time - a.time) / (b.time - a.time);
+        a.interpolate(b, a.easing.apply(t))
+    }
+
+    /// Render every frame at `fps` frames per second into `directory`, as `frame_00000.png` and so on.
+    /// `progress` is called after every frame with the number of frames done and the total
+    ///
+    /// # Errors
+    /// If there's no [`Animation::frame_count`] at `fps`, the directory can't be created or a frame can't be written
+    pub fn render_frames(
+        &self,
+        directory: &Path,
+        fps: f64,
+        width: u32,
+        height: u32,
+        gpu: Option<(&wgpu::Device, &wgpu::Queue)>,
+        mut progress: impl FnMut(u32, u32),
+    ) -> Result<(), png::EncodingError> {
+        let Some(frames) = self.frame_count(fps) else {
+            let message = "the frame rate has to be positive, and not give more frames than can be counted";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        };
+        fs::create_dir_all(directory)?;
+        let mut application = Application::default();
+        for frame in 0..frames {
+            application.set_scene(self.scene_at(frame as f64 / fps));
+            application.export_image(&directory.join(format!("frame_{frame:05}.png")), width, height, gpu)?;
+            progress(frame + 1, frames);
+        }
+        Ok(())
+    }
+
+    /// Write the keyframes in the keyframes file format
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        self.keyframes
+            .iter()
+            .map(|keyframe| {
+                format!(
+                    "\
+{SEPARATOR}
+time = {time}
+easing = {easing}
+blend_exponent = {blend_exponent}
+blend_julia = {blend_julia}
+blend_palette_speed = {blend_palette_speed}
+{scene}",
+                    time = keyframe.time,
+                    easing = scene::quote(scene::name_of(&Easing::NAMES, keyframe.easing)),
+                    blend_exponent = keyframe.blend_exponent,
+                    blend_julia = keyframe.blend_julia,
+                    blend_palette_speed = keyframe.blend_palette_speed,
+                    scene = keyframe.scene.to_toml(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Read a keyframes file, see the [module docs](self) for the format
+    ///
+    /// # Errors
+    /// If one of the scenes isn't valid, a keyframe has no time, or the times don't go up
+    pub fn from_text(text: &str) -> Result<Self, ParseSceneError> {
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        for table in scene::parse_list(text, SEPARATOR)? {
+            let time: f64 = table
+                .get("time")?
+                .ok_or_else(|| table.error("time", "every keyframe needs a time"))?;
+            if !time.is_finite() {
+                return Err(table.error("time", "time: expected a finite number"));
+            }
+            if keyframes.last().is_some_and(|last| last.time >= time) {
+                return Err(table.error("time", "the keyframes have to be in the order of their times"));
+            }
+            let mut keyframe = Keyframe::new(time, Scene::from_table(&table)?);
+            if let Some(name) = table.get_string("easing")? {
+                keyframe.easing = scene::find_name(&Easing::NAMES, &name)
+                    .ok_or_else(|| table.error("easing", &format!("unknown easing {name}")))?;
+            }
+            table.read("blend_exponent", &mut keyframe.blend_exponent)?;
+            table.read("blend_julia", &mut keyframe.blend_julia)?;
+            table.read("blend_palette_speed", &mut keyframe.blend_palette_speed)?;
+            keyframes.push(keyframe);
+        }
+        Self::new(keyframes).ok_or(ParseSceneError {
+            line: 0,
+            message: "there are no keyframes".to_owned(),
+        })
+    }
+
+    /// Read the keyframes in a file
+    ///
+    /// # Errors
+    /// If the file can't be read or isn't a valid keyframes file
+    pub fn load(path: &Path) -> Result<Self, ParseSceneError> {
+        let text = fs::read_to_string(path).map_err(|e| ParseSceneError {
+            line: 0,
+            message: e.to_string(),
+        })?;
+        Self::from_text(&text)
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// The camera at the fraction `t` of the way from `a` to `b`.
+///
+/// The zoom changes at a constant rate on a logarithmic scale. The center moves so the center of the deeper
+/// camera stays in the same place on the screen, like zooming into a fixed point. Measuring the center from
+/// the deeper camera keeps it precise no matter how far apart the zooms are
+fn interpolate_camera(a: &CameraInfo, b: &CameraInfo, t: f64) -> CameraInfo {
+    let zoom = FloatExp::exp2(lerp(a.zoom.log2(), b.zoom.log2(), t));
+    let rotation = lerp(a.rotation, b.rotation, t);
+
+    let (deep, shallow) = if b.zoom <= a.zoom { (b, a) } else { (a, b) };
+    // How much of the way back to the shallow center to go, 0 at the deep camera
+    let weight = if a.zoom == b.zoom {
+        FloatExp::from_f64(if b.zoom <= a.zoom { 1.0 - t } else { t })
+    } else {
+        (zoom - deep.zoom) / (shallow.zoom - deep.zoom)
+    };
+    let weight = BigFloat::from_float_exp(weight);
+    let offset = shallow.center.clone() - deep.center.clone();
+    let offset = Vector2Big::new(offset.x * weight.clone(), offset.y * weight);
+    CameraInfo::new(deep.center.clone() + offset, zoom, rotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector2::Vector2d;
+
+    fn keyframe(time: f64, re: &str, zoom: &str) -> Keyframe {
+        Keyframe::new(
+            time,
+            Scene {
+                camera: CameraInfo::parse(re, "0", zoom.parse().unwrap(), 0.0).unwrap(),
+                ..Scene::default()
+            },
+        )
+    }
+
+    #[test]
+    fn zoom_is_logarithmic() {
+        let animation = Animation::new(vec![keyframe(0.0, "0", "1"), keyframe(2.0, "-1.5", "1e-300")]).unwrap();
+        assert_eq!(animation.frame_count(30.0), Some(61));
+        let zoom = animation.scene_at(1.0).camera.zoom;
+        assert!((zoom.log2() - FloatExp::from_f64(1e-150).log2()).abs() < 1e-6);
+        // Past the ends, the first and last keyframes are held
+        assert_eq!(animation.scene_at(-1.0).camera, animation.keyframes()[0].scene.camera);
+        assert_eq!(animation.scene_at(5.0).camera, animation.keyframes()[1].scene.camera);
+    }
+
+    #[test]
+    fn deep_target_stays_centered() {
+        let target = "-1.7687785183177054949030071052629843286201";
+        let animation = Animation::new(vec![keyframe(0.0, "0", "2"), keyframe(1.0, target, "1e-40")]).unwrap();
+        let target = Vector2Big::parse(target, "0").unwrap();
+        for time in [0.25, 0.5, 0.75] {
+            let camera = animation.scene_at(time).camera;
+            // The distance to the target shrinks with the zoom, so it stays in the same place on the screen
+            let distance = (camera.center.x.clone() - target.x.clone()).to_float_exp() / camera.zoom;
+            assert!(
+                (distance.to_f64() + target.x.to_f64() / 2.0).abs() < 1e-9,
+                "{time}: {distance}"
+            );
+        }
+        assert_eq!(animation.scene_at(0.0).camera.center.to_f64(), Vector2d::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let mut animation = Animation::new(vec![keyframe(0.0, "0", "2"), keyframe(4.5, "-0.75", "0.01")]).unwrap();
+        animation.keyframes[0].easing = Easing::EaseOut;
+        animation.keyframes[0].blend_julia = false;
+        let loaded = Animation::from_text(&animation.to_text()).unwrap();
+        assert_eq!(loaded.to_text(), animation.to_text());
+        assert_eq!(loaded.keyframes()[0].easing, Easing::EaseOut);
+
+        let error = Animation::from_text("[[keyframe]]\ntime = 1\n\n[[keyframe]]\ntime = 1\n").unwrap_err();
+        assert_eq!(error.line, 5);
+        assert!(Animation::from_text("[[keyframe]]\n[fractal]\nmax_iter = 9\n").is_err());
+        assert!(Animation::from_text("").is_err());
+    }
+
+    /// Times and frame rates that aren't finite are errors instead of overflowing the frame count
+    #[test]
+    fn non_finite_times_are_rejected() {
+        for time in ["nan", "inf", "-inf"] {
+            let text = format!("[[keyframe]]\ntime = 0\n\n[[keyframe]]\ntime = {time}\n");
+            let error = Animation::from_text(&text).unwrap_err();
+            assert_eq!(
+                (error.line, error.message.as_str()),
+                (5, "time: expected a finite number")
+            );
+        }
+        assert!(Animation::new(vec![keyframe(0.0, "0", "1"), keyframe(f64::INFINITY, "0", "1")]).is_none());
+
+        let animation = Animation::new(vec![keyframe(0.0, "0", "1"), keyframe(1e300, "0", "1")]).unwrap();
+        for fps in [f64::INFINITY, f64::NAN, 0.0, -30.0, 1e10] {
+            assert_eq!(animation.frame_count(fps), None, "{fps}");
+        }
+        assert_eq!(
+            Animation::new(vec![keyframe(0.0, "0", "1")]).unwrap().frame_count(30.0),
+            Some(1)
+        );
+    }
+}
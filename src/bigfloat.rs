@@ -0,0 +1,335 @@
+//! Arbitrary precision numbers for coordinates deeper than [`f64`] can represent.
+
+use std::fmt::Display;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+use dashu_float::round::mode::HalfEven;
+use dashu_float::{DBig, FBig};
+
+use crate::floatexp::FloatExp;
+use crate::vector2::{Vector2, Vector2Big, Vector2d};
+
+/// An arbitrary precision binary floating point number.
+///
+/// The result of an operation has the larger precision of its two operands, so the precision only has to be
+/// set once with [`BigFloat::with_precision`] and is then carried along by the arithmetic.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct BigFloat(FBig<HalfEven, 2>);
+
+/// The error returned when parsing a string that isn't a decimal number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseBigFloatError;
+
+impl BigFloat {
+    /// The smallest precision a [`BigFloat`] is created with, in bits
+    pub const MIN_PRECISION: usize = 64;
+
+    /// Convert exactly from an [`f64`], [`None`] for NaN and the infinities
+    #[must_use]
+    pub fn from_f64(value: f64) -> Option<Self> {
+        // dashu has infinities of its own, but they can't take part in arithmetic
+        if !value.is_finite() {
+            return None;
+        }
+        let value = FBig::try_from(value).ok()?;
+        Some(Self(value).with_precision(Self::MIN_PRECISION))
+    }
+
+    /// The closest [`f64`] to this number
+    #[must_use]
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().value()
+    }
+
+    /// Convert exactly from a [`FloatExp`], which can be smaller than any [`f64`]
+    #[must_use]
+    pub fn from_float_exp(value: FloatExp) -> Self {
+        // The mantissa as a 53 bit integer
+        let significand = (value.mantissa() * 2.0_f64.powi(53)) as i64;
+        let value = FBig::from_parts(significand.into(), value.exponent() as isize - 53);
+        Self(value).with_precision(Self::MIN_PRECISION)
+    }
+
+    /// The closest [`FloatExp`] to this number
+    #[must_use]
+    pub fn to_float_exp(&self) -> FloatExp {
+        let repr = self.0.repr();
+        // The precision of a new number is the bit length of its significand,
+        // so this scales the significand into [0.5, 1) without converting the huge integer to an f64
+        let bits = FBig::<HalfEven, 2>::from_parts(repr.significand().clone(), 0).precision();
+        let mantissa = FBig::<HalfEven, 2>::from_parts(repr.significand().clone(), -(bits as isize));
+        FloatExp::new(mantissa.to_f64().value(), (repr.exponent() + bits as isize) as i32)
+    }
+
+    /// The number of bits in the mantissa
+    #[must_use]
+    pub fn precision(&self) -> usize {
+        self.0.precision()
+    }
+
+    /// Change the number of bits in the mantissa, rounding if it shrinks
+    #[must_use]
+    pub fn with_precision(self, precision: usize) -> Self {
+        Self(self.0.with_precision(precision).value())
+    }
+
+    /// The precision needed to place points `scale` apart with [`f32`] accuracy,
+    /// for coordinates of a magnitude that fits the fractals
+    #[must_use]
+    pub fn precision_for(scale: FloatExp) -> usize {
+        let log2 = scale.abs().log2();
+        if !log2.is_finite() {
+            // A zero or infinite scale doesn't need any particular precision
+            return Self::MIN_PRECISION;
+        }
+        let bits = (-log2).max(0.0) as usize + Self::MIN_PRECISION;
+        // Round up to whole words so small zoom changes don't change the precision every frame
+        bits.next_multiple_of(64)
+    }
+
+    /// Parse a decimal number like [`BigFloat::from_str`], with at least `precision` bits.
+    ///
+    /// Most decimals can't be stored exactly in binary, so a number that is going to need more bits than its
+    /// digits should be parsed with them from the start, instead of rounding it first and extending it after.
+    ///
+    /// # Errors
+    /// If the string isn't a decimal number
+    pub fn parse_with_precision(s: &str, precision: usize) -> Result<Self, ParseBigFloatError> {
+        let decimal = DBig::from_str(s.trim()).map_err(|_| ParseBigFloatError)?;
+        // Only the significant digits count, leading zeros like in 0.001 don't need bits.
+        // Rounded down, so printing the number again gives the same number of digits
+        let bits = (decimal.repr().digits() as f64 / std::f64::consts::LOG10_2).floor() as usize;
+        let binary = decimal
+            .with_rounding::<HalfEven>()
+            .with_base_and_precision::<2>(bits.max(precision))
+            .value();
+        Ok(Self(binary))
+    }
+
+    #[must_use]
+    pub fn abs(self) -> Self {
+        if self.0 < FBig::<HalfEven, 2>::ZERO {
+            -self
+        } else {
+            self
+        }
+    }
+}
+
+impl Default for BigFloat {
+    fn default() -> Self {
+        Self(FBig::ZERO).with_precision(Self::MIN_PRECISION)
+    }
+}
+
+impl FromStr for BigFloat {
+    type Err = ParseBigFloatError;
+
+    /// Parse a decimal number like `-1.25` or `3.5e-40`. The precision is chosen to fit every digit.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_precision(s, Self::MIN_PRECISION)
+    }
+}
+
+impl Display for BigFloat {
+    /// Print the number in decimal with as many digits as its precision holds
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = (self.precision() as f64 * std::f64::consts::LOG10_2).ceil() as usize;
+        let decimal = self.0.clone().with_base_and_precision::<10>(digits).value();
+        write!(f, "{decimal}")
+    }
+}
+
+impl Display for ParseBigFloatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid decimal number")
+    }
+}
+
+impl std::error::Error for ParseBigFloatError {}
+
+impl Add for BigFloat {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for BigFloat {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for BigFloat {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for BigFloat {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Mul for BigFloat {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self(self.0 * rhs.0)
+    }
+}
+
+impl Mul for &BigFloat {
+    type Output = BigFloat;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        BigFloat(&self.0 * &rhs.0)
+    }
+}
+
+impl Neg for BigFloat {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self(-self.0)
+    }
+}
+
+impl Vector2Big {
+    /// Convert exactly from a [`Vector2d`], [`None`] if a component isn't finite
+    #[must_use]
+    pub fn from_f64(value: Vector2d) -> Option<Self> {
+        Some(Self::new(BigFloat::from_f64(value.x)?, BigFloat::from_f64(value.y)?))
+    }
+
+    /// The closest [`FloatExp`] to each component
+    #[must_use]
+    pub fn to_float_exp(&self) -> Vector2<FloatExp> {
+        Vector2::new(self.x.to_float_exp(), self.y.to_float_exp())
+    }
+
+    /// The closest [`Vector2d`] to this vector
+    #[must_use]
+    pub fn to_f64(&self) -> Vector2d {
+        Vector2d::new(self.x.to_f64(), self.y.to_f64())
+    }
+
+    /// The larger precision of the two components
+    #[must_use]
+    pub fn precision(&self) -> usize {
+        self.x.precision().max(self.y.precision())
+    }
+
+    #[must_use]
+    pub fn with_precision(self, precision: usize) -> Self {
+        Self::new(self.x.with_precision(precision), self.y.with_precision(precision))
+    }
+
+    /// Parse both components from decimal strings, see [`BigFloat::from_str`]
+    ///
+    /// # Errors
+    /// If either string isn't a decimal number
+    pub fn parse(x: &str, y: &str) -> Result<Self, ParseBigFloatError> {
+        Ok(Self::new(x.parse()?, y.parse()?))
+    }
+
+    /// Parse both components with at least `precision` bits, see [`BigFloat::parse_with_precision`]
+    ///
+    /// # Errors
+    /// If either string isn't a decimal number
+    pub fn parse_with_precision(x: &str, y: &str, precision: usize) -> Result<Self, ParseBigFloatError> {
+        Ok(Self::new(
+            BigFloat::parse_with_precision(x, precision)?,
+            BigFloat::parse_with_precision(y, precision)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zero or infinite scale gets the smallest precision instead of overflowing
+    #[test]
+    fn precision_for_degenerate_scales() {
+        assert_eq!(BigFloat::precision_for(FloatExp::ZERO), BigFloat::MIN_PRECISION);
+        assert_eq!(
+            BigFloat::precision_for(FloatExp::from_f64(f64::INFINITY)),
+            BigFloat::MIN_PRECISION
+        );
+        assert_eq!(
+            BigFloat::precision_for(FloatExp::from_f64(f64::NAN)),
+            BigFloat::MIN_PRECISION
+        );
+        assert_eq!(
+            BigFloat::precision_for(FloatExp::from_f64(-1e-30)),
+            BigFloat::precision_for(FloatExp::from_f64(1e-30))
+        );
+    }
+
+    /// Deeper zooms never get less precision, and always enough to tell points `scale` apart
+    #[test]
+    fn precision_for_is_monotonic() {
+        let mut last = 0;
+        for exponent in (-20_000..=100).rev().step_by(7) {
+            let scale = FloatExp::new(0.75, exponent);
+            let precision = BigFloat::precision_for(scale);
+            assert!(precision >= last, "{scale}");
+            assert!(precision >= BigFloat::MIN_PRECISION && precision.is_multiple_of(64));
+            assert!(precision as f64 >= -scale.log2() + 24.0, "{scale}");
+            last = precision;
+        }
+    }
+
+    /// A deep coordinate printed and parsed again is the same number, with all its digits
+    #[test]
+    fn parse_display_round_trip() {
+        let digits = "-1.749957683706093503602214506070699707222801347";
+        let precision = BigFloat::precision_for(FloatExp::from_f64(1e-40));
+        let value = BigFloat::parse_with_precision(digits, precision).unwrap();
+        assert_eq!(value.precision(), precision);
+        let printed = value.to_string();
+        assert!(printed.starts_with(&digits[..digits.len() - 1]), "{printed}");
+        assert_eq!(BigFloat::parse_with_precision(&printed, precision).unwrap(), value);
+
+        // Without a precision every digit still fits
+        let value: BigFloat = digits.parse().unwrap();
+        assert!(value.to_string().starts_with(&digits[..digits.len() - 1]));
+        assert!("1.2.3".parse::<BigFloat>().is_err());
+    }
+
+    /// Converting to and from [`FloatExp`] keeps numbers far outside the range of [`f64`] exactly
+    #[test]
+    fn float_exp_round_trip() {
+        for value in [
+            FloatExp::new(0.75, -100_000),
+            FloatExp::new(-0.987_654_321_012_345_6, -1_000_000),
+            FloatExp::new(0.5, 50_000),
+            FloatExp::from_f64(-3.25),
+            FloatExp::ZERO,
+        ] {
+            let big = BigFloat::from_float_exp(value);
+            assert_eq!(big.to_float_exp(), value);
+        }
+
+        // Adding something far too small for a FloatExp still rounds to the larger number
+        let sum = BigFloat::from_f64(1.5).unwrap() + BigFloat::from_float_exp(FloatExp::new(0.5, -5000));
+        assert_eq!(sum.to_float_exp(), FloatExp::from_f64(1.5));
+    }
+
+    /// NaN and the infinities aren't silently turned into coordinates
+    #[test]
+    fn non_finite_values_are_rejected() {
+        assert_eq!(BigFloat::from_f64(-2.5).unwrap().to_f64(), -2.5);
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(BigFloat::from_f64(value).is_none());
+        }
+        assert!(Vector2Big::from_f64(Vector2d::new(0.5, f64::NAN)).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+#![warn(clippy::pedantic)]
+
+//! Render a fractal to a PNG without opening a window, for scripts and headless servers.
+
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use rust_mandel::{
+    animation::Animation, data, expmap::ExpMap, floatexp::FloatExp, scene::Scene, shader, vector2::Vector2d,
+    Application, CameraInfo, ColorScheme, FractalType, ShadingType, TrapColoring, TrapShape,
+};
+
+const USAGE: &str = "\
+Usage: mandel-render [OPTIONS] -o <FILE>
+       mandel-render --animation <FILE> [--fps <N>] [--size <WxH>] [--cpu] -o <DIRECTORY>
+       mandel-render --exp-map [OPTIONS] -o <FILE>
+       mandel-render --from-exp-map <FILE> [--frames <N>] [--size <WxH>] -o <DIRECTORY>
+
+Options:
+  -o, --output <FILE>        Where to write the PNG, or the directory for the frames of an animation.
+                             A .npy or .mdat file gets the raw iteration counts, z and derivatives instead
+      --animation <FILE>     Render the keyframes in FILE as frame_00000.png, frame_00001.png, ...
+      --fps <N>              Frames per second of the animation [default: 30]
+      --exp-map              Render the exponential map around the center instead, x is the angle and y the log
+                             of the distance from the zoom down. Every WIDTH rows zoom in by about 535 times
+      --from-exp-map <FILE>  Rebuild the frames of a zoom video from a map rendered with --exp-map
+      --frames <N>           Number of frames to rebuild from the map [default: 300]
+      --scene <FILE>         Start from a saved scene or an exported PNG, the other options override it
+      --save-scene <FILE>    Save the scene given by the options, -o can be left out then
+      --center <RE> <IM>     The point in the middle of the image, with any number of digits [default: 0 0]
+      --zoom <ZOOM>          Half the height (or width) of the image in the complex plane [default: 2.1]
+      --rotation <DEGREES>   Counterclockwise rotation of the view [default: 0]
+      --size <WxH>           The size of the image in pixels, as many as 8192x8192 at most [default: 1920x1080]
+  -i, --iterations <N>       Maximum number of iterations [default: 1024]
+      --fractal <TYPE>       mandelbrot, burning-ship or tricorn [default: mandelbrot]
+      --exponent <N>         The power z is raised to [default: 2]
+      --julia <RE> <IM>      Render the Julia set of this point
+      --palette <NAME>       earth, sky, crimson, midnight-amber, rainbow, sunset, midday or ocean
+                             [default: midnight-amber]
+      --palette-speed <N>    How fast the colors cycle with the iterations [default: 0.05]
+      --shading <TYPE>       normal, smooth, distance, lighting, orbit-trap, stripe, tia, curvature or
+                             histogram [default: smooth]
+      --boundary-width <PX>  How far from the set the distance shading darkens the colors [default: 1]
+      --light <DEGREES> <HEIGHT>
+                             Where the light of the lighting shading comes from [default: 45 1]
+      --specular <N>         The strength of the highlights of the lighting shading [default: 0.4]
+      --light-blend <N>      How much of the palette color the lighting shading keeps [default: 1]
+      --trap <SHAPE> <RE> <IM>
+                             The orbit trap, a point, line, cross, circle or stalks [default: cross 0 0]
+      --trap-angle <DEGREES> The direction of the lines of the orbit trap [default: 0]
+      --trap-size <N>        The radius of the circle trap, the width of the stalks and the distance the
+                             palette cycles over [default: 0.25]
+      --trap-coloring <BY>   distance or iteration of the closest approach to the trap [default: distance]
+      --stripe-frequency <N> The number of stripes per turn of the stripe average [default: 5]
+      --average-strength <N> How far the stripe, tia and curvature averages move through the palette
+                             [default: 1]
+      --no-perturbation      Don't render deep zooms relative to a reference orbit
+      --cpu                  Render on the CPU even if there is a GPU
+  -h, --help                 Print this help";
+
+/// Everything the command line can set
+struct Args {
+    output: Option<PathBuf>,
+    animation: Option<PathBuf>,
+    fps: f64,
+    exp_map: bool,
+    from_exp_map: Option<PathBuf>,
+    frames: u32,
+    save_scene: Option<PathBuf>,
+    width: u32,
+    height: u32,
+    cpu: bool,
+    scene: Scene,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("error: {error}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(path) = &args.save_scene {
+        if let Err(error) = args.scene.save(path) {
+            eprintln!("error: couldn't write {}: {error}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+    let Some(output) = &args.output else {
+        return ExitCode::SUCCESS;
+    };
+
+    let gpu = if args.cpu { None } else { shader::headless_device() };
+    if !args.cpu && gpu.is_none() {
+        eprintln!("No GPU found, rendering on the CPU");
+    }
+    let gpu = gpu.as_ref().map(|(device, queue)| (device, queue));
+
+    if let Some(path) = &args.animation {
+        let animation = match Animation::load(path) {
+            Ok(animation) => animation,
+            Err(error) => {
+                eprintln!("error: couldn't load {}: {error}", path.display());
+                return ExitCode::FAILURE;
+            }
+        };
+        let result = animation.render_frames(output, args.fps, args.width, args.height, gpu, |done, total| {
+            eprint!("\rRendered frame {done}/{total}");
+        });
+        eprintln!();
+        if let Err(error) = result {
+            eprintln!("error: couldn't write the frames to {}: {error}", output.display());
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    if let Some(path) = &args.from_exp_map {
+        let map = match ExpMap::load(path) {
+            Ok(map) => map,
+            Err(error) => {
+                eprintln!("error: couldn't load {}: {error}", path.display());
+                return ExitCode::FAILURE;
+            }
+        };
+        let result = map.render_frames(output, args.frames, args.width, args.height, |done, total| {
+            eprint!("\rRebuilt frame {done}/{total}");
+        });
+        eprintln!();
+        if let Err(error) = result {
+            eprintln!("error: couldn't write the frames to {}: {error}", output.display());
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    let scene = &args.scene;
+    if !scene
+        .shading_type
+        .is_supported(scene.fractal_type, scene.exponent, scene.julia)
+    {
+        let name = ShadingType::NAMES
+            .iter()
+            .find(|(_, shading_type)| *shading_type == scene.shading_type)
+            .map_or("", |(name, _)| name);
+        eprintln!(
+            "warning: the {name} shading needs an exponent above 1, and the Mandelbrot set or a Julia set, \
+             rendering with smooth shading instead"
+        );
+    }
+    let mut application = Application::default();
+    application.set_scene(args.scene);
+    application.exponential_map = args.exp_map;
+    let result = if data::is_data_path(output) {
+        application
+            .export_data(output, args.width, args.height)
+            .map_err(|e| e.to_string())
+    } else {
+        application
+            .export_image(output, args.width, args.height, gpu)
+            .map_err(|e| e.to_string())
+    };
+    if let Err(error) = result {
+        eprintln!("error: couldn't write {}: {error}", output.display());
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Parse the arguments after the program name, returns [`None`] if the help was asked for
+fn parse_args(args: Vec<String>) -> Result<Option<Args>, String> {
+    // The scene is the starting point no matter where it is given, so every other option overrides it
+    let mut scene = match args.iter().position(|arg| arg == "--scene") {
+        Some(i) => {
+            let path = args.get(i + 1).ok_or("--scene needs a value")?;
+            Scene::load(Path::new(path)).map_err(|e| format!("couldn't load {path}: {e}"))?
+        }
+        None => Scene {
+            perturbation: true,
+            ..Scene::default()
+        },
+    };
+    let mut center = None;
+    let mut zoom = None;
+    let mut rotation = None;
+    let mut output = None;
+    let mut animation = None;
+    let mut fps: f64 = 30.0;
+    let mut exp_map = false;
+    let mut from_exp_map = None;
+    let mut frames = 300;
+    let mut save_scene = None;
+    let (mut width, mut height) = (1920, 1080);
+    let mut cpu = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--scene" => {
+                value()?;
+            }
+            "--animation" => animation = Some(PathBuf::from(value()?)),
+            "--fps" => {
+                fps = parse(&arg, &value()?)?;
+                if !fps.is_finite() || fps <= 0.0 {
+                    return Err("--fps must be a positive number".to_owned());
+                }
+            }
+            "--exp-map" => exp_map = true,
+            "--from-exp-map" => from_exp_map = Some(PathBuf::from(value()?)),
+            "--frames" => frames = parse::<NonZeroU32>(&arg, &value()?)?.get(),
+            "--save-scene" => save_scene = Some(PathBuf::from(value()?)),
+            "--center" => center = Some((value()?, value()?)),
+            "--zoom" => {
+                let value: FloatExp = parse(&arg, &value()?)?;
+                if value <= FloatExp::ZERO {
+                    return Err("--zoom must be positive".to_owned());
+                }
+                zoom = Some(value);
+            }
+            "--rotation" => rotation = Some(parse::<f64>(&arg, &value()?)?.to_radians()),
+            "--size" => (width, height) = parse_size(&value()?)?,
+            "--cpu" => cpu = true,
+            other => {
+                if !parse_scene_arg(&mut scene, other, &mut value)? {
+                    return Err(format!("unexpected argument {other}"));
+                }
+            }
+        }
+    }
+
+    let zoom = zoom.unwrap_or(scene.camera.zoom);
+    let rotation = rotation.unwrap_or(scene.camera.rotation);
+    scene.camera = match center {
+        Some((re, im)) => CameraInfo::parse(&re, &im, zoom, rotation).map_err(|e| format!("--center: {e}"))?,
+        None => CameraInfo::new(scene.camera.center, zoom, rotation),
+    };
+    // The options are checked like the values of a scene file, so nothing that couldn't be loaded gets rendered
+    scene.validate().map_err(|e| format!("invalid scene, {e}"))?;
+    if output.is_none() && save_scene.is_none() {
+        return Err("no output file given, use -o <FILE>".to_owned());
+    }
+    if (animation.is_some() || from_exp_map.is_some()) && output.is_none() {
+        return Err("no output directory given for the frames, use -o <DIRECTORY>".to_owned());
+    }
+    Ok(Some(Args {
+        output,
+        animation,
+        fps,
+        exp_map,
+        from_exp_map,
+        frames,
+        save_scene,
+        width,
+        height,
+        cpu,
+        scene,
+    }))
+}
+
+/// Parse an option that sets a part of the scene other than the camera, returns whether `arg` is one of them
+fn parse_scene_arg(
+    scene: &mut Scene,
+    arg: &str,
+    value: &mut impl FnMut() -> Result<String, String>,
+) -> Result<bool, String> {
+    match arg {
+        "-i" | "--iterations" => scene.max_iter = parse(arg, &value()?)?,
+        "--fractal" => scene.fractal_type = named(&FractalType::NAMES, arg, &value()?)?,
+        "--exponent" => scene.exponent = parse(arg, &value()?)?,
+        "--julia" => {
+            scene.julia = true;
+            scene.julia_pos = Vector2d::new(parse(arg, &value()?)?, parse(arg, &value()?)?);
+        }
+        "--palette" => {
+            let names = ColorScheme::PRESETS.map(|(name, scheme)| (name.to_lowercase().replace(' ', "-"), scheme));
+            scene.color_scheme = named(&names, arg, &value()?)?;
+        }
+        "--palette-speed" => scene.palette_speed = parse(arg, &value()?)?,
+        "--shading" => scene.shading_type = named(&ShadingType::NAMES, arg, &value()?)?,
+        "--boundary-width" => scene.boundary_width = parse(arg, &value()?)?,
+        "--light" => {
+            scene.light.angle = parse::<f32>(arg, &value()?)?.to_radians();
+            scene.light.height = parse(arg, &value()?)?;
+        }
+        "--specular" => scene.light.specular = parse(arg, &value()?)?,
+        "--light-blend" => scene.light.blend = parse(arg, &value()?)?,
+        "--trap" => {
+            scene.trap.shape = named(&TrapShape::NAMES, arg, &value()?)?;
+            scene.trap.center = Vector2d::new(parse(arg, &value()?)?, parse(arg, &value()?)?);
+        }
+        "--trap-angle" => scene.trap.angle = parse::<f32>(arg, &value()?)?.to_radians(),
+        "--trap-size" => scene.trap.size = parse(arg, &value()?)?,
+        "--stripe-frequency" => scene.averages.stripe_frequency = parse(arg, &value()?)?,
+        "--average-strength" => {
+            let strength = parse(arg, &value()?)?;
+            scene.averages.stripe_strength = strength;
+            scene.averages.tia_strength = strength;
+            scene.averages.curvature_strength = strength;
+        }
+        "--trap-coloring" => scene.trap.coloring = named(&TrapColoring::NAMES, arg, &value()?)?,
+        "--no-perturbation" => scene.perturbation = false,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Parse the value of `--size`
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let (w, h) = size.split_once('x').ok_or("--size must look like 1920x1080")?;
+    let (width, height) = (parse("--size", w)?, parse("--size", h)?);
+    if width == 0 || height == 0 {
+        return Err("--size can't be zero".to_owned());
+    }
+    Ok((width, height))
+}
+
+/// Parse the value of the argument `arg`
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value for {arg}: {value}"))
+}
+
+/// Look up the value of the argument `arg` in a list of names like [`FractalType::NAMES`]
+fn named<T: Copy>(names: &[(impl AsRef<str>, T)], arg: &str, value: &str) -> Result<T, String> {
+    rust_mandel::scene::find_name(names, value).ok_or(format!("invalid value for {arg}: {value}"))
+}
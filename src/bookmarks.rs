@@ -0,0 +1,147 @@
+//! Named snapshots of the scene with small thumbnails, kept across runs in eframe's storage.
+//!
+//! A bookmarks file is a list of scene files, each one starting with a `[[bookmark]]` line and its `name`:
+//!
+//! ```text
+//! [[bookmark]]
+//! name = "Seahorse valley"
+//! version = 1
+//!
+//! [camera]
+//! re = "-0.75"
+//! ...
+//! ```
+
+use std::{fs, path::Path, thread::JoinHandle};
+
+use eframe::{egui, wgpu};
+
+use crate::scene::{self, ParseSceneError, Scene};
+use crate::Application;
+
+/// The key the bookmarks are kept under in eframe's storage
+pub const STORAGE_KEY: &str = "bookmarks";
+
+/// The size of the thumbnails in pixels
+pub const THUMBNAIL_WIDTH: u32 = 160;
+pub const THUMBNAIL_HEIGHT: u32 = 90;
+
+/// The line every bookmark in a bookmarks file starts with
+const SEPARATOR: &str = "[[bookmark]]";
+
+/// A named scene
+pub struct Bookmark {
+    pub name: String,
+    pub scene: Scene,
+    /// Rendered the first time the bookmark is shown
+    pub(crate) thumbnail: Option<egui::TextureHandle>,
+    /// The thread rendering the thumbnail with [`Bookmark::render_thumbnail`]
+    pub(crate) thumbnail_job: Option<JoinHandle<Vec<u8>>>,
+}
+
+impl Bookmark {
+    #[must_use]
+    pub fn new(name: String, scene: Scene) -> Self {
+        Self {
+            name,
+            scene,
+            thumbnail: None,
+            thumbnail_job: None,
+        }
+    }
+
+    /// Render the thumbnail of the bookmark, as tightly packed RGBA8 rows
+    #[must_use]
+    pub fn render_thumbnail(&self, gpu: Option<(&wgpu::Device, &wgpu::Queue)>) -> Vec<u8> {
+        let mut application = Application::default();
+        application.set_scene(self.scene.clone());
+        application.render_image(THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, gpu)
+    }
+}
+
+/// Write the bookmarks in the bookmarks file format
+#[must_use]
+pub fn to_text(bookmarks: &[Bookmark]) -> String {
+    bookmarks
+        .iter()
+        .map(|bookmark| {
+            format!(
+                "{SEPARATOR}\nname = {}\n{}",
+                scene::quote(&bookmark.name),
+                bookmark.scene.to_toml()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Read a bookmarks file, see the [module docs](self) for the format
+///
+/// # Errors
+/// If there is text before the first bookmark or one of the scenes isn't valid
+pub fn from_text(text: &str) -> Result<Vec<Bookmark>, ParseSceneError> {
+    scene::parse_list(text, SEPARATOR)?
+        .iter()
+        .enumerate()
+        .map(|(i, table)| {
+            let name = table
+                .get_string("name")?
+                .unwrap_or_else(|| format!("Bookmark {}", i + 1));
+            Ok(Bookmark::new(name, Scene::from_table(table)?))
+        })
+        .collect()
+}
+
+/// Write the bookmarks to a file
+///
+/// # Errors
+/// If the file can't be written
+pub fn save(bookmarks: &[Bookmark], path: &Path) -> std::io::Result<()> {
+    fs::write(path, to_text(bookmarks))
+}
+
+/// Read the bookmarks in a file
+///
+/// # Errors
+/// If the file can't be read or isn't a valid bookmarks file
+pub fn load(path: &Path) -> Result<Vec<Bookmark>, ParseSceneError> {
+    let text = fs::read_to_string(path).map_err(|e| ParseSceneError {
+        line: 0,
+        message: e.to_string(),
+    })?;
+    from_text(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FractalType;
+
+    #[test]
+    fn round_trip() {
+        let bookmarks = vec![
+            Bookmark::new("Home".to_owned(), Scene::default()),
+            Bookmark::new(
+                "The \"burning\" ship".to_owned(),
+                Scene {
+                    fractal_type: FractalType::BurningShip,
+                    max_iter: 300,
+                    ..Scene::default()
+                },
+            ),
+        ];
+        let loaded = from_text(&to_text(&bookmarks)).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].name, "The \"burning\" ship");
+        assert_eq!(loaded[1].scene.fractal_type, FractalType::BurningShip);
+        assert_eq!(to_text(&loaded), to_text(&bookmarks));
+        assert!(from_text("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let text = "[[bookmark]]\nname = \"a\"\n\n[[bookmark]]\n[fractal]\nmax_iter = lots\n";
+        assert_eq!(from_text(text).err().unwrap().line, 6);
+        assert_eq!(from_text("# bookmarks\nname = \"a\"\n").err().unwrap().line, 2);
+    }
+}
@@ -0,0 +1,1473 @@
+//! A CPU port of `shader.wgsl`.
+//!
+//! The functions here mirror the WGSL functions of the same name and work in [`f32`] so the output matches
+//! what the GPU draws. This makes it possible to render without a wgpu surface, e.g. for tests and batch jobs.
+
+use std::cell::Cell;
+use std::f32::consts::TAU;
+use std::thread;
+
+use crate::shader::{ReferenceCallback, RenderCallback, HISTOGRAM_BINS};
+use crate::vector2::Vector2f;
+use crate::ShadingType;
+
+/// Render a whole frame on the CPU and return it as tightly packed RGBA8 rows.
+///
+/// The `resolution` and `offset` of `uniforms` are replaced by `width`, `height` and zero, every other field is
+/// interpreted exactly like the fragment shader does. `reference_orbit` takes the place of the shader's storage
+/// buffer and is only read when perturbation is enabled in the flags.
+#[must_use]
+pub fn render(uniforms: &RenderCallback, reference_orbit: &[[f32; 2]], width: u32, height: u32) -> Vec<u8> {
+    if uniforms.shading_type == ShadingType::Histogram as u32 {
+        let mut values = vec![0.0; width as usize * height as usize * 2];
+        for_each_pixel(
+            uniforms,
+            reference_orbit,
+            width,
+            height,
+            &mut values,
+            |bindings, frag_coord, value| {
+                value.copy_from_slice(&fs_values(bindings, frag_coord));
+            },
+        );
+        return equalize(uniforms, &values);
+    }
+    let mut pixels = vec![0; width as usize * height as usize * 4];
+    for_each_pixel(
+        uniforms,
+        reference_orbit,
+        width,
+        height,
+        &mut pixels,
+        |bindings, frag_coord, pixel| {
+            pixel.copy_from_slice(&fs_main(bindings, frag_coord).map(to_unorm8));
+        },
+    );
+    pixels
+}
+
+/// Color the values of the histogram coloring through the histogram of all of them and return the colors as
+/// tightly packed RGBA8 rows.
+///
+/// `values` has the two values `fs_values` returns for every pixel, the escaped value and the glitch state.
+/// This does what the shader's compute passes and `fs_histogram` do for a frame on the screen.
+#[must_use]
+pub fn equalize(uniforms: &RenderCallback, values: &[f32]) -> Vec<u8> {
+    let cdf = cumulative_distribution(values.iter().step_by(2).copied());
+    values
+        .chunks_exact(2)
+        .flat_map(|value| {
+            let [r, g, b] = histogram_color(uniforms, &cdf, [value[0], value[1]]);
+            [r, g, b, 1.0].map(to_unorm8)
+        })
+        .collect()
+}
+
+/// The values [`render_data`] writes for every pixel, in order
+pub const DATA_CHANNELS: [&str; 6] = [
+    "iterations",
+    "z_re",
+    "z_im",
+    "derivative_re",
+    "derivative_im",
+    "escaped",
+];
+
+/// Render the raw values behind a frame instead of its colors, [`DATA_CHANNELS`] values per pixel in rows.
+///
+/// The iterations are the smooth iteration count whatever the shading type is, or -1 if the pixel doesn't
+/// escape. z is taken when the pixel escapes or after the last iteration, the derivative is dz/dc (dz/dz for
+/// Julia sets) times the zoom, so it's along the normalized coordinates, and escaped is 1 or 0.
+/// The frame is rendered like [`render`] otherwise.
+#[must_use]
+pub fn render_data(uniforms: &RenderCallback, reference_orbit: &[[f32; 2]], width: u32, height: u32) -> Vec<f32> {
+    let uniforms = RenderCallback {
+        shading_type: ShadingType::Smooth as u32,
+        flags: uniforms.flags | 64,
+        ..*uniforms
+    };
+    let mut values = vec![0.0; width as usize * height as usize * DATA_CHANNELS.len()];
+    for_each_pixel(
+        &uniforms,
+        reference_orbit,
+        width,
+        height,
+        &mut values,
+        |bindings, frag_coord, values| {
+            let res = pixel_value(bindings, frag_coord);
+            let z = bindings.private.final_z.get();
+            let derivative = bindings.private.derivative.get();
+            let escaped = if res >= 0.0 { 1.0 } else { 0.0 };
+            values.copy_from_slice(&[res, z.x, z.y, derivative.x, derivative.y, escaped]);
+        },
+    );
+    values
+}
+
+/// Find the pixels that are still glitched after trying every reference, the frame is rendered like [`render`].
+///
+/// `mask` has one value per pixel in rows. Only the pixels that are set are rendered, and they are
+/// cleared if they aren't glitched. A pixel can't become glitched by adding references, so after adding one only
+/// the previously glitched pixels have to be checked again.
+pub fn update_glitch_mask(
+    uniforms: &RenderCallback,
+    reference_orbit: &[[f32; 2]],
+    width: u32,
+    height: u32,
+    mask: &mut [bool],
+) {
+    for_each_pixel(
+        uniforms,
+        reference_orbit,
+        width,
+        height,
+        mask,
+        |bindings, frag_coord, glitched| {
+            if glitched[0] {
+                fs_main(bindings, frag_coord);
+                glitched[0] = bindings.private.glitched.get();
+            }
+        },
+    );
+}
+
+/// Run `shade` for every pixel of a `width` by `height` frame on all threads.
+///
+/// `pixels` holds the same number of values for every pixel in rows, the values of the current pixel are passed
+/// to `shade`.
+fn for_each_pixel<T: Send>(
+    uniforms: &RenderCallback,
+    reference_orbit: &[[f32; 2]],
+    width: u32,
+    height: u32,
+    pixels: &mut [T],
+    shade: impl Fn(&Bindings, Vector2f, &mut [T]) + Sync,
+) {
+    if pixels.is_empty() {
+        return;
+    }
+    let channels = pixels.len() / (width as usize * height as usize);
+    let uniforms = RenderCallback {
+        resolution: [width as f32, height as f32],
+        offset: [0.0, 0.0],
+        ..*uniforms
+    };
+    let row_len = width as usize * channels;
+
+    // Split the image into bands of rows, one per thread
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let rows_per_band = (height as usize).div_ceil(threads).max(1);
+
+    thread::scope(|s| {
+        for (band, chunk) in pixels.chunks_mut(rows_per_band * row_len).enumerate() {
+            let uniforms = &uniforms;
+            let shade = &shade;
+            s.spawn(move || {
+                for (i, row) in chunk.chunks_exact_mut(row_len).enumerate() {
+                    let y = (band * rows_per_band + i) as f32;
+                    for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+                        let private = Private::default();
+                        let bindings = Bindings {
+                            uniforms,
+                            reference_orbit,
+                            private: &private,
+                        };
+                        // Fragment coordinates are sampled at the center of the pixel
+                        shade(&bindings, Vector2f::new(x as f32 + 0.5, y + 0.5), pixel);
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// The resources the shader reads besides its arguments
+#[derive(Clone, Copy)]
+struct Bindings<'a> {
+    uniforms: &'a RenderCallback,
+    reference_orbit: &'a [[f32; 2]],
+    private: &'a Private,
+}
+
+/// The shader's `var<private>` globals, which every pixel has its own copy of
+#[derive(Default)]
+struct Private {
+    /// The index in `references` of the reference the pixel is iterated against
+    reference_index: Cell<usize>,
+    /// Whether the pixel is glitched with that reference
+    glitched: Cell<bool>,
+    /// The derivative of z along the real axis of the normalized coordinates, only tracked if bit 6 of the flags
+    /// is set
+    derivative: Cell<Vector2f>,
+    /// z when the pixel escaped, or after the last iteration
+    final_z: Cell<Vector2f>,
+    /// The distance between pixels in the normalized coordinates
+    pixel_size: Cell<f32>,
+    /// The closest the orbit came to the trap so far, [`TRAP_MISSED`] if it never did
+    trap_distance: Cell<f32>,
+    /// The iteration the orbit came closest to the trap at
+    trap_iteration: Cell<i32>,
+    /// The sum and count of the terms of the average colorings
+    average_sum: Cell<f32>,
+    average_count: Cell<i32>,
+    /// The last term of the average coloring
+    average_last: Cell<f32>,
+    /// The average interpolated across the last iteration, set when the pixel escapes
+    average: Cell<f32>,
+    /// z of the previous two iterations, for the curvature average
+    previous_z: Cell<Vector2f>,
+    previous_z2: Cell<Vector2f>,
+}
+
+/// Convert a color channel to a byte the same way a `Unorm` render target does
+fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// simple palette function from here: <https://iquilezles.org/articles/palettes/>
+///
+/// Uses the same truncated value of tau as the shader so the colors match exactly.
+#[allow(clippy::many_single_char_names, clippy::approx_constant)]
+fn pal(t: f32, a: [f32; 4], b: [f32; 4], c: [f32; 4], d: [f32; 4]) -> [f32; 3] {
+    std::array::from_fn(|i| a[i] + b[i] * (6.28318 * (c[i] * t + d[i])).cos())
+}
+
+fn mul_complex(a: Vector2f, b: Vector2f) -> Vector2f {
+    Vector2f::new(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x)
+}
+
+fn square_complex(a: Vector2f) -> Vector2f {
+    Vector2f::new(a.x * a.x - a.y * a.y, 2.0 * a.x * a.y)
+}
+
+fn powf_complex(a: Vector2f, n: f32) -> Vector2f {
+    let abs_n = n.abs();
+    let z = if abs_n == 2.0 {
+        square_complex(a)
+    } else {
+        let r = (a.x * a.x + a.y * a.y).sqrt();
+        let theta = a.y.atan2(a.x);
+        let ntheta = abs_n * theta;
+        Vector2f::new(ntheta.cos(), ntheta.sin()) * r.powf(abs_n)
+    };
+    if n > 0.0 {
+        return z;
+    }
+    conjugate_complex(z) / (z.x * z.x + z.y * z.y)
+}
+
+fn exp_complex(z: Vector2f) -> Vector2f {
+    Vector2f::new(z.y.cos(), z.y.sin()) * z.x.exp()
+}
+
+fn conjugate_complex(a: Vector2f) -> Vector2f {
+    Vector2f::new(a.x, -a.y)
+}
+
+fn abs_complex(a: Vector2f) -> Vector2f {
+    Vector2f::new(a.x.abs(), a.y.abs())
+}
+
+fn mandellike(bindings: &Bindings, pos: Vector2f, fractal_type: u32) -> f32 {
+    let uniforms = bindings.uniforms;
+    let mut z = Vector2f::new(0.0, 0.0);
+    let c;
+    if (uniforms.flags & 1) == 1 {
+        z = pos;
+        c = uniforms.julia_pos.into();
+    } else {
+        c = pos;
+    }
+
+    bindings.private.derivative.set(start_derivative(uniforms));
+    mandellike_loop(bindings, z, c, 0, fractal_type)
+}
+
+/// The derivative of the starting z, which is the pixel position for Julia sets and zero otherwise
+fn start_derivative(uniforms: &RenderCallback) -> Vector2f {
+    if (uniforms.flags & 1) == 1 {
+        return Vector2f::new(ldexp_flush(uniforms.zoom.into(), uniforms.zoom_exp).x, 0.0);
+    }
+    Vector2f::new(0.0, 0.0)
+}
+
+/// The derivative after iterating z, if bit 6 of the flags is set.
+/// For the Burning Ship and the Tricorn it's the derivative along the real axis only, they aren't holomorphic
+fn derivative_step(uniforms: &RenderCallback, z: Vector2f, d: Vector2f, fractal_type: u32) -> Vector2f {
+    if (uniforms.flags & 64) == 0 {
+        return d;
+    }
+    let next = derivative_iter(uniforms, z, d, fractal_type);
+    if (uniforms.flags & 1) == 1 {
+        return next;
+    }
+    // c moves with the pixel
+    next + Vector2f::new(ldexp_flush(uniforms.zoom.into(), uniforms.zoom_exp).x, 0.0)
+}
+
+/// The derivative of [`mandellike_iter`] at z without the constant, applied to `d`. It's linear in `d`
+fn derivative_iter(uniforms: &RenderCallback, z: Vector2f, d: Vector2f, fractal_type: u32) -> Vector2f {
+    match fractal_type {
+        0 => mul_complex(power_derivative(uniforms, z), d),
+        1 => {
+            // Folding z into the first quadrant flips the directions of the folded components
+            let folded = Vector2f::new(if z.x < 0.0 { -d.x } else { d.x }, if z.y < 0.0 { -d.y } else { d.y });
+            mul_complex(power_derivative(uniforms, abs_complex(z)), folded)
+        }
+        2 => conjugate_complex(mul_complex(power_derivative(uniforms, z), d)),
+        _ => d,
+    }
+}
+
+/// `n z^(n - 1)`
+fn power_derivative(uniforms: &RenderCallback, z: Vector2f) -> Vector2f {
+    if uniforms.exponent == 2.0 {
+        return z * 2.0;
+    }
+    powf_complex(z, uniforms.exponent - 1.0) * uniforms.exponent
+}
+
+/// Iterate z from iteration `start` until it escapes or reaches `max_iter`
+fn mandellike_loop(bindings: &Bindings, z_start: Vector2f, c: Vector2f, start: i32, fractal_type: u32) -> f32 {
+    let uniforms = bindings.uniforms;
+    let private = bindings.private;
+    let escape = escape_radius(uniforms);
+    let escape_sq = escape * escape;
+
+    let mut z = z_start;
+    for i in start..uniforms.max_iter {
+        average_step(bindings, z, c, i);
+        let zn_sq = z.x * z.x + z.y * z.y;
+        if zn_sq >= escape_sq {
+            private.final_z.set(z);
+            return escape_value(bindings, i, zn_sq);
+        }
+
+        trap_step(bindings, z, i);
+        private
+            .derivative
+            .set(derivative_step(uniforms, z, private.derivative.get(), fractal_type));
+        z = mandellike_iter(uniforms, z, c, fractal_type);
+    }
+    private.final_z.set(z);
+    -1.0
+}
+
+/// The value passed to the palette for a point that escaped at iteration `i`
+fn escape_value(bindings: &Bindings, i: i32, zn_sq: f32) -> f32 {
+    let uniforms = bindings.uniforms;
+    let escape = escape_radius(uniforms);
+    let log_escape = escape.ln();
+    let log_exponent = uniforms.exponent.ln();
+    // How far past the escape radius z went, from 0 when it's just outside to 1 when the previous z was
+    let fraction = ((zn_sq.ln() / (2.0 * log_escape)).ln() / log_exponent).clamp(0.0, 1.0);
+
+    match uniforms.shading_type {
+        0 => (i as f32 - 2.0).max(0.0),
+        1..=4 | 8 => (i as f32 - 2.0 - fraction).max(0.0),
+        5..=7 => {
+            bindings.private.average.set(average_value(bindings, fraction));
+            (i as f32 - 2.0 - fraction).max(0.0)
+        }
+        _ => 0.0,
+    }
+}
+
+/// Iterate only the difference between this pixel and the reference orbit.
+/// `pos` is the low precision position of the pixel and `delta` is its offset from the reference point.
+fn mandellike_perturbed(bindings: &Bindings, pos: Vector2f, delta: Vector2f, fractal_type: u32) -> f32 {
+    let uniforms = bindings.uniforms;
+    let mut dz = Vector2f::new(0.0, 0.0);
+    let mut dc = delta;
+    let mut c = pos;
+    if (uniforms.flags & 1) == 1 {
+        dz = delta;
+        dc = Vector2f::new(0.0, 0.0);
+        c = uniforms.julia_pos.into();
+    }
+
+    bindings.private.derivative.set(start_derivative(uniforms));
+    let mut start = 0;
+    if uniforms.series_skip > 0 && bindings.private.reference_index.get() == 0 {
+        dz = cexp_to_vec2(series_delta(uniforms, cexp_new(delta, 0)));
+        let derivative = series_derivative(uniforms, cexp_new(delta, 0));
+        bindings.private.derivative.set(cexp_to_vec2(derivative));
+        start = uniforms.series_skip as i32;
+    }
+    perturbed_loop(bindings, dz, dc, c, start, fractal_type)
+}
+
+/// Continue a perturbed orbit from iteration `start`
+fn perturbed_loop(bindings: &Bindings, dz: Vector2f, dc: Vector2f, c: Vector2f, start: i32, fractal_type: u32) -> f32 {
+    let uniforms = bindings.uniforms;
+    let escape = escape_radius(uniforms);
+    let escape_sq = escape * escape;
+    let last = last_reference_iteration(bindings);
+
+    let private = bindings.private;
+    let mut dz = dz;
+    let mut z = Vector2f::new(0.0, 0.0);
+    for i in start..uniforms.max_iter {
+        let z_ref = reference_z(bindings, i);
+        z = z_ref + dz;
+        average_step(bindings, z, c, i);
+        let zn_sq = z.x * z.x + z.y * z.y;
+        if zn_sq >= escape_sq {
+            private.final_z.set(z);
+            return escape_value(bindings, i, zn_sq);
+        }
+
+        // Pauldelbrot's criterion: once z is much closer to zero than the reference,
+        // dz is too big compared to z for the precision of f32
+        if !private.glitched.get() && zn_sq < uniforms.glitch_tolerance * (z_ref.x * z_ref.x + z_ref.y * z_ref.y) {
+            private.glitched.set(true);
+            if private.reference_index.get() + 1 < uniforms.ref_count as usize {
+                return GLITCHED;
+            }
+        }
+
+        trap_step(bindings, z, i);
+        private
+            .derivative
+            .set(derivative_step(uniforms, z, private.derivative.get(), fractal_type));
+        if i >= last {
+            // The reference escaped before this pixel did, so finish the orbit without it
+            return mandellike_loop(
+                bindings,
+                mandellike_iter(uniforms, z, c, fractal_type),
+                c,
+                i + 1,
+                fractal_type,
+            );
+        }
+
+        dz = perturb(uniforms, z_ref, dz, fractal_type) + dc;
+    }
+    private.final_z.set(z);
+    -1.0
+}
+
+/// Returned instead of the escape value when the pixel has to be iterated again with the next reference
+const GLITCHED: f32 = -2.0;
+
+/// The reference the pixel is currently iterated against
+fn current_reference(bindings: &Bindings) -> ReferenceCallback {
+    bindings.uniforms.references[bindings.private.reference_index.get()]
+}
+
+/// Iteration `i` of the current reference orbit
+fn reference_z(bindings: &Bindings, i: i32) -> Vector2f {
+    bindings.reference_orbit[current_reference(bindings).start as usize + i as usize].into()
+}
+
+/// The index of the last usable value in the current reference orbit.
+///
+/// Unlike the GPU, out of bounds reads would panic, so never trust `len` past the end of the slice
+fn last_reference_iteration(bindings: &Bindings) -> i32 {
+    let reference = current_reference(bindings);
+    let available = bindings.reference_orbit.len().saturating_sub(reference.start as usize);
+    (reference.len as usize).min(available) as i32 - 1
+}
+
+/// `f(z_ref + dz) - f(z_ref)` for the iteration function `f` of each fractal, without the constant
+fn perturb(uniforms: &RenderCallback, z_ref: Vector2f, dz: Vector2f, fractal_type: u32) -> Vector2f {
+    match fractal_type {
+        0 => perturb_pow(uniforms, z_ref, dz),
+        1 => perturb_burning_ship(z_ref, dz),
+        2 => perturb_pow(uniforms, conjugate_complex(z_ref), conjugate_complex(dz)),
+        _ => dz,
+    }
+}
+
+/// `(z_ref + dz)^n - z_ref^n` for an integer exponent, expanded with the binomial theorem
+/// and evaluated with Horner's method so no term suffers from cancellation
+fn perturb_pow(uniforms: &RenderCallback, z_ref: Vector2f, dz: Vector2f) -> Vector2f {
+    let n = uniforms.exponent as i32;
+    let mut acc = Vector2f::new(1.0, 0.0);
+    let mut z_pow = Vector2f::new(1.0, 0.0);
+    let mut binomial = 1.0_f32;
+    for k in (1..n).rev() {
+        z_pow = mul_complex(z_pow, z_ref);
+        binomial = binomial * (k + 1) as f32 / (n - k) as f32;
+        acc = mul_complex(acc, dz) + z_pow * binomial;
+    }
+    mul_complex(acc, dz)
+}
+
+/// The burning ship squares `(|x|, |y|)`, so the imaginary part needs the difference of two absolute values
+fn perturb_burning_ship(z_ref: Vector2f, dz: Vector2f) -> Vector2f {
+    let x = (2.0 * z_ref.x + dz.x) * dz.x - (2.0 * z_ref.y + dz.y) * dz.y;
+    let y = 2.0 * diffabs(z_ref.x * z_ref.y, z_ref.x * dz.y + dz.x * z_ref.y + dz.x * dz.y);
+    Vector2f::new(x, y)
+}
+
+/// `|c + d| - |c|` without catastrophic cancellation
+fn diffabs(c: f32, d: f32) -> f32 {
+    let cd = c + d;
+    if c >= 0.0 {
+        if cd >= 0.0 {
+            return d;
+        }
+        return -(2.0 * c + d);
+    }
+    if cd > 0.0 {
+        return 2.0 * c + d;
+    }
+    -d
+}
+
+/// An extended exponent number for deltas too small for [`f32`], the value is `m * 2^e`.
+/// Both components share the exponent, which is chosen so the larger one is in `[0.5, 1)`.
+#[derive(Clone, Copy)]
+struct ComplexExp {
+    m: Vector2f,
+    e: i32,
+}
+
+/// The exponent of zero, low enough that adding it to anything leaves the other number unchanged
+const ZERO_EXP: i32 = -1_000_000;
+
+/// The exponent of WGSL's `frexp`, so that `x = fract * 2^exp` with `fract` in `[0.5, 1)`
+fn frexp_exp(x: f32) -> i32 {
+    let biased = ((x.to_bits() >> 23) & 0xff) as i32;
+    if biased == 0 {
+        // Subnormal, scale it into the normal range first
+        return frexp_exp(x * 2.0_f32.powi(32)) - 32;
+    }
+    biased - 126
+}
+
+/// WGSL's `ldexp`, `a * 2^e`
+fn ldexp(a: Vector2f, e: i32) -> Vector2f {
+    let scale = 2.0_f64.powi(e);
+    Vector2f::new((f64::from(a.x) * scale) as f32, (f64::from(a.y) * scale) as f32)
+}
+
+fn cexp_new(m: Vector2f, e: i32) -> ComplexExp {
+    let big = m.x.abs().max(m.y.abs());
+    if big == 0.0 {
+        return ComplexExp {
+            m: Vector2f::new(0.0, 0.0),
+            e: ZERO_EXP,
+        };
+    }
+    let shift = frexp_exp(big);
+    ComplexExp {
+        m: ldexp(m, -shift),
+        e: e + shift,
+    }
+}
+
+/// `a * 2^e`, flushed to zero below the normal range of [`f32`], where the shader's `ldexp` is undefined
+fn ldexp_flush(a: Vector2f, e: i32) -> Vector2f {
+    if e < -126 {
+        return Vector2f::new(0.0, 0.0);
+    }
+    ldexp(a, e.min(127))
+}
+
+fn cexp_to_vec2(a: ComplexExp) -> Vector2f {
+    ldexp_flush(a.m, a.e)
+}
+
+fn cexp_mul(a: ComplexExp, b: ComplexExp) -> ComplexExp {
+    cexp_new(mul_complex(a.m, b.m), a.e + b.e)
+}
+
+fn cexp_add(a: ComplexExp, b: ComplexExp) -> ComplexExp {
+    // Align the smaller number to the exponent of the larger one, unless it is too small to matter
+    let shift = a.e - b.e;
+    if shift > 32 {
+        return a;
+    }
+    if shift < -32 {
+        return b;
+    }
+    if shift >= 0 {
+        return cexp_new(a.m + ldexp(b.m, -shift), a.e);
+    }
+    cexp_new(ldexp(a.m, shift) + b.m, b.e)
+}
+
+/// [`mandellike_perturbed`] with extended exponent deltas, for zooms where the pixel spacing is too small for
+/// [`f32`]. The deltas only stay extended until they are big enough for the normal perturbation loop.
+fn mandellike_perturbed_exp(bindings: &Bindings, pos: Vector2f, delta: ComplexExp, fractal_type: u32) -> f32 {
+    let uniforms = bindings.uniforms;
+    let mut dz = cexp_new(Vector2f::new(0.0, 0.0), 0);
+    let mut dc = delta;
+    let mut c = pos;
+    if (uniforms.flags & 1) == 1 {
+        dz = delta;
+        dc = cexp_new(Vector2f::new(0.0, 0.0), 0);
+        c = uniforms.julia_pos.into();
+    }
+
+    // The derivative is as small as the deltas, so it's extended too
+    let zoom = cexp_new(Vector2f::new(uniforms.zoom[0], 0.0), uniforms.zoom_exp);
+    let mut d = cexp_new(Vector2f::new(0.0, 0.0), 0);
+    if (uniforms.flags & 1) == 1 {
+        d = zoom;
+    }
+    let mut i = 0;
+    if uniforms.series_skip > 0 && bindings.private.reference_index.get() == 0 {
+        dz = series_delta(uniforms, delta);
+        d = series_derivative(uniforms, delta);
+        i = uniforms.series_skip as i32;
+    }
+
+    // While dz is this small, the pixel can't escape before the reference does
+    let last = last_reference_iteration(bindings);
+    while i < last && dz.e < -64 {
+        let z_ref = reference_z(bindings, i);
+        let z = z_ref + cexp_to_vec2(dz);
+        average_step(bindings, z, c, i);
+        trap_step(bindings, z, i);
+        if (uniforms.flags & 64) == 64 {
+            d = cexp_new(derivative_iter(uniforms, z, d.m, fractal_type), d.e);
+            if (uniforms.flags & 1) == 0 {
+                d = cexp_add(d, zoom);
+            }
+        }
+        dz = cexp_add(perturb_exp(uniforms, z_ref, dz, fractal_type), dc);
+        i += 1;
+    }
+    bindings.private.derivative.set(cexp_to_vec2(d));
+    perturbed_loop(bindings, cexp_to_vec2(dz), cexp_to_vec2(dc), c, i, fractal_type)
+}
+
+/// The delta after the iterations skipped by the series approximation, `A d + B d^2 + C d^3`
+/// where `d` is `dc`, or the starting `dz` for Julia sets
+fn series_delta(uniforms: &RenderCallback, d: ComplexExp) -> ComplexExp {
+    let a = series_coefficient(uniforms, 0);
+    let b = series_coefficient(uniforms, 1);
+    let c = series_coefficient(uniforms, 2);
+    cexp_mul(cexp_add(cexp_mul(cexp_add(cexp_mul(c, d), b), d), a), d)
+}
+
+/// The derivative of the delta after the skipped iterations along the normalized coordinates,
+/// `(A + 2B d + 3C d^2) zoom`
+fn series_derivative(uniforms: &RenderCallback, d: ComplexExp) -> ComplexExp {
+    let a = series_coefficient(uniforms, 0);
+    let b = cexp_mul(series_coefficient(uniforms, 1), cexp_new(Vector2f::new(2.0, 0.0), 0));
+    let c = cexp_mul(series_coefficient(uniforms, 2), cexp_new(Vector2f::new(3.0, 0.0), 0));
+    let zoom = cexp_new(Vector2f::new(uniforms.zoom[0], 0.0), uniforms.zoom_exp);
+    cexp_mul(cexp_add(cexp_mul(cexp_add(cexp_mul(c, d), b), d), a), zoom)
+}
+
+fn series_coefficient(uniforms: &RenderCallback, i: usize) -> ComplexExp {
+    let coefficient = uniforms.series[i];
+    cexp_new(Vector2f::new(coefficient[0], coefficient[1]), coefficient[2] as i32)
+}
+
+/// [`perturb`] for an extended exponent delta
+fn perturb_exp(uniforms: &RenderCallback, z_ref: Vector2f, dz: ComplexExp, fractal_type: u32) -> ComplexExp {
+    match fractal_type {
+        0 => perturb_pow_exp(uniforms, z_ref, dz),
+        1 => perturb_burning_ship_exp(z_ref, dz),
+        2 => perturb_pow_exp(
+            uniforms,
+            conjugate_complex(z_ref),
+            ComplexExp {
+                m: conjugate_complex(dz.m),
+                e: dz.e,
+            },
+        ),
+        _ => dz,
+    }
+}
+
+fn perturb_pow_exp(uniforms: &RenderCallback, z_ref: Vector2f, dz: ComplexExp) -> ComplexExp {
+    let n = uniforms.exponent as i32;
+    let mut acc = cexp_new(Vector2f::new(1.0, 0.0), 0);
+    let mut z_pow = Vector2f::new(1.0, 0.0);
+    let mut binomial = 1.0_f32;
+    for k in (1..n).rev() {
+        z_pow = mul_complex(z_pow, z_ref);
+        binomial = binomial * (k + 1) as f32 / (n - k) as f32;
+        acc = cexp_add(cexp_mul(acc, dz), cexp_new(z_pow * binomial, 0));
+    }
+    cexp_mul(acc, dz)
+}
+
+/// The terms are computed as mantissas with the exponent of `dz`
+fn perturb_burning_ship_exp(z_ref: Vector2f, dz: ComplexExp) -> ComplexExp {
+    let d = cexp_to_vec2(dz);
+    let x = (2.0 * z_ref.x + d.x) * dz.m.x - (2.0 * z_ref.y + d.y) * dz.m.y;
+    let y = 2.0
+        * diffabs_exp(
+            z_ref.x * z_ref.y,
+            z_ref.x * dz.m.y + dz.m.x * z_ref.y + dz.m.x * d.y,
+            dz.e,
+        );
+    cexp_new(Vector2f::new(x, y), dz.e)
+}
+
+/// [`diffabs`] for `d * 2^e`, returning the mantissa for the same exponent
+fn diffabs_exp(c: f32, d: f32, e: i32) -> f32 {
+    // The sign of c + d, taken from d if it is too small to change c
+    let mut cd = c + ldexp_flush(Vector2f::new(d, 0.0), e).x;
+    if cd == 0.0 {
+        cd = d;
+    }
+    // 2c only has to be scaled when c + d and c have different signs, so c is not bigger than d
+    let c2 = ldexp_flush(Vector2f::new(2.0 * c, 0.0), -e).x;
+    if c >= 0.0 {
+        if cd >= 0.0 {
+            return d;
+        }
+        return -(c2 + d);
+    }
+    if cd > 0.0 {
+        return c2 + d;
+    }
+    -d
+}
+
+fn mandellike_iter(uniforms: &RenderCallback, z: Vector2f, c: Vector2f, fractal_type: u32) -> Vector2f {
+    match fractal_type {
+        0 => mandelbrot(uniforms, z, c),
+        1 => burning_ship(uniforms, z, c),
+        2 => tricorn(uniforms, z, c),
+        _ => z,
+    }
+}
+
+fn mandelbrot(uniforms: &RenderCallback, z: Vector2f, c: Vector2f) -> Vector2f {
+    powf_complex(z, uniforms.exponent) + c
+}
+
+fn burning_ship(uniforms: &RenderCallback, z: Vector2f, c: Vector2f) -> Vector2f {
+    powf_complex(abs_complex(z), uniforms.exponent) + c
+}
+
+fn tricorn(uniforms: &RenderCallback, z: Vector2f, c: Vector2f) -> Vector2f {
+    powf_complex(conjugate_complex(z), uniforms.exponent) + c
+}
+
+/// `a + b` with the rounding error in `y`
+fn two_sum(a: f32, b: f32) -> Vector2f {
+    let s = a + b;
+    let bb = s - a;
+    let e = (a - (s - bb)) + (b - bb);
+    Vector2f::new(s, e)
+}
+
+/// `a + b` with the rounding error in `y`, requires `|a| >= |b|`
+fn quick_two_sum(a: f32, b: f32) -> Vector2f {
+    let s = a + b;
+    let e = b - (s - a);
+    Vector2f::new(s, e)
+}
+
+/// Split `a` into two halves with 12 bits of mantissa each, so their products are exact
+fn split(a: f32) -> Vector2f {
+    let t = 4097.0 * a;
+    let hi = t - (t - a);
+    Vector2f::new(hi, a - hi)
+}
+
+/// `a * b` with the rounding error in `y`
+fn two_prod(a: f32, b: f32) -> Vector2f {
+    let p = a * b;
+    let a_split = split(a);
+    let b_split = split(b);
+    let e = ((a_split.x * b_split.x - p) + a_split.x * b_split.y + a_split.y * b_split.x) + a_split.y * b_split.y;
+    Vector2f::new(p, e)
+}
+
+fn df_add(a: Vector2f, b: Vector2f) -> Vector2f {
+    let mut s = two_sum(a.x, b.x);
+    let t = two_sum(a.y, b.y);
+    s.y += t.x;
+    s = quick_two_sum(s.x, s.y);
+    s.y += t.y;
+    quick_two_sum(s.x, s.y)
+}
+
+fn df_mul(a: Vector2f, b: Vector2f) -> Vector2f {
+    let mut p = two_prod(a.x, b.x);
+    p.y += a.x * b.y + a.y * b.x;
+    quick_two_sum(p.x, p.y)
+}
+
+fn df_abs(a: Vector2f) -> Vector2f {
+    if a.x < 0.0 {
+        return a * -1.0;
+    }
+    a
+}
+
+/// Build a complex double-float, stored as `[re hi, re lo, im hi, im lo]` like the shader's `vec4`
+fn complex_df(re: Vector2f, im: Vector2f) -> [f32; 4] {
+    [re.x, re.y, im.x, im.y]
+}
+
+fn re_df(a: [f32; 4]) -> Vector2f {
+    Vector2f::new(a[0], a[1])
+}
+
+fn im_df(a: [f32; 4]) -> Vector2f {
+    Vector2f::new(a[2], a[3])
+}
+
+fn add_complex_df(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    complex_df(df_add(re_df(a), re_df(b)), df_add(im_df(a), im_df(b)))
+}
+
+fn mul_complex_df(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    let re = df_add(df_mul(re_df(a), re_df(b)), df_mul(im_df(a), im_df(b)) * -1.0);
+    let im = df_add(df_mul(re_df(a), im_df(b)), df_mul(im_df(a), re_df(b)));
+    complex_df(re, im)
+}
+
+fn square_complex_df(a: [f32; 4]) -> [f32; 4] {
+    let re = df_add(df_mul(re_df(a), re_df(a)), df_mul(im_df(a), im_df(a)) * -1.0);
+    let im = df_mul(df_mul(re_df(a), im_df(a)), Vector2f::new(2.0, 0.0));
+    complex_df(re, im)
+}
+
+/// Raise to a positive integer power
+fn pow_complex_df(a: [f32; 4], n: i32) -> [f32; 4] {
+    if n == 2 {
+        return square_complex_df(a);
+    }
+    let mut z = a;
+    for _ in 1..n {
+        z = mul_complex_df(z, a);
+    }
+    z
+}
+
+fn mandellike_df(bindings: &Bindings, pos: [f32; 4], fractal_type: u32) -> f32 {
+    let uniforms = bindings.uniforms;
+    let private = bindings.private;
+    let mut z = [0.0; 4];
+    let c;
+    if (uniforms.flags & 1) == 1 {
+        z = pos;
+        c = [uniforms.julia_pos[0], 0.0, uniforms.julia_pos[1], 0.0];
+    } else {
+        c = pos;
+    }
+
+    let escape = escape_radius(uniforms);
+    let escape_sq = escape * escape;
+    let n = uniforms.exponent as i32;
+
+    private.derivative.set(start_derivative(uniforms));
+    for i in 0..uniforms.max_iter {
+        average_step(bindings, Vector2f::new(z[0], z[2]), Vector2f::new(c[0], c[2]), i);
+        let zn_sq = z[0] * z[0] + z[2] * z[2];
+        if zn_sq >= escape_sq {
+            private.final_z.set(Vector2f::new(z[0], z[2]));
+            return escape_value(bindings, i, zn_sq);
+        }
+
+        let z_hi = Vector2f::new(z[0], z[2]);
+        trap_step(bindings, z_hi, i);
+        private
+            .derivative
+            .set(derivative_step(uniforms, z_hi, private.derivative.get(), fractal_type));
+        match fractal_type {
+            0 => z = pow_complex_df(z, n),
+            1 => z = pow_complex_df(complex_df(df_abs(re_df(z)), df_abs(im_df(z))), n),
+            2 => z = pow_complex_df(complex_df(re_df(z), im_df(z) * -1.0), n),
+            _ => {}
+        }
+        z = add_complex_df(z, c);
+    }
+    private.final_z.set(Vector2f::new(z[0], z[2]));
+    -1.0
+}
+
+/// Compute the RGBA color of a single pixel
+fn fs_main(bindings: &Bindings, frag_coord: Vector2f) -> [f32; 4] {
+    let uniforms = bindings.uniforms;
+    let private = bindings.private;
+    let res = pixel_value(bindings, frag_coord);
+
+    let mut color = [0.0, 0.0, 0.0];
+    if res.is_nan() {
+        color = [1.0, 0.0, 1.0];
+    } else if res >= 0.0 {
+        let scheme = &uniforms.color_scheme;
+        let mut t = res * uniforms.palette_speed;
+        if (5..=7).contains(&uniforms.shading_type) {
+            t += private.average.get() * uniforms.average_strength;
+        }
+        color = pal(t, scheme.a, scheme.b, scheme.c, scheme.d);
+        if uniforms.shading_type == 2 && (uniforms.flags & 64) == 64 {
+            color = color.map(|channel| channel * distance_shade(bindings));
+        }
+        if uniforms.shading_type == 3 && (uniforms.flags & 64) == 64 {
+            color = light_shade(bindings, color);
+        }
+    }
+    if (uniforms.flags & 128) == 128 && !res.is_nan() && private.trap_distance.get() < TRAP_MISSED {
+        let scheme = &uniforms.color_scheme;
+        color = pal(trap_value(bindings), scheme.a, scheme.b, scheme.c, scheme.d);
+    }
+
+    let [r, g, b] = glitch_overlay(uniforms, color, glitch_state(bindings));
+    [r, g, b, 1.0]
+}
+
+/// The glitch state of the pixel, -1 if it's still glitched, otherwise the index of the reference it used
+fn glitch_state(bindings: &Bindings) -> f32 {
+    let private = bindings.private;
+    if private.glitched.get() {
+        return -1.0;
+    }
+    private.reference_index.get() as f32
+}
+
+/// The glitch overlay shows pixels that are still glitched in red,
+/// and pixels that needed another reference tinted green
+fn glitch_overlay(uniforms: &RenderCallback, color: [f32; 3], state: f32) -> [f32; 3] {
+    if (uniforms.flags & 16) != 16 {
+        return color;
+    }
+    if state < 0.0 {
+        [1.0, 0.0, 0.0]
+    } else if state > 0.0 {
+        [color[0] * 0.5, color[1] * 0.5 + 0.5, color[2] * 0.5]
+    } else {
+        color
+    }
+}
+
+/// The cumulative distribution of the histogram, like the buffer the shader's `cs_cdf` writes
+struct Cdf {
+    low: f32,
+    high: f32,
+    /// The fraction of the escaped pixels in the bins before each bin, and 1 at the end
+    values: Vec<f32>,
+}
+
+/// The first pass of the histogram coloring, the value passed to the palette and the glitch state of the pixel
+fn fs_values(bindings: &Bindings, frag_coord: Vector2f) -> [f32; 2] {
+    let res = pixel_value(bindings, frag_coord);
+    [res, glitch_state(bindings)]
+}
+
+/// Build the histogram of the escaped values and sum it up, like `cs_range`, `cs_histogram` and `cs_cdf` do
+fn cumulative_distribution(values: impl Iterator<Item = f32> + Clone) -> Cdf {
+    // The bits of positive floats sort like the floats
+    let escaped = values.filter(|value| *value >= 0.0);
+    let low = f32::from_bits(escaped.clone().map(f32::to_bits).min().unwrap_or(u32::MAX));
+    let high = f32::from_bits(escaped.clone().map(f32::to_bits).max().unwrap_or(0));
+    let mut counts = vec![0_u32; HISTOGRAM_BINS];
+    for value in escaped {
+        let bin = (histogram_position(value, low, high) as usize).min(HISTOGRAM_BINS - 1);
+        counts[bin] += 1;
+    }
+
+    let total = counts.iter().sum::<u32>();
+    let mut sum = 0;
+    let mut values = Vec::with_capacity(HISTOGRAM_BINS + 1);
+    for count in counts {
+        values.push(sum as f32 / total.max(1) as f32);
+        sum += count;
+    }
+    values.push(1.0);
+    Cdf { low, high, values }
+}
+
+/// The color of an escaped value is picked by the fraction of the escaped pixels with a smaller value, so the
+/// palette is spread evenly over the frame whatever the iteration counts are
+fn histogram_color(uniforms: &RenderCallback, cdf: &Cdf, value: [f32; 2]) -> [f32; 3] {
+    let mut color = [0.0, 0.0, 0.0];
+    if value[0].is_nan() {
+        color = [1.0, 0.0, 1.0];
+    } else if value[0] >= 0.0 {
+        let scheme = &uniforms.color_scheme;
+        color = pal(equalized(cdf, value[0]), scheme.a, scheme.b, scheme.c, scheme.d);
+    }
+    glitch_overlay(uniforms, color, value[1])
+}
+
+/// The cumulative distribution at an escaped value, interpolated inside its bin
+fn equalized(cdf: &Cdf, value: f32) -> f32 {
+    let position = histogram_position(value, cdf.low, cdf.high);
+    let bin = (position as usize).min(HISTOGRAM_BINS - 1);
+    mix(cdf.values[bin], cdf.values[bin + 1], position - bin as f32)
+}
+
+/// Where an escaped value is in the histogram, from 0 at the smallest value to [`HISTOGRAM_BINS`] at the biggest
+fn histogram_position(value: f32, low: f32, high: f32) -> f32 {
+    if high <= low {
+        return 0.0;
+    }
+    ((value - low) / (high - low) * HISTOGRAM_BINS as f32).clamp(0.0, HISTOGRAM_BINS as f32)
+}
+
+/// How much the color of an escaped pixel is darkened by its distance to the set, 0 on the boundary and 1 from
+/// `boundary_width` pixels away. The distance is estimated as `|z| ln|z| / |dz/dc|`
+fn distance_shade(bindings: &Bindings) -> f32 {
+    let private = bindings.private;
+    let z_abs = private.final_z.get().length();
+    let distance = z_abs * z_abs.ln() / private.derivative.get().length() / private.pixel_size.get();
+    if distance.is_nan() {
+        return 1.0;
+    }
+    (distance / bindings.uniforms.boundary_width).clamp(0.0, 1.0)
+}
+
+/// The Blinn-Phong exponent, higher makes the highlights smaller
+const SHININESS: f32 = 24.0;
+
+/// `color` lit with Lambert and Blinn-Phong shading. The potential falls towards the set along `z / dz`, so the
+/// surface falls away from the set with the normal tilted that way, turned by the rotation to match the screen
+fn light_shade(bindings: &Bindings, color: [f32; 3]) -> [f32; 3] {
+    let uniforms = bindings.uniforms;
+    let private = bindings.private;
+    let rotation = Vector2f::from(uniforms.rotation);
+    let slope = mul_complex(
+        private.final_z.get(),
+        conjugate_complex(mul_complex(private.derivative.get(), rotation)),
+    )
+    .normalize();
+    let normal = normalize3([slope.x, slope.y, 1.0]);
+    if normal[2].is_nan() {
+        return color;
+    }
+    let [x, y, z, strength] = uniforms.light;
+    let light = [x, y, z];
+    let diffuse = dot3(normal, light).max(0.0);
+    let halfway = normalize3([x, y, z + 1.0]);
+    let specular = strength * dot3(normal, halfway).max(0.0).powf(SHININESS);
+    std::array::from_fn(|i| mix(1.0, color[i], uniforms.light_blend) * diffuse + specular)
+}
+
+fn normalize3(a: [f32; 3]) -> [f32; 3] {
+    let length = dot3(a, a).sqrt();
+    a.map(|component| component / length)
+}
+
+fn dot3(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+/// `trap_distance` before the orbit comes close to the trap
+const TRAP_MISSED: f32 = 3.0e38;
+
+fn start_trap(bindings: &Bindings) {
+    bindings.private.trap_distance.set(TRAP_MISSED);
+    bindings.private.trap_iteration.set(0);
+}
+
+/// Record how close `z` at iteration `i` is to the trap, if bit 7 of the flags is set.
+/// The starting z is left out, for the Mandelbrot set it's zero for every pixel
+fn trap_step(bindings: &Bindings, z: Vector2f, i: i32) {
+    let uniforms = bindings.uniforms;
+    let private = bindings.private;
+    if (uniforms.flags & 128) == 0 || i == 0 {
+        return;
+    }
+    let distance = distance_to_trap(uniforms, z);
+    if uniforms.trap_shape == 4 && distance >= uniforms.trap_size {
+        return;
+    }
+    if distance < private.trap_distance.get() {
+        private.trap_distance.set(distance);
+        private.trap_iteration.set(i);
+    }
+}
+
+fn distance_to_trap(uniforms: &RenderCallback, z: Vector2f) -> f32 {
+    let [re, im, cos, sin] = uniforms.trap;
+    let offset = z - Vector2f::new(re, im);
+    // Along the lines of the trap
+    let aligned = mul_complex(offset, conjugate_complex(Vector2f::new(cos, sin)));
+    match uniforms.trap_shape {
+        1 => aligned.y.abs(),
+        2 | 4 => aligned.x.abs().min(aligned.y.abs()),
+        3 => (offset.length() - uniforms.trap_size).abs(),
+        _ => offset.length(),
+    }
+}
+
+/// The value passed to the palette for the closest approach to the trap
+fn trap_value(bindings: &Bindings) -> f32 {
+    let uniforms = bindings.uniforms;
+    if uniforms.trap_coloring == 1 {
+        return bindings.private.trap_iteration.get() as f32 * uniforms.palette_speed;
+    }
+    bindings.private.trap_distance.get() / uniforms.trap_size
+}
+
+/// The escape radius, the average colorings need a bigger one to be smooth
+fn escape_radius(uniforms: &RenderCallback) -> f32 {
+    if (5..=7).contains(&uniforms.shading_type) {
+        return AVERAGE_ESCAPE;
+    }
+    4.0
+}
+
+const AVERAGE_ESCAPE: f32 = 100.0;
+
+fn start_average(bindings: &Bindings) {
+    let private = bindings.private;
+    private.average_sum.set(0.0);
+    private.average_count.set(0);
+    private.average_last.set(0.0);
+    private.average.set(0.0);
+}
+
+/// Add the term of `z` at iteration `i` to the average coloring of the shading type
+fn average_step(bindings: &Bindings, z: Vector2f, c: Vector2f, i: i32) {
+    let uniforms = bindings.uniforms;
+    let private = bindings.private;
+    match uniforms.shading_type {
+        // Stripe average: stripes going out from the origin
+        5 => {
+            if i >= 1 {
+                add_average(bindings, 0.5 * (uniforms.stripe_frequency * z.y.atan2(z.x)).sin() + 0.5);
+            }
+        }
+        // Triangle inequality average: where |z| is between the smallest and the biggest it can be for the size
+        // of the previous z and c
+        6 => {
+            if i >= 2 {
+                let power = (z - c).length();
+                let c_abs = c.length();
+                let low = (power - c_abs).abs();
+                let high = power + c_abs;
+                if high > low {
+                    add_average(bindings, (z.length() - low) / (high - low));
+                }
+            }
+        }
+        // Curvature average: how sharply the orbit turns
+        7 => {
+            if i >= 2 {
+                let previous = private.previous_z.get();
+                let turn = mul_complex(z - previous, conjugate_complex(previous - private.previous_z2.get()));
+                if turn.x != 0.0 || turn.y != 0.0 {
+                    add_average(bindings, turn.y.atan2(turn.x).abs() / (0.5 * TAU));
+                }
+            }
+        }
+        _ => return,
+    }
+    private.previous_z2.set(private.previous_z.get());
+    private.previous_z.set(z);
+}
+
+fn add_average(bindings: &Bindings, term: f32) {
+    let private = bindings.private;
+    private.average_sum.set(private.average_sum.get() + term);
+    private.average_count.set(private.average_count.get() + 1);
+    private.average_last.set(term);
+}
+
+/// The average of the terms, moving towards the average without the last term as z goes further past the escape
+/// radius, so it's continuous across the iteration the pixel escapes at
+fn average_value(bindings: &Bindings, fraction: f32) -> f32 {
+    let private = bindings.private;
+    let count = private.average_count.get();
+    if count == 0 {
+        return 0.0;
+    }
+    let current = private.average_sum.get() / count as f32;
+    if count == 1 {
+        return current;
+    }
+    let previous = (private.average_sum.get() - private.average_last.get()) / (count - 1) as f32;
+    mix(current, previous, fraction)
+}
+
+/// The value passed to the palette for the pixel at `frag_coord`, negative if it doesn't escape
+fn pixel_value(bindings: &Bindings, frag_coord: Vector2f) -> f32 {
+    let uniforms = bindings.uniforms;
+    let resolution: Vector2f = uniforms.resolution.into();
+    let offset: Vector2f = uniforms.offset.into();
+    let center = uniforms.center;
+    // Relative to the center of the screen, with the imaginary axis pointing up and rotated with the camera
+    let centered = (frag_coord - resolution * 0.5 - offset) / resolution.x.min(resolution.y) * 2.0;
+    let mut flipped = Vector2f::new(centered.x, -centered.y);
+    let private = bindings.private;
+    private.pixel_size.set(2.0 / resolution.x.min(resolution.y));
+    if (uniforms.flags & 32) == 32 {
+        // Exponential map: the angle around the center goes along x, one turn over the width, and the distance
+        // shrinks down y from the zoom at the top row, by the same factor per row as a pixel is wide
+        let pixel = frag_coord - offset;
+        flipped = exp_complex(Vector2f::new(-pixel.y, pixel.x) * (TAU / resolution.x));
+        private.pixel_size.set(flipped.length() * TAU / resolution.x);
+    }
+    start_trap(bindings);
+    start_average(bindings);
+    let [cos, sin] = uniforms.rotation;
+    let normalized = Vector2f::new(flipped.x * cos - flipped.y * sin, flipped.x * sin + flipped.y * cos);
+    let zoom = ldexp_flush(uniforms.zoom.into(), uniforms.zoom_exp);
+    let delta = normalized * zoom.x;
+    let scaled = delta + Vector2f::new(center[0], center[2]);
+
+    let private = bindings.private;
+    if (uniforms.flags & 2) == 2 && uniforms.ref_count > 0 {
+        // Try the references in order until the pixel isn't glitched
+        private.reference_index.set(0);
+        loop {
+            private.glitched.set(false);
+            start_trap(bindings);
+            start_average(bindings);
+            let res = mandellike_perturbed_reference(bindings, normalized, scaled);
+            if res != GLITCHED {
+                break res;
+            }
+            private.reference_index.set(private.reference_index.get() + 1);
+        }
+    } else if (uniforms.flags & 4) == 4 {
+        // Offset from the camera in double-float precision before adding the camera center
+        let re = df_add(df_mul(Vector2f::new(normalized.x, 0.0), zoom), re_df(center));
+        let im = df_add(df_mul(Vector2f::new(normalized.y, 0.0), zoom), im_df(center));
+        mandellike_df(bindings, complex_df(re, im), uniforms.fractal_type)
+    } else {
+        mandellike(bindings, scaled, uniforms.fractal_type)
+    }
+}
+
+/// Iterate the pixel against the current reference, with extended exponent deltas if bit 3 of the flags is set
+fn mandellike_perturbed_reference(bindings: &Bindings, normalized: Vector2f, scaled: Vector2f) -> f32 {
+    let uniforms = bindings.uniforms;
+    let offset = normalized + Vector2f::from(current_reference(bindings).offset);
+    if (uniforms.flags & 8) == 8 {
+        // The offset from the reference, keeping the exponent of the zoom
+        let delta_exp = cexp_mul(
+            cexp_new(offset, 0),
+            cexp_new(Vector2f::new(uniforms.zoom[0], 0.0), uniforms.zoom_exp),
+        );
+        return mandellike_perturbed_exp(bindings, scaled, delta_exp, uniforms.fractal_type);
+    }
+    let zoom = ldexp_flush(uniforms.zoom.into(), uniforms.zoom_exp);
+    mandellike_perturbed(bindings, scaled, offset * zoom.x, uniforms.fractal_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::floatexp::FloatExp;
+    use crate::vector2::{Vector2Big, Vector2d};
+    use crate::{Application, CameraInfo, FractalType, Light, ShadingType};
+
+    /// The iterations [`render_data`] gives the one pixel of a view centered on `point`
+    fn point_iterations(fractal_type: FractalType, julia: Option<Vector2d>, point: Vector2d) -> f32 {
+        let mut application = Application {
+            camera: CameraInfo::new(Vector2Big::from_f64(point).unwrap(), FloatExp::from_f64(1e-3), 0.0),
+            max_iter: 1000,
+            fractal_type,
+            julia: julia.is_some(),
+            julia_pos: julia.unwrap_or_default(),
+            ..Application::default()
+        };
+        application.render_data(1, 1).values[0]
+    }
+
+    /// The smooth iteration count of `point`, iterated in [`f64`] straight from the definitions
+    fn expected_iterations(fractal_type: FractalType, julia: Option<Vector2d>, point: Vector2d) -> f32 {
+        let (mut z, c) = julia.map_or((Vector2d::new(0.0, 0.0), point), |c| (point, c));
+        for i in 0..1000 {
+            let zn_sq = z.x * z.x + z.y * z.y;
+            if zn_sq >= 16.0 {
+                let fraction = ((zn_sq.ln() / (2.0 * 4f64.ln())).ln() / 2f64.ln()).clamp(0.0, 1.0);
+                return (f64::from(i) - 2.0 - fraction).max(0.0) as f32;
+            }
+            let z_in = match fractal_type {
+                FractalType::Mandelbrot => z,
+                FractalType::BurningShip => Vector2d::new(z.x.abs(), z.y.abs()),
+                FractalType::Tricorn => Vector2d::new(z.x, -z.y),
+            };
+            z = Vector2d::new(z_in.x * z_in.x - z_in.y * z_in.y, 2.0 * z_in.x * z_in.y) + c;
+        }
+        -1.0
+    }
+
+    /// Points in the set never escape and points outside escape with the smooth iteration count,
+    /// for every fractal and their Julia sets
+    #[test]
+    fn interior_and_escape_points() {
+        let julia_pos = Vector2d::new(-0.4, 0.0);
+        for fractal_type in [FractalType::Mandelbrot, FractalType::BurningShip, FractalType::Tricorn] {
+            for julia in [None, Some(julia_pos)] {
+                // On the real axis, with a real Julia position, every fractal iterates like the Mandelbrot set
+                for interior in [Vector2d::new(-0.5, 0.0), Vector2d::new(0.0, 0.0)] {
+                    assert_eq!(expected_iterations(fractal_type, julia, interior), -1.0);
+                    assert_eq!(
+                        point_iterations(fractal_type, julia, interior),
+                        -1.0,
+                        "{fractal_type:?} {julia:?}"
+                    );
+                }
+                let escapes: &[_] = if julia.is_some() {
+                    &[Vector2d::new(-0.6, 0.7), Vector2d::new(-0.2, 1.0)]
+                } else {
+                    &[
+                        Vector2d::new(-1.8, 0.05),
+                        Vector2d::new(0.5, -0.5),
+                        Vector2d::new(0.6, 0.5),
+                    ]
+                };
+                for &escape in escapes {
+                    let expected = expected_iterations(fractal_type, julia, escape);
+                    let iterations = point_iterations(fractal_type, julia, escape);
+                    assert!(expected > 0.0);
+                    assert!(
+                        (iterations - expected).abs() < 1e-3,
+                        "{fractal_type:?} {julia:?} {escape:?}"
+                    );
+                }
+            }
+        }
+
+        // c = -0.8 - 0.4i first gets past the escape radius at iteration 8, with |z| = 5.35, so a bit below 8 - 2
+        let iterations = point_iterations(FractalType::Mandelbrot, None, Vector2d::new(-0.8, -0.4));
+        assert!((iterations - 5.724_838).abs() < 1e-4, "{iterations}");
+    }
+
+    /// The antenna of the Mandelbrot set along the real axis is thinner than a pixel, so only the distance
+    /// shading shows it, as a dark line through the middle of the image
+    #[test]
+    fn distance_shading_draws_thin_filaments() {
+        let brightness = |shading_type| {
+            let mut application = Application {
+                camera: CameraInfo::new(
+                    Vector2Big::from_f64(Vector2d::new(-1.9, 0.0)).unwrap(),
+                    FloatExp::from_f64(0.05),
+                    0.0,
+                ),
+                shading_type,
+                ..Application::default()
+            };
+            let rgba = application.render_image(32, 32, None);
+            let row = |y: usize| {
+                let pixels = rgba[y * 32 * 4..][..32 * 4].chunks_exact(4);
+                pixels
+                    .map(|pixel| pixel[..3].iter().map(|&c| c as u32).sum::<u32>())
+                    .sum::<u32>()
+            };
+            (row(0), row(16))
+        };
+        let (smooth_edge, smooth_middle) = brightness(ShadingType::Smooth);
+        let (distance_edge, distance_middle) = brightness(ShadingType::Distance);
+        assert_eq!(smooth_edge, distance_edge);
+        assert!(distance_middle * 2 < smooth_middle, "{distance_middle} {smooth_middle}");
+    }
+
+    /// Up and to the right of the set the surface falls towards the top right, so a light from there is brighter
+    #[test]
+    fn lighting_faces_away_from_the_set() {
+        let brightness = |angle: f32| {
+            let mut application = Application {
+                camera: CameraInfo::new(
+                    Vector2Big::from_f64(Vector2d::new(1.3, 0.9)).unwrap(),
+                    FloatExp::from_f64(0.1),
+                    0.0,
+                ),
+                shading_type: ShadingType::Lighting,
+                light: Light {
+                    angle,
+                    height: 0.5,
+                    specular: 0.0,
+                    blend: 0.0,
+                },
+                ..Application::default()
+            };
+            let rgba = application.render_image(8, 8, None);
+            rgba.iter().map(|&c| c as u32).sum::<u32>()
+        };
+        let towards = brightness(std::f32::consts::FRAC_PI_4);
+        let away = brightness(std::f32::consts::FRAC_PI_4 + std::f32::consts::PI);
+        assert!(away * 2 < towards, "{away} {towards}");
+    }
+
+    /// With the palette speed at zero the color only comes from the average, which shouldn't jump where the
+    /// escape iteration changes
+    #[test]
+    fn averages_are_continuous() {
+        for shading_type in [
+            ShadingType::Stripe,
+            ShadingType::TriangleInequality,
+            ShadingType::Curvature,
+        ] {
+            let mut application = Application {
+                camera: CameraInfo::new(
+                    Vector2Big::from_f64(Vector2d::new(-2.0, 0.3)).unwrap(),
+                    FloatExp::from_f64(0.002),
+                    0.0,
+                ),
+                shading_type,
+                palette_speed: 0.0,
+                ..Application::default()
+            };
+            let rgba = application.render_image(256, 1, None);
+            let pixels: Vec<&[u8]> = rgba.chunks_exact(4).collect();
+            let biggest_step = pixels
+                .windows(2)
+                .flat_map(|pair| (0..3).map(|i| pair[0][i].abs_diff(pair[1][i])))
+                .max()
+                .unwrap();
+            let range = (0..3)
+                .map(|i| {
+                    let channel = pixels.iter().map(|pixel| pixel[i]);
+                    channel.clone().max().unwrap() - channel.min().unwrap()
+                })
+                .max()
+                .unwrap();
+            assert!(
+                u32::from(biggest_step) * 4 < u32::from(range),
+                "{shading_type:?} {biggest_step} {range}"
+            );
+        }
+    }
+
+    /// Values bunched up at the start of their range still get spread evenly over the palette
+    #[test]
+    fn histogram_equalizes_skewed_values() {
+        let values = (0..1000).map(|i| (i * i) as f32);
+        let cdf = cumulative_distribution(values.clone().chain([-1.0, f32::NAN]));
+        for (i, value) in values.enumerate() {
+            let expected = i as f32 / 1000.0;
+            assert!((equalized(&cdf, value) - expected).abs() < 0.02, "{i}");
+        }
+        assert_eq!(equalized(&cdf, 999.0 * 999.0), 1.0);
+    }
+
+    #[test]
+    fn trap_distances() {
+        let (sin, cos) = std::f32::consts::FRAC_PI_4.sin_cos();
+        let uniforms = RenderCallback {
+            trap: [1.0, 0.0, cos, sin],
+            trap_size: 0.5,
+            ..bytemuck::Zeroable::zeroed()
+        };
+        // A point on the line through the center of the trap, and one straight across from it
+        let z = Vector2f::new(2.0, 1.0);
+        let expected = [
+            (0, 2.0_f32.sqrt()),
+            (1, 0.0),
+            (2, 0.0),
+            (3, 2.0_f32.sqrt() - 0.5),
+            (4, 0.0),
+        ];
+        for (shape, distance) in expected {
+            let uniforms = RenderCallback {
+                trap_shape: shape,
+                ..uniforms
+            };
+            assert!(
+                (distance_to_trap(&uniforms, z) - distance).abs() < 1e-6,
+                "shape {shape}"
+            );
+        }
+        let z = Vector2f::new(0.0, 1.0);
+        let uniforms = RenderCallback {
+            trap_shape: 1,
+            ..uniforms
+        };
+        assert!((distance_to_trap(&uniforms, z) - 2.0_f32.sqrt()).abs() < 1e-6);
+    }
+}
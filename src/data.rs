@@ -0,0 +1,231 @@
+//! Exporting the raw values behind the colors of the current view, for processing them with other tools.
+//!
+//! Every pixel gets the values named in [`cpu::DATA_CHANNELS`] as little-endian [`f32`], in rows from the top
+//! with the channels of a pixel next to each other. They are saved either as a numpy `.npy` array with the shape
+//! `(height, width, 6)`, or after a text header in any other file:
+//!
+//! ```text
+//! mandelbrot-data 1
+//! width 1920
+//! height 1080
+//! channels iterations z_re z_im derivative_re derivative_im escaped
+//! type float32 little-endian
+//! end_header
+//! ```
+//!
+//! The data is always rendered on the CPU, and of the plain view even with [`Application::exponential_map`] set.
+
+use std::{fs, io, path::Path};
+
+use crate::export::{check_export_size, orbit_buffer};
+use crate::{cpu, Application};
+
+/// The file extension of numpy arrays
+pub const NPY_EXTENSION: &str = "npy";
+
+/// The file extension of the format with a text header
+pub const HEADER_EXTENSION: &str = "mdat";
+
+/// The values of every pixel of a rendered view
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawData {
+    pub width: u32,
+    pub height: u32,
+    /// [`cpu::DATA_CHANNELS`] values per pixel in rows
+    pub values: Vec<f32>,
+}
+
+impl Application {
+    /// Render the raw values of the current view into a `width` by `height` image, see [`cpu::render_data`].
+    /// The derivative is along the pixels, so `|z| ln|z| / |derivative|` estimates the distance to the set in
+    /// pixels. That only works if every pixel has the same size, so the exponential map is left out
+    pub fn render_data(&mut self, width: u32, height: u32) -> RawData {
+        let exponential_map = std::mem::replace(&mut self.exponential_map, false);
+        let (uniforms, references) = self.image_uniforms(width, height);
+        self.exponential_map = exponential_map;
+        let mut values = cpu::render_data(&uniforms, &orbit_buffer(&references), width, height);
+        // A pixel is this far apart in the normalized coordinates
+        let pixel_size = 2.0 / width.min(height) as f32;
+        for pixel in values.chunks_exact_mut(cpu::DATA_CHANNELS.len()) {
+            pixel[3] *= pixel_size;
+            pixel[4] *= pixel_size;
+        }
+        RawData { width, height, values }
+    }
+
+    /// Render the raw values of the current view with [`Application::render_data`] and save them at `path`,
+    /// as a numpy array if the extension is `.npy`, otherwise in the format with a text header
+    ///
+    /// # Errors
+    /// If the size is rejected by [`check_export_size`] or the file can't be written
+    pub fn export_data(&mut self, path: &Path, width: u32, height: u32) -> io::Result<()> {
+        check_export_size(width, height)?;
+        let data = self.render_data(width, height);
+        let is_npy = path.extension().is_some_and(|extension| extension == NPY_EXTENSION);
+        fs::write(path, if is_npy { data.to_npy() } else { data.to_header_format() })
+    }
+}
+
+/// Whether exporting to `path` should save the raw data instead of an image
+#[must_use]
+pub fn is_data_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == NPY_EXTENSION || extension == HEADER_EXTENSION)
+}
+
+impl RawData {
+    /// The data as a numpy array file, version 1.0 of the format
+    #[must_use]
+    pub fn to_npy(&self) -> Vec<u8> {
+        let mut header = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+            self.height,
+            self.width,
+            cpu::DATA_CHANNELS.len()
+        );
+        // The magic, the version and the header length take 10 bytes, and the data has to start aligned
+        let padded = (10 + header.len() + 1).next_multiple_of(64) - 10;
+        header.extend(std::iter::repeat_n(' ', padded - header.len() - 1));
+        header.push('\n');
+
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        self.extend_values(&mut bytes);
+        bytes
+    }
+
+    /// The data in the format with a text header, see the [module docs](self)
+    #[must_use]
+    pub fn to_header_format(&self) -> Vec<u8> {
+        let header = format!(
+            "\
+mandelbrot-data 1
+width {}
+height {}
+channels {}
+type float32 little-endian
+end_header
+",
+            self.width,
+            self.height,
+            cpu::DATA_CHANNELS.join(" ")
+        );
+        let mut bytes = header.into_bytes();
+        self.extend_values(&mut bytes);
+        bytes
+    }
+
+    fn extend_values(&self, bytes: &mut Vec<u8>) {
+        bytes.reserve(self.values.len() * 4);
+        for value in &self.values {
+            bytes.extend(value.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::floatexp::FloatExp;
+    use crate::vector2::{Vector2Big, Vector2d};
+    use crate::{CameraInfo, FractalType};
+
+    #[test]
+    fn npy_header_is_aligned() {
+        let data = RawData {
+            width: 3,
+            height: 2,
+            values: vec![1.5; 3 * 2 * 6],
+        };
+        let bytes = data.to_npy();
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'shape': (2, 3, 6)"));
+        assert!(header.ends_with('\n'));
+        assert_eq!(bytes.len(), 10 + header_len + 3 * 2 * 6 * 4);
+        assert_eq!(bytes[10 + header_len..][..4], 1.5_f32.to_le_bytes());
+    }
+
+    /// Far outside the set every pixel escapes after the same number of iterations, so the derivative can be
+    /// compared to the difference of z between neighboring pixels
+    #[test]
+    fn derivative_matches_neighbors() {
+        for fractal_type in [FractalType::Mandelbrot, FractalType::BurningShip, FractalType::Tricorn] {
+            let mut application = Application {
+                camera: CameraInfo::new(
+                    Vector2Big::from_f64(Vector2d::new(1.3, 0.9)).unwrap(),
+                    FloatExp::from_f64(1e-3),
+                    0.0,
+                ),
+                fractal_type,
+                ..Application::default()
+            };
+            let data = application.render_data(8, 8);
+            let pixel = |x: usize, y: usize| &data.values[(y * 8 + x) * 6..][..6];
+            let (left, right) = (pixel(3, 4), pixel(4, 4));
+            assert_eq!(left[5], 1.0);
+            assert_eq!(left[0].floor(), right[0].floor());
+            let difference = [right[1] - left[1], right[2] - left[2]];
+            for (difference, derivative) in difference.into_iter().zip(&left[3..5]) {
+                assert!(
+                    (difference - derivative).abs() < 0.05 * left[3].hypot(left[4]),
+                    "{fractal_type:?}: {difference} {derivative}"
+                );
+            }
+        }
+    }
+
+    /// The pixels of the exponential map don't have one size to scale the derivative by, so the data is of the
+    /// plain view instead
+    #[test]
+    fn exponential_map_is_left_out() {
+        let mut application = Application::default();
+        let plain = application.render_data(12, 8);
+        application.exponential_map = true;
+        assert_eq!(application.render_data(12, 8), plain);
+        assert!(application.exponential_map);
+    }
+
+    #[test]
+    fn perturbation_has_the_same_derivative() {
+        let camera = CameraInfo::new(
+            Vector2Big::from_f64(Vector2d::new(-0.75, 0.2)).unwrap(),
+            FloatExp::from_f64(0.05),
+            0.0,
+        );
+        let mut direct = Application {
+            camera: camera.clone(),
+            ..Application::default()
+        };
+        let mut perturbed = Application {
+            camera,
+            perturbation: true,
+            ..Application::default()
+        };
+        let direct = direct.render_data(16, 16);
+        let perturbed = perturbed.render_data(16, 16);
+        for (a, b) in direct.values.chunks_exact(6).zip(perturbed.values.chunks_exact(6)) {
+            // Pixels near the boundary can end up with a different z after many iterations in f32
+            let z_agrees = (a[1] - b[1]).hypot(a[2] - b[2]) < 0.01 * a[1].hypot(a[2]);
+            if a[5] == 1.0 && a[0].floor() == b[0].floor() && z_agrees {
+                let size = a[3].hypot(a[4]);
+                assert!((a[3] - b[3]).hypot(a[4] - b[4]) < 0.05 * size, "{a:?} {b:?}");
+            }
+        }
+    }
+
+    /// Sizes that would need gigabytes are refused before anything is rendered or written
+    #[test]
+    fn oversized_exports_are_rejected() {
+        let path = std::env::temp_dir().join(format!("rust-mandel-{}-oversized.npy", std::process::id()));
+        let mut application = Application::default();
+        for (width, height) in [(32768, 32768), (16384, 8192), (0, 100)] {
+            let error = application.export_data(&path, width, height).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!path.exists());
+        assert!(check_export_size(8192, 8192).is_ok());
+    }
+}
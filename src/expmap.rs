@@ -0,0 +1,246 @@
+//! Exponential maps: the log-polar image of a zoom around the center of the view, and rebuilding the frames of a
+//! zoom video from it.
+//!
+//! The angle around the center goes along the rows of a map, one turn over the width, and the distance from the
+//! center shrinks down the columns, starting at the zoom of the camera. Every row is `exp(TAU / width)` times
+//! closer to the center than the one above, which keeps the pixels square, so a map as tall as it is wide zooms
+//! in by a factor of `exp(TAU)`, about 535. Rendering one tall map is much faster than rendering every frame of
+//! a zoom, and any frame along the way is rebuilt from it by sampling the map in polar coordinates.
+
+use std::{
+    f64::consts::{LOG2_E, SQRT_2, TAU},
+    fs, io,
+    path::Path,
+};
+
+use eframe::wgpu;
+
+use crate::export;
+use crate::floatexp::FloatExp;
+use crate::scene::Scene;
+use crate::{Application, CameraInfo};
+
+/// A rendered exponential map
+pub struct ExpMap {
+    /// The scene the map was rendered from, the zoom of its camera is the distance at the top of the map
+    pub scene: Scene,
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed RGBA8 rows
+    pub rgba: Vec<u8>,
+}
+
+impl Application {
+    /// Render the exponential map around the center of the current view, starting at the current zoom.
+    /// `height` decides how deep the map goes, see the [module docs](self).
+    ///
+    /// The map is rendered in strips as tall as they are wide, each with the zoom at its top, so the offsets of
+    /// the pixels stay in the range of [`f32`] however deep the map goes.
+    /// [`None`] if `width` or `height` is zero
+    pub fn render_exp_map(
+        &mut self,
+        width: u32,
+        height: u32,
+        gpu: Option<(&wgpu::Device, &wgpu::Queue)>,
+    ) -> Option<ExpMap> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let camera = self.camera.clone();
+        let exponential_map = std::mem::replace(&mut self.exponential_map, true);
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for top in (0..height).step_by(width as usize) {
+            let depth = TAU * top as f64 / width as f64;
+            let zoom = camera.zoom * FloatExp::exp2(-depth * LOG2_E);
+            self.camera = CameraInfo::new(camera.center.clone(), zoom, camera.rotation);
+            rgba.extend(self.render_image(width, width.min(height - top), gpu));
+        }
+        self.camera = camera;
+        self.exponential_map = exponential_map;
+        Some(ExpMap {
+            scene: self.scene(),
+            width,
+            height,
+            rgba,
+        })
+    }
+}
+
+impl ExpMap {
+    /// Save the map as a PNG at `path`, with its scene embedded so it can be loaded again with [`ExpMap::load`]
+    ///
+    /// # Errors
+    /// If the file can't be written
+    pub fn save(&self, path: &Path) -> Result<(), png::EncodingError> {
+        export::write_png(path, self.width, self.height, &self.rgba, Some(&self.scene))
+    }
+
+    /// Load a map saved with [`ExpMap::save`]
+    ///
+    /// # Errors
+    /// If the file can't be read, or isn't an RGBA8 PNG with a scene in it
+    pub fn load(path: &Path) -> Result<Self, png::DecodingError> {
+        let invalid = |message: String| png::DecodingError::from(io::Error::new(io::ErrorKind::InvalidData, message));
+        let data = fs::read(path)?;
+        let scene = Scene::from_png(&data).map_err(|e| invalid(e.to_string()))?;
+        let mut reader = png::Decoder::new(data.as_slice()).read_info()?;
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba)?;
+        if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+            return Err(invalid("the map isn't an RGBA8 image".to_owned()));
+        }
+        rgba.truncate(info.buffer_size());
+        Ok(Self {
+            scene,
+            width: info.width,
+            height: info.height,
+            rgba,
+        })
+    }
+
+    /// The depths of the first and the last `width` by `height` frame that can be rebuilt from the map, as the
+    /// natural log of how far they are zoomed in from the top of the map. The corners of the first frame are on
+    /// the top row, and only the pixels next to the center of the last frame are past the bottom.
+    /// [`None`] if the map isn't tall enough for a single frame
+    #[must_use]
+    pub fn depth_range(&self, width: u32, height: u32) -> Option<(f64, f64)> {
+        let shorter = width.min(height) as f64;
+        let first = ((width as f64).hypot(height as f64) / shorter).ln();
+        let last = TAU * self.height as f64 / self.width as f64 - (shorter / SQRT_2).ln();
+        (first <= last).then_some((first, last))
+    }
+
+    /// The scene of the frame at `depth`
+    #[must_use]
+    pub fn frame_scene(&self, depth: f64) -> Scene {
+        let camera = &self.scene.camera;
+        let zoom = camera.zoom * FloatExp::exp2(-depth * LOG2_E);
+        Scene {
+            camera: CameraInfo::new(camera.center.clone(), zoom, camera.rotation),
+            ..self.scene.clone()
+        }
+    }
+
+    /// Rebuild the `width` by `height` frame at `depth`, as tightly packed RGBA8 rows.
+    /// Parts of the frame that are outside the map get the color of its nearest row
+    #[must_use]
+    pub fn frame(&self, depth: f64, width: u32, height: u32) -> Vec<u8> {
+        let scale = 2.0 / width.min(height) as f64;
+        let pixels_per_unit = self.width as f64 / TAU;
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            for x in 0..width {
+                // Relative to the center in units of the zoom, with the imaginary axis pointing up like the shader
+                let re = (x as f64 + 0.5 - width as f64 * 0.5) * scale;
+                let im = (height as f64 * 0.5 - y as f64 - 0.5) * scale;
+                let column = im.atan2(re).rem_euclid(TAU) * pixels_per_unit;
+                let row = (depth - re.hypot(im).ln()) * pixels_per_unit;
+                rgba.extend(self.sample(column, row));
+            }
+        }
+        rgba
+    }
+
+    /// Rebuild `frames` frames zooming in at a constant speed over the [`ExpMap::depth_range`], and save them
+    /// in `directory` as `frame_00000.png`, `frame_00001.png`, ... `progress` is called with the number of frames
+    /// done and the total after each frame
+    ///
+    /// # Errors
+    /// If the map isn't tall enough for frames of this size, or a frame can't be written
+    pub fn render_frames(
+        &self,
+        directory: &Path,
+        frames: u32,
+        width: u32,
+        height: u32,
+        mut progress: impl FnMut(u32, u32),
+    ) -> Result<(), png::EncodingError> {
+        let Some((first, last)) = self.depth_range(width, height) else {
+            let message = "the map isn't tall enough for frames of this size";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        };
+        fs::create_dir_all(directory)?;
+        for frame in 0..frames {
+            let depth = first + (last - first) * frame as f64 / (frames - 1).max(1) as f64;
+            let path = directory.join(format!("frame_{frame:05}.png"));
+            let scene = self.frame_scene(depth);
+            export::write_png(&path, width, height, &self.frame(depth, width, height), Some(&scene))?;
+            progress(frame + 1, frames);
+        }
+        Ok(())
+    }
+
+    /// The color at `column` and `row`, in pixels from the top left corner, interpolated between the nearest
+    /// pixels. The columns wrap around, and rows past the top or the bottom are clamped
+    fn sample(&self, column: f64, row: f64) -> [u8; 4] {
+        let (width, height) = (self.width as usize, self.height as usize);
+        // Pixels are sampled at their centers, like the shader does
+        let column = column - 0.5;
+        let row = (row - 0.5).clamp(0.0, (height - 1) as f64);
+        let (fx, fy) = (column - column.floor(), row - row.floor());
+        let x0 = (column.floor() as i64).rem_euclid(width as i64) as usize;
+        let x1 = (x0 + 1) % width;
+        let y0 = row as usize;
+        let y1 = (y0 + 1).min(height - 1);
+        let pixel = |x: usize, y: usize, channel: usize| self.rgba[(y * width + x) * 4 + channel] as f64;
+        std::array::from_fn(|channel| {
+            let top = lerp(pixel(x0, y0, channel), pixel(x1, y0, channel), fx);
+            let bottom = lerp(pixel(x0, y1, channel), pixel(x1, y1, channel), fx);
+            lerp(top, bottom, fy).round() as u8
+        })
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_match_direct_renders() {
+        let mut application = Application {
+            max_iter: 256,
+            ..Application::default()
+        };
+        let map = application.render_exp_map(256, 256, None).unwrap();
+        let (first, last) = map.depth_range(32, 24).unwrap();
+
+        for depth in [first, (first + last) * 0.5] {
+            let frame = map.frame(depth, 32, 24);
+            application.set_scene(map.frame_scene(depth));
+            let direct = application.render_image(32, 24, None);
+            let difference = frame
+                .iter()
+                .zip(&direct)
+                .map(|(&a, &b)| (a as f64 - b as f64).abs())
+                .sum::<f64>()
+                / frame.len() as f64;
+            assert!(difference < 8.0, "mean difference {difference} at depth {depth}");
+        }
+    }
+
+    #[test]
+    fn empty_maps_are_rejected() {
+        let mut application = Application::default();
+        assert!(application.render_exp_map(0, 256, None).is_none());
+        assert!(application.render_exp_map(256, 0, None).is_none());
+        assert!(!application.exponential_map);
+    }
+
+    #[test]
+    fn depth_range_needs_a_tall_enough_map() {
+        let map = ExpMap {
+            scene: Scene::default(),
+            width: 100,
+            height: 50,
+            rgba: vec![0; 100 * 50 * 4],
+        };
+        assert!(map.depth_range(1920, 1080).is_none());
+        let (first, last) = map.depth_range(4, 4).unwrap();
+        assert!((first - SQRT_2.ln()).abs() < 1e-12);
+        assert!((last - (TAU * 0.5 - (4.0 / SQRT_2).ln())).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,156 @@
+//! Rendering the current view offscreen at any resolution and saving it as a PNG.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    sync::Arc,
+};
+
+use eframe::{
+    egui::{Pos2, Rect, Vec2},
+    wgpu,
+};
+
+use crate::perturbation::ReferenceOrbit;
+use crate::scene::{self, Scene};
+use crate::viewport::Viewport;
+use crate::{cpu, shader, vector2::Vector2d, Application};
+
+/// The most pixels an exported image can have, as many as 8192 by 8192. The CPU renderer keeps several values
+/// for every pixel in memory, so much larger exports would need many gigabytes
+pub const MAX_EXPORT_PIXELS: u64 = 1 << 26;
+
+impl Application {
+    /// Render the current view into a `width` by `height` image, as tightly packed RGBA8 rows.
+    ///
+    /// Uses the GPU if a device and its queue are given, otherwise the much slower CPU renderer,
+    /// which works without a window or a GPU. The glitch overlay is never drawn into the image.
+    pub fn render_image(&mut self, width: u32, height: u32, gpu: Option<(&wgpu::Device, &wgpu::Queue)>) -> Vec<u8> {
+        let (uniforms, references) = self.image_uniforms(width, height);
+        if let Some((device, queue)) = gpu {
+            return shader::render_image(device, queue, &uniforms, &references, width, height);
+        }
+        cpu::render(&uniforms, &orbit_buffer(&references), width, height)
+    }
+
+    /// The uniforms and references for rendering the current view into a `width` by `height` image,
+    /// without the glitch overlay
+    pub(crate) fn image_uniforms(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> (shader::RenderCallback, Vec<Arc<ReferenceOrbit>>) {
+        let rect = Rect::from_min_size(Pos2::ZERO, Vec2::new(width as f32, height as f32));
+        let viewport = Viewport::new(rect, 1.0, self.camera.clone());
+
+        // Prepare the references for the image the same way a frame on the screen is prepared,
+        // the image can have a different shape, so the series and glitches can be different
+        self.update_reference_orbit();
+        let references = self.references();
+        let ref_offset = self.reference_callbacks(&references)[0].offset;
+        self.update_series(rect, Vector2d::new(ref_offset[0] as f64, ref_offset[1] as f64));
+        if !self.glitch_correction || self.reference_orbit.is_none() {
+            self.glitch_references.clear();
+            self.glitch_scan = None;
+        } else if !self.glitch_scan_matches(&self.glitch_scan_uniforms(rect)) {
+            self.search_glitch_references(rect);
+        }
+
+        let references = self.references();
+        let mut uniforms = self.uniforms(&viewport, &references);
+        uniforms.flags &= !16;
+        (uniforms, references)
+    }
+
+    /// Render the current view with [`Application::render_image`] and save it as a PNG at `path`,
+    /// with the [`Scene`] it shows embedded so it can be opened again with [`Scene::load`].
+    /// With [`Application::exponential_map`] set, the whole image is an [`ExpMap`](crate::expmap::ExpMap) instead
+    ///
+    /// # Errors
+    /// If the size is rejected by [`check_export_size`] or the file can't be written
+    pub fn export_image(
+        &mut self,
+        path: &Path,
+        width: u32,
+        height: u32,
+        gpu: Option<(&wgpu::Device, &wgpu::Queue)>,
+    ) -> Result<(), png::EncodingError> {
+        check_export_size(width, height)?;
+        if self.exponential_map {
+            let map = self.render_exp_map(width, height, gpu);
+            let empty = || io::Error::new(io::ErrorKind::InvalidInput, "the exponential map is empty");
+            return map.ok_or_else(empty)?.save(path);
+        }
+        let image = self.render_image(width, height, gpu);
+        write_png(path, width, height, &image, Some(&self.scene()))
+    }
+
+    /// A copy of everything that decides what is rendered, with the reference orbits that are already computed,
+    /// for exporting on another thread while this one keeps drawing
+    #[must_use]
+    pub(crate) fn exporter(&self) -> Self {
+        let mut exporter = Self {
+            exponential_map: self.exponential_map,
+            reference_orbit: self.reference_orbit.clone(),
+            series_approximation: self.series_approximation,
+            series_tolerance: self.series_tolerance,
+            series: self.series,
+            glitch_correction: self.glitch_correction,
+            glitch_references: self.glitch_references.clone(),
+            glitch_scan: self.glitch_scan,
+            ..Self::default()
+        };
+        exporter.set_scene(self.scene());
+        exporter
+    }
+}
+
+/// Check that a `width` by `height` export has at least one pixel and at most [`MAX_EXPORT_PIXELS`], before
+/// anything is allocated for it
+///
+/// # Errors
+/// If it doesn't
+pub fn check_export_size(width: u32, height: u32) -> io::Result<()> {
+    let pixels = u64::from(width) * u64::from(height);
+    if pixels == 0 || pixels > MAX_EXPORT_PIXELS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "can't export {width}x{height} pixels, the size has to be between 1 and {MAX_EXPORT_PIXELS} pixels"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The reference orbits one after another, as the CPU renderer takes them
+pub(crate) fn orbit_buffer(references: &[Arc<ReferenceOrbit>]) -> Vec<[f32; 2]> {
+    references
+        .iter()
+        .flat_map(|orbit| orbit.orbit.iter().copied())
+        .collect()
+}
+
+/// Write tightly packed RGBA8 rows to a PNG file, with `scene` in an iTXt chunk if there is one
+///
+/// # Errors
+/// If the file can't be written
+pub fn write_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    scene: Option<&Scene>,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    if let Some(scene) = scene {
+        // Before the image data, so readers find it without decoding the image
+        encoder.add_itxt_chunk(scene::PNG_KEYWORD.to_owned(), scene.to_toml())?;
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()
+}
@@ -0,0 +1,343 @@
+//! Floating point numbers with an extended exponent, for zooms beyond the range of [`f64`].
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::ops::{Add, Div, DivAssign, Mul, MulAssign, Neg, Sub};
+use std::str::FromStr;
+
+/// A floating point number stored as `mantissa * 2^exponent`.
+///
+/// The mantissa is kept in `[0.5, 1)` (or zero), so the precision is that of an [`f64`],
+/// but the exponent is an [`i32`] and can go far below the smallest [`f64`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatExp {
+    mantissa: f64,
+    exponent: i32,
+}
+
+/// The error returned when parsing a string that isn't a number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseFloatExpError;
+
+impl FloatExp {
+    pub const ZERO: Self = Self {
+        mantissa: 0.0,
+        exponent: 0,
+    };
+
+    /// `mantissa * 2^exponent`, the mantissa doesn't have to be normalized
+    #[must_use]
+    pub fn new(mantissa: f64, exponent: i32) -> Self {
+        if mantissa == 0.0 || !mantissa.is_finite() {
+            return Self { mantissa, exponent: 0 };
+        }
+        let (mantissa, shift) = frexp(mantissa);
+        Self {
+            mantissa,
+            exponent: exponent.saturating_add(shift),
+        }
+    }
+
+    #[must_use]
+    pub fn from_f64(value: f64) -> Self {
+        Self::new(value, 0)
+    }
+
+    /// The closest [`f64`], which is zero or infinite when the exponent is out of its range
+    #[must_use]
+    pub fn to_f64(self) -> f64 {
+        ldexp(self.mantissa, self.exponent)
+    }
+
+    /// The mantissa in `[0.5, 1)`, or zero
+    #[must_use]
+    pub fn mantissa(self) -> f64 {
+        self.mantissa
+    }
+
+    #[must_use]
+    pub fn exponent(self) -> i32 {
+        self.exponent
+    }
+
+    #[must_use]
+    pub fn abs(self) -> Self {
+        Self {
+            mantissa: self.mantissa.abs(),
+            exponent: self.exponent,
+        }
+    }
+
+    #[must_use]
+    pub fn log2(self) -> f64 {
+        self.mantissa.log2() + self.exponent as f64
+    }
+
+    /// `2^value`
+    #[must_use]
+    pub fn exp2(value: f64) -> Self {
+        let exponent = value.floor();
+        Self::new((value - exponent).exp2(), exponent as i32)
+    }
+}
+
+/// Split a finite, non-zero `value` into a mantissa in `[0.5, 1)` and a power of two
+fn frexp(value: f64) -> (f64, i32) {
+    let bits = value.to_bits();
+    let biased = ((bits >> 52) & 0x7ff) as i32;
+    if biased == 0 {
+        // Subnormal, scale it into the normal range first
+        let (mantissa, exponent) = frexp(value * 2.0_f64.powi(64));
+        return (mantissa, exponent - 64);
+    }
+    let mantissa = f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52));
+    (mantissa, biased - 1022)
+}
+
+/// `value * 2^exponent`, in two steps so the power of two itself can't overflow
+fn ldexp(value: f64, exponent: i32) -> f64 {
+    let exponent = exponent.clamp(-2200, 2200);
+    let half = exponent / 2;
+    value * 2.0_f64.powi(half) * 2.0_f64.powi(exponent - half)
+}
+
+impl Default for FloatExp {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl From<f64> for FloatExp {
+    fn from(value: f64) -> Self {
+        Self::from_f64(value)
+    }
+}
+
+impl FromStr for FloatExp {
+    type Err = ParseFloatExpError;
+
+    /// Parse a number like `2.5` or `1.5e-1000`, the decimal exponent can be as large as an [`i32`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // Numbers in the normal range of f64 are parsed exactly
+        if let Ok(value) = s.parse::<f64>() {
+            if value.is_normal() {
+                return Ok(Self::from_f64(value));
+            }
+        }
+
+        let (mantissa, exponent) = s.split_once(['e', 'E']).unwrap_or((s, "0"));
+        let mantissa: f64 = mantissa.parse().map_err(|_| ParseFloatExpError)?;
+        let exponent: i32 = exponent.parse().map_err(|_| ParseFloatExpError)?;
+        if !mantissa.is_finite() {
+            return Err(ParseFloatExpError);
+        }
+        Ok(Self::from_f64(mantissa) * Self::exp2(exponent as f64 * std::f64::consts::LOG2_10))
+    }
+}
+
+impl Display for FloatExp {
+    /// Print the number in scientific notation with about 12 significant digits when it's very large or small
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let log10 = self.abs().log2() * std::f64::consts::LOG10_2;
+        if self.mantissa == 0.0 || (-5.0..15.0).contains(&log10) {
+            return write!(f, "{}", self.to_f64());
+        }
+
+        let mut exponent = log10.floor();
+        let mut mantissa = ((10.0_f64.powf(log10 - exponent) * 1e11).round() / 1e11).copysign(self.mantissa);
+        if mantissa.abs() >= 10.0 {
+            mantissa /= 10.0;
+            exponent += 1.0;
+        }
+        write!(f, "{mantissa}e{exponent}")
+    }
+}
+
+impl Display for ParseFloatExpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid number")
+    }
+}
+
+impl std::error::Error for ParseFloatExpError {}
+
+impl PartialOrd for FloatExp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (*self - *other).mantissa.partial_cmp(&0.0)
+    }
+}
+
+impl Add for FloatExp {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        if self.mantissa == 0.0 {
+            return rhs;
+        }
+        if rhs.mantissa == 0.0 {
+            return self;
+        }
+        // Align the smaller number to the exponent of the larger one, unless it is too small to matter
+        let shift = i64::from(self.exponent) - i64::from(rhs.exponent);
+        if shift > 64 {
+            self
+        } else if shift < -64 {
+            rhs
+        } else if shift >= 0 {
+            Self::new(self.mantissa + ldexp(rhs.mantissa, -shift as i32), self.exponent)
+        } else {
+            Self::new(ldexp(self.mantissa, shift as i32) + rhs.mantissa, rhs.exponent)
+        }
+    }
+}
+
+impl Sub for FloatExp {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self + -rhs
+    }
+}
+
+impl Neg for FloatExp {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self {
+            mantissa: -self.mantissa,
+            exponent: self.exponent,
+        }
+    }
+}
+
+impl Mul for FloatExp {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::new(self.mantissa * rhs.mantissa, self.exponent.saturating_add(rhs.exponent))
+    }
+}
+
+impl Mul<f64> for FloatExp {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        self * Self::from_f64(rhs)
+    }
+}
+
+impl MulAssign<f64> for FloatExp {
+    fn mul_assign(&mut self, rhs: f64) {
+        *self = *self * rhs;
+    }
+}
+
+impl Div for FloatExp {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        Self::new(self.mantissa / rhs.mantissa, self.exponent.saturating_sub(rhs.exponent))
+    }
+}
+
+impl Div<f64> for FloatExp {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        self / Self::from_f64(rhs)
+    }
+}
+
+impl DivAssign<f64> for FloatExp {
+    fn div_assign(&mut self, rhs: f64) {
+        *self = *self / rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `a` and `b` differ by at most a relative `1e-10`, about the precision [`FloatExp`] is printed with
+    fn assert_close(a: FloatExp, b: FloatExp) {
+        assert!(((a - b) / b).abs().to_f64() < 1e-10, "{a} {b}");
+    }
+
+    /// Exponents far outside the range of [`f64`] are parsed into the exponent instead of becoming zero or infinity
+    #[test]
+    fn parse_extreme_exponents() {
+        let tiny: FloatExp = "1.5e-1000".parse().unwrap();
+        assert!((tiny.log2() * std::f64::consts::LOG10_2 - (1.5f64.log10() - 1000.0)).abs() < 1e-9);
+        let huge: FloatExp = "-2.5E+5000".parse().unwrap();
+        assert!(huge.mantissa() < 0.0);
+        assert!((huge.abs().log2() * std::f64::consts::LOG10_2 - (2.5f64.log10() + 5000.0)).abs() < 1e-9);
+        assert_eq!("0.25".parse::<FloatExp>().unwrap(), FloatExp::new(0.5, -1));
+        assert_eq!("0".parse::<FloatExp>().unwrap(), FloatExp::ZERO);
+
+        for invalid in ["", "e5", "1e", "1.5e-99999999999", "inf", "NaN", "x"] {
+            assert_eq!(invalid.parse::<FloatExp>(), Err(ParseFloatExpError), "{invalid}");
+        }
+    }
+
+    /// Printing a number and parsing it again gives the same number, also beyond the range of [`f64`]
+    #[test]
+    fn display_round_trip() {
+        assert_eq!(FloatExp::from_f64(0.125).to_string(), "0.125");
+        assert_eq!(FloatExp::ZERO.to_string(), "0");
+        assert_eq!("1.5e-1000".parse::<FloatExp>().unwrap().to_string(), "1.5e-1000");
+        assert_eq!(
+            "-9.99999999999e300".parse::<FloatExp>().unwrap().to_string(),
+            "-9.99999999999e300"
+        );
+
+        for value in [
+            FloatExp::from_f64(-3.75e-7),
+            FloatExp::new(0.7, -40_000),
+            FloatExp::new(-0.6, 12_345),
+        ] {
+            assert_close(value.to_string().parse().unwrap(), value);
+        }
+    }
+
+    /// Adding numbers too far apart to change each other keeps the larger one, closer ones are aligned exactly
+    #[test]
+    fn add_and_sub_different_exponents() {
+        let one = FloatExp::from_f64(1.0);
+        let tiny = FloatExp::new(0.5, -1000);
+        assert_eq!(one + tiny, one);
+        assert_eq!(tiny + one, one);
+        assert_eq!(one - tiny, one);
+        assert_eq!(tiny - one, -one);
+        assert_eq!(tiny + tiny, FloatExp::new(0.5, -999));
+        assert_eq!(tiny - tiny, FloatExp::ZERO);
+
+        let small = FloatExp::exp2(-40.0);
+        assert_eq!(one + small, FloatExp::from_f64(1.0 + 2.0_f64.powi(-40)));
+        assert_eq!(small - one, FloatExp::from_f64(2.0_f64.powi(-40) - 1.0));
+        assert_close(
+            FloatExp::new(0.75, -5000) + FloatExp::new(0.5, -5010),
+            FloatExp::new(0.75 + 0.5 / 1024.0, -5000),
+        );
+    }
+
+    /// Numbers are ordered by value, whatever their exponents
+    #[test]
+    fn ordering() {
+        let values = [
+            FloatExp::new(-0.5, 2000),
+            FloatExp::from_f64(-1.0),
+            FloatExp::new(-0.5, -2000),
+            FloatExp::ZERO,
+            FloatExp::new(0.5, -2000),
+            FloatExp::new(0.75, -2000),
+            FloatExp::from_f64(1.0),
+            FloatExp::new(0.5, 2000),
+        ];
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(a.partial_cmp(b), Some(i.cmp(&j)), "{a} {b}");
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Undo and redo of the scene, with continuous gestures merged into single steps.
+
+use crate::scene::Scene;
+use crate::Application;
+
+/// How long the scene has to stay unchanged before a change becomes a step, in seconds.
+/// Drags, scroll bursts and dragged sliders change the scene every frame, this makes each of them one step
+const SETTLE_TIME: f64 = 0.5;
+
+/// The most steps that can be undone, the oldest are forgotten first
+const MAX_STEPS: usize = 256;
+
+/// The scenes before and after the current one
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    undo: Vec<Scene>,
+    redo: Vec<Scene>,
+    /// The scene at the end of the last step, [`None`] before the first update
+    settled: Option<Scene>,
+    /// The scene of the last update
+    latest: Option<Scene>,
+    /// The time the scene last changed
+    changed_at: f64,
+}
+
+impl History {
+    /// Follow the scene of every frame, at `time` in seconds.
+    /// A change becomes a step once it has settled, and `holding` (a mouse button) is false
+    pub fn update(&mut self, scene: &Scene, time: f64, holding: bool) {
+        if self.latest.as_ref() != Some(scene) {
+            self.latest = Some(scene.clone());
+            self.changed_at = time;
+        }
+        if self.settled.is_none() {
+            self.settled = Some(scene.clone());
+        }
+        if !holding && time - self.changed_at >= SETTLE_TIME {
+            self.commit();
+        }
+    }
+
+    /// Make the change since the last step a step of its own
+    fn commit(&mut self) {
+        if self.latest == self.settled {
+            return;
+        }
+        if let Some(previous) = std::mem::replace(&mut self.settled, self.latest.clone()) {
+            self.undo.push(previous);
+            if self.undo.len() > MAX_STEPS {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+    }
+
+    /// Go back a step from `current`, returns the scene to show.
+    /// A change that hasn't settled yet is undone as a whole
+    pub fn undo(&mut self, current: &Scene) -> Option<Scene> {
+        self.latest = Some(current.clone());
+        self.commit();
+        let scene = self.undo.pop()?;
+        self.redo.extend(self.settled.replace(scene.clone()));
+        self.latest = Some(scene.clone());
+        Some(scene)
+    }
+
+    /// Go forward a step from `current`, returns the scene to show.
+    /// Nothing can be redone after a new change
+    pub fn redo(&mut self, current: &Scene) -> Option<Scene> {
+        self.latest = Some(current.clone());
+        self.commit();
+        let scene = self.redo.pop()?;
+        self.undo.extend(self.settled.replace(scene.clone()));
+        self.latest = Some(scene.clone());
+        Some(scene)
+    }
+
+    #[must_use]
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.latest != self.settled
+    }
+
+    #[must_use]
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty() && self.latest == self.settled
+    }
+}
+
+impl Application {
+    /// Go back to the scene before the last change
+    pub fn undo(&mut self) {
+        if let Some(scene) = self.history.undo(&self.scene()) {
+            self.set_scene(scene);
+        }
+    }
+
+    /// Go forward to the scene of the last undone change
+    pub fn redo(&mut self) {
+        if let Some(scene) = self.history.redo(&self.scene()) {
+            self.set_scene(scene);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene(max_iter: i32) -> Scene {
+        Scene {
+            max_iter,
+            ..Scene::default()
+        }
+    }
+
+    #[test]
+    fn gestures_are_merged() {
+        let mut history = History::default();
+        history.update(&scene(100), 0.0, false);
+        // A drag that changes the scene every frame, and is held still for a while before letting go
+        for (i, time) in [0.1, 0.2, 0.3, 1.0, 1.5].into_iter().enumerate() {
+            history.update(&scene(200 + i32::try_from(i).unwrap()), time, true);
+        }
+        history.update(&scene(204), 1.6, true);
+        history.update(&scene(204), 2.5, false);
+        assert!(!history.can_redo());
+
+        assert_eq!(history.undo(&scene(204)).unwrap().max_iter, 100);
+        assert!(!history.can_undo());
+        assert!(history.can_redo());
+        assert_eq!(history.redo(&scene(100)).unwrap().max_iter, 204);
+        assert!(history.redo(&scene(204)).is_none());
+    }
+
+    #[test]
+    fn unsettled_changes_are_undone() {
+        let mut history = History::default();
+        history.update(&scene(100), 0.0, false);
+        history.update(&scene(200), 1.0, false);
+        history.update(&scene(300), 1.1, false);
+        assert_eq!(history.undo(&scene(300)).unwrap().max_iter, 100);
+
+        // A new change forgets what could be redone
+        history.update(&scene(400), 2.0, false);
+        assert!(!history.can_redo());
+        assert!(history.redo(&scene(400)).is_none());
+        assert_eq!(history.undo(&scene(400)).unwrap().max_iter, 100);
+    }
+}
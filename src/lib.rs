@@ -0,0 +1,900 @@
+#![warn(clippy::pedantic)]
+#![allow(
+    clippy::missing_panics_doc,
+    clippy::cast_lossless,
+    clippy::float_cmp,
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss,
+    clippy::pub_underscore_fields
+)]
+
+pub mod animation;
+pub mod bigfloat;
+pub mod bookmarks;
+pub mod cpu;
+pub mod data;
+pub mod expmap;
+pub mod export;
+pub mod floatexp;
+pub mod history;
+pub mod persistence;
+pub mod perturbation;
+pub mod scene;
+pub mod shader;
+pub mod ui;
+pub mod vector2;
+pub mod viewport;
+
+use std::sync::Arc;
+
+use eframe::{
+    egui::{self, Rect, Sense},
+    egui_wgpu,
+};
+
+use bigfloat::BigFloat;
+use floatexp::FloatExp;
+use perturbation::{ReferenceOrbit, ReferenceParams, SeriesApproximation};
+use vector2::{Vector2Big, Vector2d};
+use viewport::Viewport;
+
+/// Struct containing all application state info.
+///
+/// The public fields are the settings that decide what is rendered, the rest is derived from them.
+#[allow(clippy::struct_excessive_bools)]
+pub struct Application {
+    pub camera: CameraInfo,
+    pub max_iter: i32,
+    pub exponent: f32,
+    pub fractal_type: FractalType,
+    pub shading_type: ShadingType,
+    pub color_scheme: ColorScheme,
+    pub palette_speed: f32,
+    /// How far from the set the distance shading darkens the colors, in pixels
+    pub boundary_width: f32,
+    pub light: Light,
+    pub trap: OrbitTrap,
+    pub averages: Averages,
+    /// Render the Julia set of `julia_pos` instead
+    pub julia: bool,
+    pub julia_pos: Vector2d,
+    /// Render relative to a high precision reference orbit where the fractal type and exponent support it
+    pub perturbation: bool,
+    /// Render the exponential map around the center instead, see [`expmap`]
+    pub exponential_map: bool,
+    reference_orbit: Option<Arc<ReferenceOrbit>>,
+    series_approximation: bool,
+    series_tolerance: f64,
+    series: Option<SeriesApproximation>,
+    glitch_correction: bool,
+    /// Extra references inside regions where the main reference is glitched
+    glitch_references: Vec<Arc<ReferenceOrbit>>,
+    /// The uniforms the glitch references were searched with, see [`Application::glitch_scan_uniforms`]
+    glitch_scan: Option<shader::RenderCallback>,
+    /// The glitch scan uniforms of the previous frame, to tell if the view is still moving
+    glitch_scan_last_frame: Option<shader::RenderCallback>,
+    show_glitches: bool,
+    location_input: ui::LocationInput,
+    export_input: ui::ExportInput,
+    scene_input: ui::SceneInput,
+    bookmarks: Vec<bookmarks::Bookmark>,
+    bookmarks_input: ui::BookmarksInput,
+    history: history::History,
+    /// Why the bookmarks or settings of the last run couldn't be restored, shown until it's dismissed
+    restore_error: Option<String>,
+}
+
+/// Contains a cosine color palette for the shader
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorScheme {
+    pub a: [f32; 3],
+    pub b: [f32; 3],
+    pub c: [f32; 3],
+    pub d: [f32; 3],
+}
+
+#[rustfmt::skip]
+impl ColorScheme {
+    #[must_use]
+    pub const fn new(a: [f32; 3], b: [f32; 3], c: [f32; 3], d: [f32; 3]) -> Self {
+        Self { a, b, c, d }
+    }
+    // A few color palettes from here: https://iquilezles.org/articles/palettes/
+    pub const RAINBOW: Self = Self::new([0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [1.0, 1.0, 1.0], [0.00, 0.33, 0.67]);
+    pub const EARTH: Self = Self::new([0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [1.0, 1.0, 1.0], [0.00, 0.10, 0.20]);
+    pub const SKY: Self = Self::new([0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [1.0, 1.0, 1.0], [0.30, 0.20, 0.20]);
+    pub const MIDDAY: Self = Self::new([0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [1.0, 1.0, 0.5], [0.80, 0.90, 0.30]);
+    pub const MIDNIGHTAMBER: Self = Self::new([0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [1.0, 0.7, 0.4], [0.00, 0.15, 0.20]);
+    pub const SUNSET: Self = Self::new([0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [2.0, 1.0, 0.0], [0.50, 0.20, 0.25]);
+    pub const CRIMSON: Self = Self::new([0.8, 0.5, 0.4], [0.2, 0.4, 0.2], [2.0, 1.0, 1.0], [0.00, 0.25, 0.25]);
+    pub const OCEAN: Self = Self::new([0.250, 0.500, 0.500], [0.198, 0.250, 0.250], [1.000, 1.000, 1.000], [0.900, 0.650, 0.800]);
+
+    /// Every palette with its name, in the order the settings panel shows them
+    pub const PRESETS: [(&'static str, Self); 8] = [
+        ("Earth", Self::EARTH),
+        ("Sky", Self::SKY),
+        ("Crimson", Self::CRIMSON),
+        ("Midnight Amber", Self::MIDNIGHTAMBER),
+        ("Rainbow", Self::RAINBOW),
+        ("Sunset", Self::SUNSET),
+        ("Midday", Self::MIDDAY),
+        ("Ocean", Self::OCEAN),
+    ];
+}
+
+/// The light of the lighting shading, which lights the fractal as a surface falling away from the set
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Light {
+    /// The direction the light comes from, counterclockwise from the right of the screen in radians
+    pub angle: f32,
+    /// How high the light is above the screen, 0 is level with it and 1 is at 45 degrees
+    pub height: f32,
+    /// The strength of the highlights
+    pub specular: f32,
+    /// How much of the palette color is kept, 0 lights the fractal in gray
+    pub blend: f32,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            angle: std::f32::consts::FRAC_PI_4,
+            height: 1.0,
+            specular: 0.4,
+            blend: 1.0,
+        }
+    }
+}
+
+impl Light {
+    /// The unit vector pointing at the light, with z out of the screen
+    #[must_use]
+    pub fn direction(&self) -> [f32; 3] {
+        let length = 1.0_f32.hypot(self.height);
+        [
+            self.angle.cos() / length,
+            self.angle.sin() / length,
+            self.height / length,
+        ]
+    }
+}
+
+/// The shape of an orbit trap, the orbits of the pixels are colored by how close they come to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TrapShape {
+    Point,
+    /// A line through the center of the trap, along its angle
+    Line,
+    /// Two lines crossing at the center of the trap, one along its angle
+    Cross,
+    /// A circle around the center of the trap, as big as its size
+    Circle,
+    /// The arms of a cross as wide as the size of the trap. Pixels that never come that close get the smooth
+    /// coloring
+    Stalks,
+}
+
+impl TrapShape {
+    /// Every trap shape with the name used in scene files and on the command line
+    pub const NAMES: [(&'static str, Self); 5] = [
+        ("point", Self::Point),
+        ("line", Self::Line),
+        ("cross", Self::Cross),
+        ("circle", Self::Circle),
+        ("stalks", Self::Stalks),
+    ];
+}
+
+/// What the orbit trap coloring passes to the palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TrapColoring {
+    /// The closest distance to the trap, divided by its size
+    Distance,
+    /// The iteration the orbit came closest at, times the palette speed
+    Iteration,
+}
+
+impl TrapColoring {
+    /// Every trap coloring with the name used in scene files and on the command line
+    pub const NAMES: [(&'static str, Self); 2] = [("distance", Self::Distance), ("iteration", Self::Iteration)];
+}
+
+/// The orbit trap of the orbit trap shading, in the plane of z
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrbitTrap {
+    pub shape: TrapShape,
+    pub center: Vector2d,
+    /// The direction of the lines, counterclockwise from the real axis in radians
+    pub angle: f32,
+    /// The radius of the circle and the width of the stalks, and the distance the palette cycles over
+    pub size: f32,
+    pub coloring: TrapColoring,
+}
+
+impl Default for OrbitTrap {
+    fn default() -> Self {
+        Self {
+            shape: TrapShape::Cross,
+            center: Vector2d::default(),
+            angle: 0.0,
+            size: 0.25,
+            coloring: TrapColoring::Distance,
+        }
+    }
+}
+
+/// The parameters of the average colorings. Each of them adds its average times its strength to the smooth
+/// iteration count, so a strength of 1 moves through the whole palette
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Averages {
+    /// How many stripes the stripe average has per turn around the origin
+    pub stripe_frequency: f32,
+    pub stripe_strength: f32,
+    pub tia_strength: f32,
+    pub curvature_strength: f32,
+}
+
+impl Default for Averages {
+    fn default() -> Self {
+        Self {
+            stripe_frequency: 5.0,
+            stripe_strength: 1.0,
+            tia_strength: 1.0,
+            curvature_strength: 1.0,
+        }
+    }
+}
+
+impl Averages {
+    /// The strength of the average coloring of `shading_type`, zero if it isn't one
+    #[must_use]
+    pub fn strength(&self, shading_type: ShadingType) -> f32 {
+        match shading_type {
+            ShadingType::Stripe => self.stripe_strength,
+            ShadingType::TriangleInequality => self.tia_strength,
+            ShadingType::Curvature => self.curvature_strength,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The size of the handle for dragging the orbit trap, in points
+const TRAP_HANDLE_SIZE: f32 = 12.0;
+
+/// The longer side of the low resolution render that is searched for glitches, in pixels
+const GLITCH_SCAN_SIZE: u32 = 96;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ShadingType {
+    Normal,
+    Smooth,
+    /// Smooth, darkened by the estimated distance to the set, so thin filaments show up as lines
+    Distance,
+    /// Smooth, lit by a [`Light`] with the normals from the derivative
+    Lighting,
+    /// Colored by how close the orbits come to an [`OrbitTrap`], inside the set too
+    OrbitTrap,
+    /// Smooth, shifted by the average of stripes around the origin over the orbit, see [`Averages`]
+    Stripe,
+    /// Smooth, shifted by the triangle inequality average, where each `|z|` is between the smallest and the
+    /// biggest it can be
+    TriangleInequality,
+    /// Smooth, shifted by the average of how sharply the orbit turns
+    Curvature,
+    /// Smooth, spread evenly over the palette by the histogram of the frame instead of the palette speed
+    Histogram,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum FractalType {
+    Mandelbrot,
+    BurningShip,
+    Tricorn,
+}
+
+impl ShadingType {
+    /// Every shading type with the name used in scene files and on the command line
+    pub const NAMES: [(&'static str, Self); 9] = [
+        ("normal", Self::Normal),
+        ("smooth", Self::Smooth),
+        ("distance", Self::Distance),
+        ("lighting", Self::Lighting),
+        ("orbit-trap", Self::OrbitTrap),
+        ("stripe", Self::Stripe),
+        ("tia", Self::TriangleInequality),
+        ("curvature", Self::Curvature),
+        ("histogram", Self::Histogram),
+    ];
+
+    /// Whether the shading works for the fractal. The distance estimate and the lighting need a conformal
+    /// iteration, which the Burning Ship and the Tricorn only are for Julia sets, and an exponent above one
+    #[must_use]
+    pub fn is_supported(self, fractal_type: FractalType, exponent: f32, julia: bool) -> bool {
+        match self {
+            Self::Normal
+            | Self::Smooth
+            | Self::OrbitTrap
+            | Self::Stripe
+            | Self::TriangleInequality
+            | Self::Curvature
+            | Self::Histogram => true,
+            Self::Distance | Self::Lighting => exponent > 1.0 && (fractal_type == FractalType::Mandelbrot || julia),
+        }
+    }
+}
+
+impl FractalType {
+    /// Every fractal type with the name used in scene files and on the command line
+    pub const NAMES: [(&'static str, Self); 3] = [
+        ("mandelbrot", Self::Mandelbrot),
+        ("burning-ship", Self::BurningShip),
+        ("tricorn", Self::Tricorn),
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraInfo {
+    /// The point in the center of the screen.
+    /// Stored with arbitrary precision, so the camera can be moved at zooms far beyond [`f64`]
+    pub center: Vector2Big,
+    /// Half the height (or width) of the view, with an extended exponent for zooms beyond [`f64`]
+    pub zoom: FloatExp,
+    /// Counterclockwise rotation of the view in radians
+    pub rotation: f64,
+}
+
+impl Default for CameraInfo {
+    fn default() -> Self {
+        Self {
+            center: Vector2Big::default(),
+            zoom: FloatExp::from_f64(2.1),
+            rotation: 0.0,
+        }
+    }
+}
+
+impl CameraInfo {
+    /// A camera looking at `center`, with the precision of `center` raised to what `zoom` needs
+    #[must_use]
+    pub fn new(center: Vector2Big, zoom: FloatExp, rotation: f64) -> Self {
+        let precision = center.precision().max(BigFloat::precision_for(zoom));
+        Self {
+            center: center.with_precision(precision),
+            zoom,
+            rotation,
+        }
+    }
+
+    /// A camera looking at the point with the decimal coordinates `re` and `im`, keeping every digit and
+    /// parsed with the precision `zoom` needs
+    ///
+    /// # Errors
+    /// If either coordinate isn't a decimal number
+    pub fn parse(re: &str, im: &str, zoom: FloatExp, rotation: f64) -> Result<Self, bigfloat::ParseBigFloatError> {
+        let center = Vector2Big::parse_with_precision(re, im, BigFloat::precision_for(zoom))?;
+        Ok(Self::new(center, zoom, rotation))
+    }
+
+    /// Move the camera by `offset` times the zoom,
+    /// raising the precision of the center if the current zoom needs more
+    pub fn translate(&mut self, offset: Vector2d) {
+        let precision = BigFloat::precision_for(self.zoom).max(self.center.precision());
+        let offset = Vector2Big::new(
+            BigFloat::from_float_exp(FloatExp::from_f64(offset.x) * self.zoom),
+            BigFloat::from_float_exp(FloatExp::from_f64(offset.y) * self.zoom),
+        );
+        // Change the precision first, the sum is only as precise as the center
+        self.center = self.center.clone().with_precision(precision) + offset;
+    }
+}
+
+/// The initial settings, without the shaders that [`Application::new`] sets up.
+/// This is enough for rendering on the CPU with [`Application::render_image`].
+impl Default for Application {
+    fn default() -> Self {
+        Self {
+            // Setup the initial settings
+            camera: CameraInfo::default(),
+            max_iter: 1024,
+            exponent: 2.0,
+            fractal_type: FractalType::Mandelbrot,
+            shading_type: ShadingType::Smooth,
+            color_scheme: ColorScheme::MIDNIGHTAMBER,
+            palette_speed: 0.05,
+            boundary_width: 1.0,
+            light: Light::default(),
+            trap: OrbitTrap::default(),
+            averages: Averages::default(),
+            julia: false,
+            julia_pos: Vector2d::default(),
+            perturbation: false,
+            exponential_map: false,
+            reference_orbit: None,
+            series_approximation: true,
+            series_tolerance: 1e-4,
+            series: None,
+            glitch_correction: true,
+            glitch_references: Vec::new(),
+            glitch_scan: None,
+            glitch_scan_last_frame: None,
+            show_glitches: false,
+            location_input: ui::LocationInput::from_camera(&CameraInfo::default()),
+            export_input: ui::ExportInput::default(),
+            scene_input: ui::SceneInput::default(),
+            bookmarks: Vec::new(),
+            bookmarks_input: ui::BookmarksInput::default(),
+            history: history::History::default(),
+            restore_error: None,
+        }
+    }
+}
+
+impl Application {
+    #[must_use]
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        // Get the WGPU render state
+        let wgpu_render_state = cc
+            .wgpu_render_state
+            .as_ref()
+            .expect("You need to run eframe with the wgpu backend");
+
+        // compile and link the shader program
+        shader::init(wgpu_render_state);
+
+        // Restore the bookmarks and settings of the last run, anything that can't be read is left at the default
+        let mut application = Self::default();
+        let Some(storage) = cc.storage else {
+            return application;
+        };
+        let mut errors = Vec::new();
+        if let Some(text) = storage.get_string(bookmarks::STORAGE_KEY) {
+            match bookmarks::from_text(&text) {
+                Ok(bookmarks) => application.bookmarks = bookmarks,
+                Err(error) => errors.push(format!("bookmarks: {error}")),
+            }
+        }
+        if let Some(text) = storage.get_string(persistence::STORAGE_KEY) {
+            if let Err(error) = application.restore_settings(&text) {
+                errors.push(format!("settings: {error}"));
+            }
+        }
+        if !errors.is_empty() {
+            application.restore_error = Some(format!(
+                "Couldn't restore the last run, using the defaults for the {}",
+                errors.join(", ")
+            ));
+        }
+        application
+    }
+
+    /// Whether the current frame should be rendered with perturbation
+    fn use_perturbation(&self) -> bool {
+        // A reference orbit needs an exact Julia position
+        let julia_is_finite = !self.julia || (self.julia_pos.x.is_finite() && self.julia_pos.y.is_finite());
+        self.perturbation && perturbation::is_supported(self.fractal_type, self.exponent) && julia_is_finite
+    }
+
+    /// Whether the current frame should be iterated with double-float arithmetic on the GPU.
+    ///
+    /// Plain [`f32`] runs out of precision for the pixel spacing at around this zoom. Double-floats are much
+    /// slower, so they are only used when needed, and they only support integer exponents.
+    fn use_double_float(&self) -> bool {
+        !self.use_perturbation()
+            && self.camera.zoom < FloatExp::from_f64(1e-4)
+            && self.exponent >= 1.0
+            && self.exponent.fract() == 0.0
+    }
+
+    /// Whether the perturbation deltas should be iterated with an extended exponent.
+    ///
+    /// The pixel spacing gets close to the smallest normal [`f32`] at around this zoom, and the deltas would
+    /// lose their precision or become zero. Only the first iterations run with the extended exponent,
+    /// until the deltas are big enough for [`f32`].
+    fn use_float_exp(&self) -> bool {
+        self.use_perturbation() && self.camera.zoom < FloatExp::from_f64(1e-30)
+    }
+
+    /// Whether the derivative has to be tracked for the shading of the current frame
+    fn use_derivative(&self) -> bool {
+        matches!(self.shading_type, ShadingType::Distance | ShadingType::Lighting)
+            && self
+                .shading_type
+                .is_supported(self.fractal_type, self.exponent, self.julia)
+    }
+
+    /// Whether the closest approach to the orbit trap has to be tracked for the shading of the current frame
+    fn use_trap(&self) -> bool {
+        self.shading_type == ShadingType::OrbitTrap
+    }
+
+    /// Whether the shading needs every iteration of the orbit, so the series approximation can't skip any
+    fn needs_whole_orbit(&self) -> bool {
+        matches!(
+            self.shading_type,
+            ShadingType::OrbitTrap | ShadingType::Stripe | ShadingType::TriangleInequality | ShadingType::Curvature
+        )
+    }
+
+    /// Recompute the reference orbit if the parameters it depends on have changed,
+    /// or the camera moved too far away from it
+    fn update_reference_orbit(&mut self) {
+        if !self.use_perturbation() {
+            self.reference_orbit = None;
+            return;
+        }
+
+        let params = ReferenceParams {
+            max_iter: self.max_iter,
+            exponent: self.exponent,
+            fractal_type: self.fractal_type,
+            julia: self.julia.then_some(self.julia_pos),
+        };
+        let center = self.camera.center.clone();
+        let precision = BigFloat::precision_for(self.camera.zoom);
+        // Keep the old reference while it is still on screen, so panning doesn't recompute it every frame
+        if self.reference_orbit.as_ref().is_some_and(|orbit| {
+            let offset = orbit.offset_to(&center).to_float_exp();
+            orbit.params == params
+                && orbit.center.precision() >= precision
+                && offset.x.abs() <= self.camera.zoom
+                && offset.y.abs() <= self.camera.zoom
+        }) {
+            return;
+        }
+        self.reference_orbit = ReferenceOrbit::new(center.with_precision(precision), params).map(Arc::new);
+        self.series = None;
+        self.glitch_references.clear();
+        self.glitch_scan = None;
+    }
+
+    /// Recompute the series approximation if the reference, the view size or the tolerance changed
+    fn update_series(&mut self, rect: Rect, ref_offset: Vector2d) {
+        let Some(orbit) = self.reference_orbit.as_ref().filter(|_| {
+            self.series_approximation && perturbation::series_is_supported(self.fractal_type, self.exponent)
+        }) else {
+            self.series = None;
+            return;
+        };
+
+        // The largest offset of a pixel from the reference, the corners are this far from the center
+        let corner = rect.size().length() / rect.width().min(rect.height());
+        let offset = (ref_offset.x * ref_offset.x + ref_offset.y * ref_offset.y).sqrt();
+        let radius = self.camera.zoom * (corner as f64 + offset);
+        if self
+            .series
+            .is_some_and(|series| series.radius == radius && series.tolerance == self.series_tolerance)
+        {
+            return;
+        }
+        self.series = Some(SeriesApproximation::new(orbit, radius, self.series_tolerance));
+    }
+
+    /// The main reference orbit followed by the glitch references, in the order the shader tries them
+    fn references(&self) -> Vec<Arc<ReferenceOrbit>> {
+        self.reference_orbit
+            .iter()
+            .chain(&self.glitch_references)
+            .take(shader::MAX_REFERENCES)
+            .cloned()
+            .collect()
+    }
+
+    /// Where each of `references` is stored in the orbit buffer and where it is on the screen
+    fn reference_callbacks(
+        &self,
+        references: &[Arc<ReferenceOrbit>],
+    ) -> [shader::ReferenceCallback; shader::MAX_REFERENCES] {
+        let mut callbacks = [shader::ReferenceCallback::default(); shader::MAX_REFERENCES];
+        let center = &self.camera.center;
+        let mut start = 0;
+        for (callback, orbit) in callbacks.iter_mut().zip(references) {
+            // The reference is usually not exactly in the center of the screen.
+            // Sent relative to the zoom, because the offset itself can be too small for an f32
+            let offset = orbit.offset_to(center).to_float_exp();
+            *callback = shader::ReferenceCallback {
+                offset: [
+                    (offset.x / self.camera.zoom).to_f64() as f32,
+                    (offset.y / self.camera.zoom).to_f64() as f32,
+                ],
+                start: start as u32,
+                len: orbit.len() as u32,
+            };
+            start += orbit.len();
+        }
+        callbacks
+    }
+
+    /// The uniforms for rendering `viewport` with `references`
+    fn uniforms(&self, viewport: &Viewport, references: &[Arc<ReferenceOrbit>]) -> shader::RenderCallback {
+        let center = viewport.camera.center.to_f64();
+        shader::RenderCallback {
+            center: {
+                let [x_hi, x_lo] = shader::split_f64(center.x);
+                let [y_hi, y_lo] = shader::split_f64(center.y);
+                [x_hi, x_lo, y_hi, y_lo]
+            },
+            zoom: shader::split_f64(viewport.camera.zoom.mantissa()),
+            zoom_exp: viewport.camera.zoom.exponent(),
+            resolution: viewport.resolution(),
+            offset: viewport.offset(),
+            max_iter: self.max_iter,
+            exponent: self.exponent,
+            fractal_type: self.fractal_type as u32,
+            shading_type: self.shading_type as u32,
+            one: 1.0,
+            color_scheme: self.color_scheme.into(),
+            palette_speed: self.palette_speed,
+            flags: (self.julia as u32)
+                | ((self.use_perturbation() as u32) << 1)
+                | ((self.use_double_float() as u32) << 2)
+                | ((self.use_float_exp() as u32) << 3)
+                | ((self.show_glitches as u32) << 4)
+                | ((self.exponential_map as u32) << 5)
+                | ((self.use_derivative() as u32) << 6)
+                | ((self.use_trap() as u32) << 7),
+            julia_pos: (self.julia_pos.x as f32, self.julia_pos.y as f32).into(),
+            ref_count: references.len() as u32,
+            glitch_tolerance: perturbation::GLITCH_TOLERANCE * perturbation::GLITCH_TOLERANCE,
+            series_skip: self
+                .series
+                .filter(|_| !self.needs_whole_orbit())
+                .map_or(0, |series| series.skip as u32),
+            _p0: 0,
+            series: self.series.map_or([[0.0; 4]; 3], |series| {
+                series.coefficients.map(shader::pack_complex_exp)
+            }),
+            references: self.reference_callbacks(references),
+            rotation: viewport.rotation(),
+            boundary_width: self.boundary_width,
+            light_blend: self.light.blend,
+            light: {
+                let [x, y, z] = self.light.direction();
+                [x, y, z, self.light.specular]
+            },
+            trap: {
+                let (sin, cos) = self.trap.angle.sin_cos();
+                [self.trap.center.x as f32, self.trap.center.y as f32, cos, sin]
+            },
+            trap_size: self.trap.size,
+            trap_shape: self.trap.shape as u32,
+            trap_coloring: self.trap.coloring as u32,
+            stripe_frequency: self.averages.stripe_frequency,
+            average_strength: self.averages.strength(self.shading_type),
+            _p2: 0.0,
+            _p3: [0.0; 2],
+        }
+    }
+
+    /// The uniforms for finding glitches with only the main reference, at a low resolution with the same aspect
+    /// ratio as `rect`. Everything that doesn't change which pixels are glitched is left out, so they can be
+    /// compared to tell if the glitch references have to be searched again.
+    ///
+    /// The exponential map is scanned as the normal view, which covers the circle at the top of the map, and the
+    /// derivative and the orbit trap aren't tracked.
+    fn glitch_scan_uniforms(&self, rect: Rect) -> shader::RenderCallback {
+        let references: Vec<_> = self.reference_orbit.iter().cloned().collect();
+        let uniforms = self.uniforms(&self.glitch_scan_viewport(rect), &references);
+        shader::RenderCallback {
+            flags: uniforms.flags & !(16 | 32 | 64 | 128),
+            color_scheme: ColorScheme::RAINBOW.into(),
+            palette_speed: 0.0,
+            shading_type: ShadingType::Normal as u32,
+            boundary_width: 0.0,
+            light_blend: 0.0,
+            light: [0.0; 4],
+            trap: [0.0; 4],
+            trap_size: 0.0,
+            trap_shape: 0,
+            trap_coloring: 0,
+            stripe_frequency: 0.0,
+            average_strength: 0.0,
+            ..uniforms
+        }
+    }
+
+    /// The low resolution view that is searched for glitches, with one point per pixel
+    fn glitch_scan_viewport(&self, rect: Rect) -> Viewport {
+        let scale = GLITCH_SCAN_SIZE as f32 / rect.width().max(rect.height());
+        let size = (rect.size() * scale).round().max(egui::Vec2::splat(1.0));
+        Viewport::new(Rect::from_min_size(egui::Pos2::ZERO, size), 1.0, self.camera.clone())
+    }
+
+    /// Search for glitched regions and place an extra reference in each of them, until no pixel is glitched or
+    /// there is no room for more references.
+    ///
+    /// Searching renders the view on the CPU, so it waits until the view stops moving.
+    fn update_glitch_references(&mut self, rect: Rect, ctx: &egui::Context) {
+        if !self.glitch_correction || self.reference_orbit.is_none() {
+            self.glitch_references.clear();
+            self.glitch_scan = None;
+            return;
+        }
+
+        let uniforms = self.glitch_scan_uniforms(rect);
+        let key = bytemuck::bytes_of(&uniforms);
+        let last_frame = self.glitch_scan_last_frame.replace(uniforms);
+        if self.glitch_scan_matches(&uniforms) {
+            return;
+        }
+        if last_frame.as_ref().is_none_or(|last| bytemuck::bytes_of(last) != key) {
+            // Still moving, check again next frame
+            ctx.request_repaint();
+            return;
+        }
+        self.search_glitch_references(rect);
+    }
+
+    /// Whether the glitch references were searched with the glitch scan `uniforms`
+    fn glitch_scan_matches(&self, uniforms: &shader::RenderCallback) -> bool {
+        self.glitch_scan
+            .as_ref()
+            .is_some_and(|scan| bytemuck::bytes_of(scan) == bytemuck::bytes_of(uniforms))
+    }
+
+    /// Replace the glitch references with new ones found in a low resolution render of the view in `rect`
+    fn search_glitch_references(&mut self, rect: Rect) {
+        let uniforms = self.glitch_scan_uniforms(rect);
+        let viewport = self.glitch_scan_viewport(rect);
+        let [width, height] = uniforms.resolution.map(|v| v as u32);
+        let mut mask = vec![true; width as usize * height as usize];
+        self.glitch_references.clear();
+        loop {
+            let references = self.references();
+            let orbits: Vec<[f32; 2]> = references
+                .iter()
+                .flat_map(|orbit| orbit.orbit.iter().copied())
+                .collect();
+            let scan = shader::RenderCallback {
+                ref_count: references.len() as u32,
+                references: self.reference_callbacks(&references),
+                ..uniforms
+            };
+            cpu::update_glitch_mask(&scan, &orbits, width, height, &mut mask);
+            if references.len() == shader::MAX_REFERENCES {
+                break;
+            }
+            let Some(pixel) = perturbation::glitch_center(&mask, width as usize) else {
+                break;
+            };
+
+            // Pixels are sampled at their centers, like the shader does
+            let pixel = egui::Pos2::new(
+                (pixel % width as usize) as f32 + 0.5,
+                (pixel / width as usize) as f32 + 0.5,
+            );
+            let center = viewport.screen_to_complex(pixel);
+            let Some(orbit) = ReferenceOrbit::new(center, references[0].params) else {
+                break;
+            };
+            self.glitch_references.push(Arc::new(orbit));
+        }
+        self.glitch_scan = Some(uniforms);
+    }
+
+    /// Custom WGPU shader painting and input processing
+    fn custom_painting(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let viewport = self.inputs(ui, ctx);
+        let rect = viewport.rect;
+        self.update_reference_orbit();
+
+        let references = self.references();
+        let ref_offset = self.reference_callbacks(&references)[0].offset;
+        self.update_series(rect, Vector2d::new(ref_offset[0] as f64, ref_offset[1] as f64));
+        self.update_glitch_references(rect, ctx);
+
+        let references = self.references();
+        ui.painter().add(egui_wgpu::Callback::new_paint_callback(
+            rect,
+            shader::PaintCallback {
+                uniforms: self.uniforms(&viewport, &references),
+                reference_orbits: references,
+            },
+        ));
+        // The overlay is placed with the plain mapping from the screen, which the exponential map doesn't show
+        if self.shading_type == ShadingType::OrbitTrap && !self.exponential_map {
+            self.trap_overlay(ui, &viewport);
+        }
+    }
+
+    /// Draw the orbit trap over the fractal, with a handle at its center that can be dragged to move it
+    fn trap_overlay(&mut self, ui: &mut egui::Ui, viewport: &Viewport) {
+        let Some(center) = Vector2Big::from_f64(self.trap.center) else {
+            return;
+        };
+        let center = viewport.complex_to_screen(&center);
+        let handle = Rect::from_center_size(center, egui::Vec2::splat(TRAP_HANDLE_SIZE));
+        let response = ui.interact(handle, ui.id().with("trap_handle"), Sense::drag());
+        if response.dragged() {
+            self.trap.center = viewport.screen_to_complex(center + response.drag_delta()).to_f64();
+        }
+
+        let painter = ui.painter_at(viewport.rect);
+        let stroke = egui::Stroke::new(1.5, egui::Color32::from_white_alpha(192));
+        // Long enough to cross the whole view from anywhere on it
+        let length = viewport.rect.size().length();
+        let direction = viewport
+            .normalized_vector_to_screen(Vector2d::new(
+                self.trap.angle.cos() as f64,
+                self.trap.angle.sin() as f64,
+            ))
+            .normalized();
+        let line = |direction: egui::Vec2| {
+            painter.line_segment([center - direction * length, center + direction * length], stroke);
+        };
+        match self.trap.shape {
+            TrapShape::Point => {}
+            TrapShape::Line => line(direction),
+            TrapShape::Cross | TrapShape::Stalks => {
+                line(direction);
+                line(direction.rot90());
+            }
+            TrapShape::Circle => {
+                let radius = (FloatExp::from_f64(self.trap.size as f64) / self.camera.zoom).to_f64() as f32;
+                painter.circle_stroke(center, radius * viewport.scale(), stroke);
+            }
+        }
+        let fill = if response.hovered() || response.dragged() {
+            egui::Color32::WHITE
+        } else {
+            egui::Color32::from_white_alpha(128)
+        };
+        painter.circle(
+            center,
+            TRAP_HANDLE_SIZE * 0.5,
+            fill,
+            egui::Stroke::new(1.0, egui::Color32::BLACK),
+        );
+    }
+
+    /// Input processing, returns where the fractal is drawn
+    fn inputs(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) -> Viewport {
+        let size = ui.available_size();
+        let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+        let viewport = Viewport::new(rect, ctx.pixels_per_point(), self.camera.clone());
+        // The mouse is mapped to the plain view, which would move the camera somewhere else than the exponential
+        // map shows, so the view is only changed from the settings then
+        if self.exponential_map {
+            return viewport;
+        }
+
+        // Get the mouse position in normalized coordinates
+        let mouse_pos = ctx.input(|i| i.pointer.latest_pos().unwrap_or_default());
+        let mouse_normalized = viewport.screen_to_normalized(mouse_pos);
+
+        let zoom_before = self.camera.zoom;
+
+        // Zooming
+        ctx.input(|i| {
+            self.camera.zoom *= 1.01_f64.powf(-i.raw_scroll_delta.y as f64);
+
+            if i.zoom_delta() != 1.0 {
+                self.camera.zoom *= i.zoom_delta() as f64;
+            }
+        });
+        // Adjust camera position to keep the world position under the mouse constant.
+        // The point under the mouse is `mouse_normalized * zoom` away from the center, so the center moves by the
+        // difference of that before and after zooming, measured in units of the new zoom
+        self.camera
+            .translate(mouse_normalized * ((zoom_before / self.camera.zoom).to_f64() - 1.0));
+
+        // Drag handling, the point under the mouse follows it
+        if response.dragged_by(egui::PointerButton::Primary) {
+            self.camera
+                .translate(-viewport.screen_vector_to_normalized(response.drag_motion()));
+        }
+        if response.secondary_clicked() || response.dragged_by(egui::PointerButton::Secondary) {
+            let click_position = response.interact_pointer_pos().unwrap();
+            self.julia_pos = viewport.screen_to_complex(click_position).to_f64();
+        }
+        Viewport {
+            camera: self.camera.clone(),
+            ..viewport
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Keeping the settings between runs in eframe's storage.
+//!
+//! The settings are written as a scene file with extra `[render]` and `[export]` sections, so they get the same
+//! handling of missing and unknown keys. The window geometry and the widths of the panels are kept by eframe.
+
+use crate::scene::{quote, ParseSceneError, Scene, Table};
+use crate::Application;
+
+/// The key the settings are kept under in eframe's storage
+pub const STORAGE_KEY: &str = "settings";
+
+impl Application {
+    /// The current scene and the settings that aren't part of it, in the scene file format
+    #[must_use]
+    pub fn settings_to_text(&self) -> String {
+        format!(
+            "\
+{scene}
+[render]
+series_approximation = {series_approximation}
+series_tolerance = {series_tolerance}
+glitch_correction = {glitch_correction}
+show_glitches = {show_glitches}
+exponential_map = {exponential_map}
+
+[export]
+width = {width}
+height = {height}
+path = {path}
+",
+            scene = self.scene().to_toml(),
+            series_approximation = self.series_approximation,
+            series_tolerance = self.series_tolerance,
+            glitch_correction = self.glitch_correction,
+            show_glitches = self.show_glitches,
+            exponential_map = self.exponential_map,
+            width = self.export_input.width,
+            height = self.export_input.height,
+            path = quote(&self.export_input.path),
+        )
+    }
+
+    /// Restore settings written by [`Application::settings_to_text`].
+    /// Missing settings get their default value, and nothing is changed if the text isn't valid
+    ///
+    /// # Errors
+    /// If the text isn't valid, or a value has the wrong type
+    pub fn restore_settings(&mut self, text: &str) -> Result<(), ParseSceneError> {
+        let table = Table::parse(text)?;
+        let scene = Scene::from_table(&table)?;
+        let mut restored = Self::default();
+        table.read("render.series_approximation", &mut restored.series_approximation)?;
+        table.read("render.series_tolerance", &mut restored.series_tolerance)?;
+        table.read("render.glitch_correction", &mut restored.glitch_correction)?;
+        table.read("render.show_glitches", &mut restored.show_glitches)?;
+        table.read("render.exponential_map", &mut restored.exponential_map)?;
+        table.read("export.width", &mut restored.export_input.width)?;
+        table.read("export.height", &mut restored.export_input.height)?;
+        if let Some(path) = table.get_string("export.path")? {
+            restored.export_input.path = path;
+        }
+
+        // Everything is valid, only now change the settings
+        self.set_scene(scene);
+        self.copy_settings(restored);
+        Ok(())
+    }
+
+    /// Go back to the default settings and view, keeping the bookmarks. This can be undone like any other change
+    pub fn reset_to_defaults(&mut self) {
+        let defaults = Self::default();
+        self.set_scene(defaults.scene());
+        self.copy_settings(defaults);
+    }
+
+    /// Take the settings that aren't part of the scene from `other`
+    fn copy_settings(&mut self, other: Self) {
+        self.series_approximation = other.series_approximation;
+        self.series_tolerance = other.series_tolerance;
+        self.glitch_correction = other.glitch_correction;
+        self.show_glitches = other.show_glitches;
+        self.exponential_map = other.exponential_map;
+        self.export_input.width = other.export_input.width;
+        self.export_input.height = other.export_input.height;
+        self.export_input.path = other.export_input.path;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColorScheme, FractalType};
+
+    #[test]
+    fn round_trip() {
+        let mut application = Application {
+            max_iter: 5000,
+            fractal_type: FractalType::Tricorn,
+            color_scheme: ColorScheme::SKY,
+            series_approximation: false,
+            show_glitches: true,
+            ..Application::default()
+        };
+        application.export_input.width = 640;
+        let text = application.settings_to_text();
+
+        let mut restored = Application::default();
+        restored.restore_settings(&text).unwrap();
+        assert_eq!(restored.settings_to_text(), text);
+        assert_eq!(restored.color_scheme, ColorScheme::SKY);
+        assert!(!restored.series_approximation);
+    }
+
+    #[test]
+    fn invalid_settings_change_nothing() {
+        let mut application = Application {
+            max_iter: 5000,
+            ..Application::default()
+        };
+        let before = application.settings_to_text();
+        assert!(application
+            .restore_settings("[fractal]\nmax_iter = 10\n[render]\nshow_glitches = maybe\n")
+            .is_err());
+        assert!(application.restore_settings("[camera\n").is_err());
+        assert_eq!(application.settings_to_text(), before);
+
+        // Settings from before a key existed still load
+        application
+            .restore_settings("version = 1\n[fractal]\nmax_iter = 10\n")
+            .unwrap();
+        assert_eq!(application.max_iter, 10);
+        assert!(application.series_approximation);
+    }
+}
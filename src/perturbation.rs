@@ -0,0 +1,420 @@
+//! Perturbation theory for deep zooms.
+//!
+//! A single reference orbit is iterated on the CPU with arbitrary precision, and every pixel only iterates the
+//! (small) difference between its own orbit and the reference in [`f32`]. The deltas keep their relative precision
+//! no matter how far the camera is zoomed in, so the image stays sharp long after plain [`f32`] coordinates turn
+//! into blocks.
+//! See <https://mathr.co.uk/blog/2021-05-14_deep_zoom_theory_and_practice.html> for the theory.
+
+use std::collections::VecDeque;
+
+use crate::floatexp::FloatExp;
+use crate::vector2::{Complex, Vector2Big, Vector2d};
+use crate::FractalType;
+
+/// Everything besides the reference point that changes the values of a [`ReferenceOrbit`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReferenceParams {
+    pub max_iter: i32,
+    pub exponent: f32,
+    pub fractal_type: FractalType,
+    /// The Julia constant, or [`None`] when rendering the Mandelbrot-like set itself
+    pub julia: Option<Vector2d>,
+}
+
+/// A high precision orbit that the pixels of a perturbation render are compared against
+#[derive(Debug, Clone)]
+pub struct ReferenceOrbit {
+    /// The point the reference is iterated for. In Julia mode this is the starting `z`, otherwise it is `c`
+    pub center: Vector2Big,
+    pub params: ReferenceParams,
+    /// `z` of every iteration, starting at iteration 0 and ending with the first value outside the escape radius
+    /// (or after `max_iter` iterations). Stored as [`f32`] because that is what the GPU consumes.
+    pub orbit: Vec<[f32; 2]>,
+}
+
+/// A pixel is glitched when `|z|` gets smaller than this times `|z_ref|`.
+///
+/// This is Pauldelbrot's glitch criterion: `dz` is then so much bigger than `z` that the rounding errors of
+/// [`f32`] dominate, which shows up as flat blobs.
+pub const GLITCH_TOLERANCE: f32 = 1e-3;
+
+/// Whether the perturbation formulas in the shader can render this fractal.
+///
+/// The Mandelbrot set and the Tricorn work for any integer exponent of at least two,
+/// the Burning Ship only for the standard exponent of two.
+#[must_use]
+pub fn is_supported(fractal_type: FractalType, exponent: f32) -> bool {
+    match fractal_type {
+        FractalType::Mandelbrot | FractalType::Tricorn => exponent >= 2.0 && exponent.fract() == 0.0,
+        FractalType::BurningShip => exponent == 2.0,
+    }
+}
+
+impl ReferenceOrbit {
+    /// Iterate the reference orbit for the given point, using the precision of `center`.
+    /// [`None`] if the Julia position isn't finite, there's no exact point to iterate with then
+    #[must_use]
+    pub fn new(center: Vector2Big, params: ReferenceParams) -> Option<Self> {
+        let precision = center.precision();
+        let (z, c) = match params.julia {
+            Some(julia_pos) => (center.clone(), Vector2Big::from_f64(julia_pos)?),
+            None => (Vector2Big::default(), center.clone()),
+        };
+        let mut z = z.with_precision(precision);
+        let exponent = params.exponent.abs() as u32;
+
+        let escape_sq = 16.0;
+        // Most orbits escape long before max_iter, which is only limited by the user, so the orbit grows as needed
+        let mut orbit = Vec::new();
+        let mut z_low = z.to_f64();
+        orbit.push([z_low.x as f32, z_low.y as f32]);
+        for _ in 0..params.max_iter {
+            if z_low.x * z_low.x + z_low.y * z_low.y >= escape_sq {
+                break;
+            }
+            z = match params.fractal_type {
+                FractalType::Mandelbrot => pow_complex(&z, exponent),
+                FractalType::BurningShip => pow_complex(&Vector2Big::new(z.x.abs(), z.y.abs()), exponent),
+                FractalType::Tricorn => pow_complex(&Vector2Big::new(z.x, -z.y), exponent),
+            } + c.clone();
+            z_low = z.to_f64();
+            orbit.push([z_low.x as f32, z_low.y as f32]);
+        }
+
+        Some(Self { center, params, orbit })
+    }
+
+    /// The offset from the reference point to `point`
+    #[must_use]
+    pub fn offset_to(&self, point: &Vector2Big) -> Vector2Big {
+        point.clone() - self.center.clone()
+    }
+
+    /// The number of iterations stored in the orbit
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.orbit.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.orbit.is_empty()
+    }
+}
+
+/// Raise a complex number to a positive integer power by repeated multiplication
+fn pow_complex(a: &Vector2Big, n: u32) -> Vector2Big {
+    let mut z = a.clone();
+    for _ in 1..n {
+        z = Vector2Big::new(&z.x * &a.x - &z.y * &a.y, &z.x * &a.y + &z.y * &a.x);
+    }
+    z
+}
+
+/// Whether [`SeriesApproximation`] works for this fractal.
+///
+/// The series is only derived for `z^2 + c`, the Burning Ship and the Tricorn aren't analytic in `dc`.
+#[must_use]
+pub fn series_is_supported(fractal_type: FractalType, exponent: f32) -> bool {
+    fractal_type == FractalType::Mandelbrot && exponent == 2.0
+}
+
+/// The first terms of the Taylor series of a pixel's delta after `skip` iterations,
+/// `dz = A d + B d^2 + C d^3`, where `d` is `dc` (or the starting `dz` for Julia sets).
+///
+/// Evaluating it lets every pixel start at iteration `skip` instead of 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeriesApproximation {
+    pub skip: usize,
+    /// `[A, B, C]`
+    pub coefficients: [Complex<FloatExp>; 3],
+    /// The largest `|d|` the series was checked for
+    pub radius: FloatExp,
+    pub tolerance: f64,
+}
+
+impl SeriesApproximation {
+    /// Advance the series along the reference for as long as the first left out term, `D d^4`, stays below
+    /// `tolerance` times the largest term, for every `|d|` up to `radius`.
+    ///
+    /// The coefficients are iterated with the same [`f32`] orbit the pixels use.
+    #[must_use]
+    #[allow(clippy::many_single_char_names)]
+    pub fn new(orbit: &ReferenceOrbit, radius: FloatExp, tolerance: f64) -> Self {
+        let zero = Complex::new(FloatExp::ZERO, FloatExp::ZERO);
+        let one = Complex::new(FloatExp::from_f64(1.0), FloatExp::ZERO);
+        let two = FloatExp::from_f64(2.0);
+        let julia = orbit.params.julia.is_some();
+        // dz starts at d for Julia sets and at 0 otherwise, and only the Mandelbrot set adds dc every iteration
+        let (mut a, added) = if julia { (one, zero) } else { (zero, one) };
+        let (mut b, mut c, mut d) = (zero, zero, zero);
+
+        // Powers of the radius, squared like the norms they are compared with
+        let r_2 = radius * radius;
+        let r_4 = r_2 * r_2;
+        let r_6 = r_4 * r_2;
+        let r_8 = r_4 * r_4;
+        let tolerance_2 = FloatExp::from_f64(tolerance * tolerance);
+        let mut skip = 0;
+        // The pixels still have to check the iteration where the reference escapes themselves
+        while skip + 1 < orbit.len() {
+            let [x, y] = orbit.orbit[skip];
+            let z_2 = Complex::new(FloatExp::from_f64(2.0 * x as f64), FloatExp::from_f64(2.0 * y as f64));
+            let next_a = z_2 * a + added;
+            let next_b = z_2 * b + a * a;
+            let next_c = z_2 * c + a * b * two;
+            let next_d = z_2 * d + a * c * two + b * b;
+
+            let largest = max_exp(
+                next_a.norm_sqr() * r_2,
+                max_exp(next_b.norm_sqr() * r_4, next_c.norm_sqr() * r_6),
+            );
+            if next_d.norm_sqr() * r_8 > tolerance_2 * largest {
+                break;
+            }
+            (a, b, c, d) = (next_a, next_b, next_c, next_d);
+            skip += 1;
+        }
+
+        Self {
+            skip,
+            coefficients: [a, b, c],
+            radius,
+            tolerance,
+        }
+    }
+}
+
+fn max_exp(a: FloatExp, b: FloatExp) -> FloatExp {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// The glitched pixel that is the furthest away from any pixel that isn't glitched,
+/// which makes a good reference point for the glitched region around it.
+///
+/// `mask` has one value per pixel in rows of `width`. Returns the index of the pixel,
+/// or [`None`] if no pixel is glitched.
+#[must_use]
+pub fn glitch_center(mask: &[bool], width: usize) -> Option<usize> {
+    if width == 0 || !mask.contains(&true) {
+        return None;
+    }
+
+    // Breadth first search starting from every pixel that isn't glitched
+    let mut distance: Vec<usize> = mask
+        .iter()
+        .map(|&glitched| if glitched { usize::MAX } else { 0 })
+        .collect();
+    let mut queue: VecDeque<usize> = (0..mask.len()).filter(|&i| !mask[i]).collect();
+    if queue.is_empty() {
+        // Everything is glitched, so start in the middle
+        return Some(mask.len() / width / 2 * width + width / 2);
+    }
+    let mut furthest = queue[0];
+    while let Some(i) = queue.pop_front() {
+        furthest = i;
+        let (x, y) = (i % width, i / width);
+        let neighbours = [
+            (x > 0).then(|| i - 1),
+            (x + 1 < width).then_some(i + 1),
+            (y > 0).then(|| i - width),
+            (i + width < mask.len()).then_some(i + width),
+        ];
+        for j in neighbours.into_iter().flatten() {
+            if distance[j] == usize::MAX {
+                distance[j] = distance[i] + 1;
+                queue.push_back(j);
+            }
+        }
+    }
+    Some(furthest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::RawData;
+    use crate::shader::RenderCallback;
+    use crate::{cpu, export, Application, CameraInfo};
+    use eframe::egui::{Pos2, Rect, Vec2};
+    use std::sync::Arc;
+
+    /// The raw data of a small view near the boundary at a zoom of 1e-6, where plain [`f32`] can't tell the
+    /// pixels apart
+    fn boundary_data(center: Vector2d, exponent: f32, perturbation: bool) -> RawData {
+        let mut application = Application {
+            camera: CameraInfo::new(Vector2Big::from_f64(center).unwrap(), FloatExp::from_f64(1e-6), 0.0),
+            max_iter: 500,
+            exponent,
+            perturbation,
+            ..Application::default()
+        };
+        let data = application.render_data(32, 32);
+        assert_eq!(application.reference_orbit.is_some(), perturbation);
+        data
+    }
+
+    /// The perturbed iteration escapes at the same iteration with the same z as iterating every pixel directly
+    #[test]
+    fn perturbation_matches_direct_iteration() {
+        // Just outside the seahorse valley, and outside the cubic Mandelbrot set for the general power formula
+        for (center, exponent) in [(Vector2d::new(-0.7436, 0.1318), 2.0), (Vector2d::new(-0.6, 0.6), 3.0)] {
+            let direct = boundary_data(center, exponent, false);
+            let perturbed = boundary_data(center, exponent, true);
+            let channels = cpu::DATA_CHANNELS.len();
+            let mut escaped = 0;
+            for (a, b) in direct
+                .values
+                .chunks_exact(channels)
+                .zip(perturbed.values.chunks_exact(channels))
+            {
+                assert_eq!(a[5], b[5]);
+                if a[5] == 0.0 {
+                    continue;
+                }
+                escaped += 1;
+                assert!((a[0] - b[0]).abs() < 1e-3, "iterations {} {}", a[0], b[0]);
+                assert!(
+                    (a[1] - b[1]).abs() < 1e-2 && (a[2] - b[2]).abs() < 1e-2,
+                    "z {a:?} {b:?}"
+                );
+            }
+            assert!(escaped > direct.values.len() / channels / 2, "{exponent}");
+        }
+    }
+
+    /// Skipping the first iterations with the series approximation at a zoom of 1e-12 gives the same iteration counts
+    /// as perturbing every iteration, up to the error the series tolerance allows
+    #[test]
+    fn series_approximation_matches_iteration() {
+        let render = |series_approximation| {
+            let mut application = Application {
+                // Around a minibrot on the real axis, so the iteration counts vary over the view
+                camera: CameraInfo::new(
+                    Vector2Big::from_f64(Vector2d::new(-1.985_540_371_654_130_5, 0.0)).unwrap(),
+                    FloatExp::from_f64(1e-12),
+                    0.0,
+                ),
+                max_iter: 1000,
+                perturbation: true,
+                series_approximation,
+                ..Application::default()
+            };
+            let data = application.render_data(32, 32);
+            (data, application.series)
+        };
+        let (iterated, no_series) = render(false);
+        let (approximated, series) = render(true);
+        assert!(no_series.is_none());
+        assert!(series.unwrap().skip > 0);
+
+        let channels = cpu::DATA_CHANNELS.len();
+        for (a, b) in iterated
+            .values
+            .chunks_exact(channels)
+            .zip(approximated.values.chunks_exact(channels))
+        {
+            assert_eq!(a[5], b[5]);
+            assert!((a[0] - b[0]).abs() < 0.5, "iterations {} {}", a[0], b[0]);
+        }
+    }
+
+    /// The pixels of the glitch scan of `application` that are still glitched with all of its references
+    fn glitched_pixels(application: &Application, rect: Rect) -> usize {
+        let references = application.references();
+        let uniforms = RenderCallback {
+            ref_count: references.len() as u32,
+            references: application.reference_callbacks(&references),
+            ..application.glitch_scan_uniforms(rect)
+        };
+        let [width, height] = uniforms.resolution.map(|v| v as u32);
+        let mut mask = vec![true; width as usize * height as usize];
+        cpu::update_glitch_mask(&uniforms, &export::orbit_buffer(&references), width, height, &mut mask);
+        mask.iter().filter(|&&glitched| glitched).count()
+    }
+
+    /// The search starts at the pixel furthest inside the glitches, which is in the biggest blob
+    #[test]
+    fn glitch_center_of_mask() {
+        let rows = [
+            "#.......", //
+            "....###.", //
+            "...####.", //
+            "...####.", //
+            "....##..", //
+        ];
+        let mask: Vec<bool> = rows.iter().flat_map(|row| row.chars().map(|c| c == '#')).collect();
+        let center = glitch_center(&mask, 8).unwrap();
+        assert!([2 * 8 + 5, 3 * 8 + 5].contains(&center), "{center}");
+
+        assert_eq!(glitch_center(&[false; 40], 8), None);
+        assert_eq!(glitch_center(&[true; 40], 8), Some(2 * 8 + 4));
+    }
+
+    /// A reference outside the minibrot in the middle of the view glitches its center,
+    /// the references the search adds fix every pixel
+    #[test]
+    fn glitch_references_fix_minibrot() {
+        let center = Vector2d::new(-1.985_540_371_654_130_5, 0.0);
+        let zoom = 1e-4;
+        let mut application = Application {
+            camera: CameraInfo::new(Vector2Big::from_f64(center).unwrap(), FloatExp::from_f64(zoom), 0.0),
+            max_iter: 1000,
+            perturbation: true,
+            ..Application::default()
+        };
+        let params = ReferenceParams {
+            max_iter: 1000,
+            exponent: 2.0,
+            fractal_type: FractalType::Mandelbrot,
+            julia: None,
+        };
+        let reference = Vector2Big::from_f64(Vector2d::new(center.x + 0.9 * zoom, center.y - 0.63 * zoom)).unwrap();
+        application.reference_orbit = ReferenceOrbit::new(reference, params).map(Arc::new);
+
+        let rect = Rect::from_min_size(Pos2::ZERO, Vec2::new(64.0, 64.0));
+        assert!(glitched_pixels(&application, rect) > 100);
+        application.search_glitch_references(rect);
+        assert!(!application.glitch_references.is_empty());
+        assert_eq!(glitched_pixels(&application, rect), 0);
+    }
+
+    /// A Julia position that isn't finite has no reference orbit, the view is rendered without perturbation
+    #[test]
+    fn non_finite_julia_has_no_reference() {
+        let params = ReferenceParams {
+            max_iter: 100,
+            exponent: 2.0,
+            fractal_type: FractalType::Mandelbrot,
+            julia: Some(Vector2d::new(f64::NAN, 0.0)),
+        };
+        assert!(ReferenceOrbit::new(Vector2Big::default(), params).is_none());
+
+        let mut application = Application {
+            julia: true,
+            julia_pos: Vector2d::new(f64::INFINITY, 0.0),
+            perturbation: true,
+            ..Application::default()
+        };
+        application.render_data(4, 4);
+        assert!(application.reference_orbit.is_none());
+    }
+
+    /// Only integer exponents of at least two have perturbation formulas, and only two for the Burning Ship
+    #[test]
+    fn unsupported_exponents() {
+        assert!(is_supported(FractalType::Mandelbrot, 2.0));
+        assert!(is_supported(FractalType::Tricorn, 3.0));
+        assert!(is_supported(FractalType::BurningShip, 2.0));
+        assert!(!is_supported(FractalType::Mandelbrot, 2.5));
+        assert!(!is_supported(FractalType::Tricorn, 3.5));
+        assert!(!is_supported(FractalType::Mandelbrot, 1.0));
+        assert!(!is_supported(FractalType::BurningShip, 3.0));
+        assert!(!is_supported(FractalType::BurningShip, 2.5));
+    }
+}
@@ -0,0 +1,830 @@
+//! Saving and loading everything that decides what is rendered as a human-readable scene file.
+//!
+//! Scenes are written in a small subset of TOML: `[section]` headers and `key = value` lines, where values are
+//! quoted strings, numbers, booleans or arrays of numbers. Coordinates are stored as decimal strings, so they
+//! keep every digit no matter how deep the zoom is.
+//!
+//! Every scene has a `version`. Keys that are missing get their default value and unknown keys are ignored,
+//! so files written before a field existed keep loading, and so do files from newer versions.
+//!
+//! Exported PNGs carry their scene in an iTXt chunk with the keyword [`PNG_KEYWORD`], so any render can be
+//! opened again as the view it was made from.
+
+use std::fmt::{Display, Write};
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use crate::floatexp::FloatExp;
+use crate::vector2::Vector2d;
+use crate::{
+    ui, Application, Averages, CameraInfo, ColorScheme, FractalType, Light, OrbitTrap, ShadingType, TrapColoring,
+    TrapShape,
+};
+
+/// The version written into new scene files.
+/// Increase it when the meaning of an existing key changes, new keys don't need a new version.
+pub const SCENE_VERSION: u32 = 1;
+
+/// The keyword of the PNG text chunk that holds the scene of an exported image
+pub const PNG_KEYWORD: &str = "mandelbrot-scene";
+
+/// The first bytes of every PNG file
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// A snapshot of the settings of an [`Application`] that decide what is rendered
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    pub camera: CameraInfo,
+    pub max_iter: i32,
+    pub exponent: f32,
+    pub fractal_type: FractalType,
+    pub shading_type: ShadingType,
+    pub color_scheme: ColorScheme,
+    pub palette_speed: f32,
+    pub boundary_width: f32,
+    pub light: Light,
+    pub trap: OrbitTrap,
+    pub averages: Averages,
+    pub julia: bool,
+    pub julia_pos: Vector2d,
+    pub perturbation: bool,
+}
+
+/// An error in a scene file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSceneError {
+    /// The line the error is on, starting at 1, or 0 if it isn't on a single line
+    pub line: usize,
+    pub message: String,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Application::default().scene()
+    }
+}
+
+impl Scene {
+    /// Write the scene in the scene file format
+    #[must_use]
+    pub fn to_toml(&self) -> String {
+        // Values that aren't finite can't be written, they're replaced by their defaults
+        let default = Self::default();
+        let color = (self.color_scheme, default.color_scheme);
+        let zoom = if self.camera.zoom.mantissa().is_finite() {
+            self.camera.zoom
+        } else {
+            default.camera.zoom
+        };
+        format!(
+            "\
+version = {SCENE_VERSION}
+
+[camera]
+re = {re}
+im = {im}
+zoom = {zoom}
+rotation = {rotation}
+
+[fractal]
+type = {fractal_type}
+max_iter = {max_iter}
+exponent = {exponent}
+julia = {julia}
+julia_re = {julia_re}
+julia_im = {julia_im}
+perturbation = {perturbation}
+
+[color]
+shading = {shading}
+palette_speed = {palette_speed}
+boundary_width = {boundary_width}
+light_angle = {light_angle}
+light_height = {light_height}
+specular = {specular}
+light_blend = {light_blend}
+stripe_frequency = {stripe_frequency}
+stripe_strength = {stripe_strength}
+tia_strength = {tia_strength}
+curvature_strength = {curvature_strength}
+palette_a = {a}
+palette_b = {b}
+palette_c = {c}
+palette_d = {d}
+
+[trap]
+shape = {trap_shape}
+re = {trap_re}
+im = {trap_im}
+angle = {trap_angle}
+size = {trap_size}
+coloring = {trap_coloring}
+",
+            re = quote(&self.camera.center.x),
+            im = quote(&self.camera.center.y),
+            zoom = quote(&zoom),
+            rotation = finite_or(self.camera.rotation, default.camera.rotation),
+            fractal_type = quote(name_of(&FractalType::NAMES, self.fractal_type)),
+            max_iter = self.max_iter,
+            exponent = finite_or(self.exponent, default.exponent),
+            julia = self.julia,
+            julia_re = finite_or(self.julia_pos.x, default.julia_pos.x),
+            julia_im = finite_or(self.julia_pos.y, default.julia_pos.y),
+            perturbation = self.perturbation,
+            shading = quote(name_of(&ShadingType::NAMES, self.shading_type)),
+            palette_speed = finite_or(self.palette_speed, default.palette_speed),
+            boundary_width = finite_or(self.boundary_width, default.boundary_width),
+            light_angle = finite_or(self.light.angle, default.light.angle),
+            light_height = finite_or(self.light.height, default.light.height),
+            specular = finite_or(self.light.specular, default.light.specular),
+            light_blend = finite_or(self.light.blend, default.light.blend),
+            stripe_frequency = finite_or(self.averages.stripe_frequency, default.averages.stripe_frequency),
+            stripe_strength = finite_or(self.averages.stripe_strength, default.averages.stripe_strength),
+            tia_strength = finite_or(self.averages.tia_strength, default.averages.tia_strength),
+            curvature_strength = finite_or(self.averages.curvature_strength, default.averages.curvature_strength),
+            a = array(color.0.a, color.1.a),
+            b = array(color.0.b, color.1.b),
+            c = array(color.0.c, color.1.c),
+            d = array(color.0.d, color.1.d),
+            trap_shape = quote(name_of(&TrapShape::NAMES, self.trap.shape)),
+            trap_re = finite_or(self.trap.center.x, default.trap.center.x),
+            trap_im = finite_or(self.trap.center.y, default.trap.center.y),
+            trap_angle = finite_or(self.trap.angle, default.trap.angle),
+            trap_size = finite_or(self.trap.size, default.trap.size),
+            trap_coloring = quote(name_of(&TrapColoring::NAMES, self.trap.coloring)),
+        )
+    }
+
+    /// Read a scene in the scene file format, see the [module docs](self) for what is accepted
+    ///
+    /// # Errors
+    /// If the text isn't valid, or a value has the wrong type
+    pub fn from_toml(text: &str) -> Result<Self, ParseSceneError> {
+        Self::from_table(&Table::parse(text)?)
+    }
+
+    /// Read a scene from the keys of a scene file, other keys in the file are left for the caller
+    pub(crate) fn from_table(table: &Table) -> Result<Self, ParseSceneError> {
+        let mut scene = Self::default();
+
+        if let Some(version) = table.get::<u32>("version")? {
+            if version == 0 {
+                return Err(table.error("version", "unknown scene version 0"));
+            }
+        }
+
+        let zoom = match table.get_string("camera.zoom")? {
+            Some(zoom) => zoom
+                .parse::<FloatExp>()
+                .ok()
+                .filter(|zoom| *zoom > FloatExp::ZERO)
+                .ok_or_else(|| table.error("camera.zoom", "zoom: expected a positive number"))?,
+            None => scene.camera.zoom,
+        };
+        let rotation = table.get("camera.rotation")?.unwrap_or(scene.camera.rotation);
+        scene.camera = match (table.get_string("camera.re")?, table.get_string("camera.im")?) {
+            (Some(re), Some(im)) => CameraInfo::parse(&re, &im, zoom, rotation)
+                .map_err(|e| table.error("camera.re", &format!("camera position: {e}")))?,
+            (None, None) => CameraInfo::new(scene.camera.center, zoom, rotation),
+            _ => return Err(table.error("camera.re", "camera.re and camera.im have to be given together")),
+        };
+
+        if let Some(name) = table.get_string("fractal.type")? {
+            scene.fractal_type = find_name(&FractalType::NAMES, &name)
+                .ok_or_else(|| table.error("fractal.type", &format!("unknown fractal type {name}")))?;
+        }
+        table.read("fractal.max_iter", &mut scene.max_iter)?;
+        table.read("fractal.exponent", &mut scene.exponent)?;
+        table.read("fractal.julia", &mut scene.julia)?;
+        table.read("fractal.julia_re", &mut scene.julia_pos.x)?;
+        table.read("fractal.julia_im", &mut scene.julia_pos.y)?;
+        table.read("fractal.perturbation", &mut scene.perturbation)?;
+
+        if let Some(name) = table.get_string("color.shading")? {
+            scene.shading_type = find_name(&ShadingType::NAMES, &name)
+                .ok_or_else(|| table.error("color.shading", &format!("unknown shading type {name}")))?;
+        }
+        table.read("color.palette_speed", &mut scene.palette_speed)?;
+        table.read("color.boundary_width", &mut scene.boundary_width)?;
+        table.read("color.light_angle", &mut scene.light.angle)?;
+        table.read("color.light_height", &mut scene.light.height)?;
+        table.read("color.specular", &mut scene.light.specular)?;
+        table.read("color.light_blend", &mut scene.light.blend)?;
+        table.read("color.stripe_frequency", &mut scene.averages.stripe_frequency)?;
+        table.read("color.stripe_strength", &mut scene.averages.stripe_strength)?;
+        table.read("color.tia_strength", &mut scene.averages.tia_strength)?;
+        table.read("color.curvature_strength", &mut scene.averages.curvature_strength)?;
+        for (key, channel) in [
+            ("color.palette_a", &mut scene.color_scheme.a),
+            ("color.palette_b", &mut scene.color_scheme.b),
+            ("color.palette_c", &mut scene.color_scheme.c),
+            ("color.palette_d", &mut scene.color_scheme.d),
+        ] {
+            if let Some(value) = table.get_array(key)? {
+                *channel = value;
+            }
+        }
+
+        if let Some(name) = table.get_string("trap.shape")? {
+            scene.trap.shape = find_name(&TrapShape::NAMES, &name)
+                .ok_or_else(|| table.error("trap.shape", &format!("unknown trap shape {name}")))?;
+        }
+        table.read("trap.re", &mut scene.trap.center.x)?;
+        table.read("trap.im", &mut scene.trap.center.y)?;
+        table.read("trap.angle", &mut scene.trap.angle)?;
+        table.read("trap.size", &mut scene.trap.size)?;
+        if let Some(name) = table.get_string("trap.coloring")? {
+            scene.trap.coloring = find_name(&TrapColoring::NAMES, &name)
+                .ok_or_else(|| table.error("trap.coloring", &format!("unknown trap coloring {name}")))?;
+        }
+        if let Some((key, expected)) = scene.invalid_value() {
+            return Err(table.error(key, &format!("{key}: {expected}")));
+        }
+        Ok(scene)
+    }
+
+    /// Check that every value is in its range, the same way [`Scene::from_toml`] checks the values of a file
+    ///
+    /// # Errors
+    /// If a value isn't, with the key of the value in scene files
+    pub fn validate(&self) -> Result<(), ParseSceneError> {
+        match self.invalid_value() {
+            Some((key, expected)) => Err(ParseSceneError {
+                line: 0,
+                message: format!("{key}: {expected}"),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The key of the first value that is out of its range and what it has to be.
+    /// Every number has to be finite, and the counts and sizes positive
+    fn invalid_value(&self) -> Option<(&'static str, &'static str)> {
+        const FINITE: &str = "expected a finite number";
+        const POSITIVE: &str = "expected a positive number";
+        let finite = |values: &[f32]| values.iter().all(|value| value.is_finite());
+        let zoom = self.camera.zoom;
+        let checks = [
+            (
+                "camera.zoom",
+                zoom > FloatExp::ZERO && zoom.mantissa().is_finite(),
+                POSITIVE,
+            ),
+            ("camera.rotation", self.camera.rotation.is_finite(), FINITE),
+            ("fractal.max_iter", self.max_iter >= 1, POSITIVE),
+            ("fractal.exponent", self.exponent.is_finite(), FINITE),
+            ("fractal.julia_re", self.julia_pos.x.is_finite(), FINITE),
+            ("fractal.julia_im", self.julia_pos.y.is_finite(), FINITE),
+            ("color.palette_speed", self.palette_speed.is_finite(), FINITE),
+            (
+                "color.boundary_width",
+                finite(&[self.boundary_width]) && self.boundary_width > 0.0,
+                POSITIVE,
+            ),
+            ("color.light_angle", self.light.angle.is_finite(), FINITE),
+            ("color.light_height", self.light.height.is_finite(), FINITE),
+            ("color.specular", self.light.specular.is_finite(), FINITE),
+            ("color.light_blend", self.light.blend.is_finite(), FINITE),
+            (
+                "color.stripe_frequency",
+                self.averages.stripe_frequency.is_finite(),
+                FINITE,
+            ),
+            (
+                "color.stripe_strength",
+                self.averages.stripe_strength.is_finite(),
+                FINITE,
+            ),
+            ("color.tia_strength", self.averages.tia_strength.is_finite(), FINITE),
+            (
+                "color.curvature_strength",
+                self.averages.curvature_strength.is_finite(),
+                FINITE,
+            ),
+            ("color.palette_a", finite(&self.color_scheme.a), FINITE),
+            ("color.palette_b", finite(&self.color_scheme.b), FINITE),
+            ("color.palette_c", finite(&self.color_scheme.c), FINITE),
+            ("color.palette_d", finite(&self.color_scheme.d), FINITE),
+            ("trap.re", self.trap.center.x.is_finite(), FINITE),
+            ("trap.im", self.trap.center.y.is_finite(), FINITE),
+            ("trap.angle", self.trap.angle.is_finite(), FINITE),
+            ("trap.size", finite(&[self.trap.size]) && self.trap.size > 0.0, POSITIVE),
+        ];
+        checks
+            .into_iter()
+            .find(|(_, valid, _)| !valid)
+            .map(|(key, _, expected)| (key, expected))
+    }
+
+    /// Write the scene to a file
+    ///
+    /// # Errors
+    /// If the file can't be written
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        fs::write(path, self.to_toml())
+    }
+
+    /// Read the scene embedded in a PNG exported by [`Application::export_image`]
+    ///
+    /// # Errors
+    /// If the data isn't a PNG, has no scene or the scene isn't valid
+    pub fn from_png(data: &[u8]) -> Result<Self, ParseSceneError> {
+        let error = |message: String| ParseSceneError { line: 0, message };
+        let reader = png::Decoder::new(data)
+            .read_info()
+            .map_err(|e| error(format!("invalid PNG: {e}")))?;
+        let chunk = reader
+            .info()
+            .utf8_text
+            .iter()
+            .find(|chunk| chunk.keyword == PNG_KEYWORD)
+            .ok_or_else(|| error("the image has no scene in it".to_owned()))?;
+        let text = chunk.get_text().map_err(|e| error(format!("invalid PNG: {e}")))?;
+        Self::from_toml(&text)
+    }
+
+    /// Read a scene from the contents of either a scene file or an exported PNG
+    ///
+    /// # Errors
+    /// If the data isn't a valid scene or a PNG with one in it
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ParseSceneError> {
+        if data.starts_with(PNG_SIGNATURE) {
+            return Self::from_png(data);
+        }
+        let text = std::str::from_utf8(data).map_err(|e| ParseSceneError {
+            line: 0,
+            message: e.to_string(),
+        })?;
+        Self::from_toml(text)
+    }
+
+    /// Read a scene from a scene file or an exported PNG
+    ///
+    /// # Errors
+    /// If the file can't be read or isn't a valid scene
+    pub fn load(path: &Path) -> Result<Self, ParseSceneError> {
+        let data = fs::read(path).map_err(|e| ParseSceneError {
+            line: 0,
+            message: e.to_string(),
+        })?;
+        Self::from_bytes(&data)
+    }
+}
+
+impl Application {
+    /// The current settings as a [`Scene`]
+    #[must_use]
+    pub fn scene(&self) -> Scene {
+        Scene {
+            camera: self.camera.clone(),
+            max_iter: self.max_iter,
+            exponent: self.exponent,
+            fractal_type: self.fractal_type,
+            shading_type: self.shading_type,
+            color_scheme: self.color_scheme,
+            palette_speed: self.palette_speed,
+            boundary_width: self.boundary_width,
+            light: self.light,
+            trap: self.trap,
+            averages: self.averages,
+            julia: self.julia,
+            julia_pos: self.julia_pos,
+            perturbation: self.perturbation,
+        }
+    }
+
+    /// Replace the current settings with `scene`
+    pub fn set_scene(&mut self, scene: Scene) {
+        self.camera = scene.camera;
+        self.max_iter = scene.max_iter;
+        self.exponent = scene.exponent;
+        self.fractal_type = scene.fractal_type;
+        self.shading_type = scene.shading_type;
+        self.color_scheme = scene.color_scheme;
+        self.palette_speed = scene.palette_speed;
+        self.boundary_width = scene.boundary_width;
+        self.light = scene.light;
+        self.trap = scene.trap;
+        self.averages = scene.averages;
+        self.julia = scene.julia;
+        self.julia_pos = scene.julia_pos;
+        self.perturbation = scene.perturbation;
+        self.location_input = ui::LocationInput::from_camera(&self.camera);
+    }
+}
+
+impl Display for ParseSceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ParseSceneError {}
+
+/// The name of `value` in a list of names like [`FractalType::NAMES`]
+pub(crate) fn name_of<T: Copy + PartialEq>(names: &[(&'static str, T)], value: T) -> &'static str {
+    names.iter().find(|(_, v)| *v == value).map_or("", |(name, _)| name)
+}
+
+/// The value called `name` in a list of names like [`FractalType::NAMES`]
+#[must_use]
+pub fn find_name<T: Copy>(names: &[(impl AsRef<str>, T)], name: &str) -> Option<T> {
+    names.iter().find(|(n, _)| n.as_ref() == name).map(|(_, v)| *v)
+}
+
+/// Write a string value, with quotes, backslashes and control characters escaped so it stays on one line
+pub(crate) fn quote(value: &(impl Display + ?Sized)) -> String {
+    let mut quoted = String::from('"');
+    for c in value.to_string().chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            // Writing to a String can't fail
+            c if c.is_control() => _ = write!(quoted, "\\u{:04X}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Write a number, or `default` if it isn't finite, NaN and the infinities can't be read back
+fn finite_or<T: Into<f64> + Copy + Display>(value: T, default: T) -> String {
+    if value.into().is_finite() { value } else { default }.to_string()
+}
+
+/// Write an array of numbers, or `default` if one of them isn't finite
+fn array(values: [f32; 3], default: [f32; 3]) -> String {
+    let values = if values.iter().all(|value| value.is_finite()) {
+        values
+    } else {
+        default
+    };
+    let values: Vec<_> = values.iter().map(ToString::to_string).collect();
+    format!("[{}]", values.join(", "))
+}
+
+/// Parse a list of scene files, each one starting with a `separator` line like `[[bookmark]]`.
+/// Only blank lines and comments can come before the first one
+pub(crate) fn parse_list(text: &str, separator: &str) -> Result<Vec<Table>, ParseSceneError> {
+    // The line number of every separator, and the text after it
+    let mut blocks: Vec<(usize, String)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim() == separator {
+            blocks.push((i + 1, String::new()));
+        } else if let Some((_, block)) = blocks.last_mut() {
+            block.push_str(line);
+            block.push('\n');
+        } else if !strip_comment(line).trim().is_empty() {
+            return Err(ParseSceneError {
+                line: i + 1,
+                message: format!("expected {separator}"),
+            });
+        }
+    }
+
+    blocks
+        .into_iter()
+        .map(|(separator_line, block)| {
+            let mut table = Table::parse(&block).map_err(|mut error| {
+                error.line += separator_line;
+                error
+            })?;
+            // Count the lines from the start of the whole text
+            for (line, _) in table.0.values_mut() {
+                *line += separator_line;
+            }
+            Ok(table)
+        })
+        .collect()
+}
+
+/// The keys of a scene file, as `section.key`, with the unparsed values and the lines they are on
+pub(crate) struct Table(HashMap<String, (usize, String)>);
+
+impl Table {
+    pub(crate) fn parse(text: &str) -> Result<Self, ParseSceneError> {
+        let mut values = HashMap::new();
+        let mut section = "";
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let error = |message: &str| ParseSceneError {
+                line: line_number,
+                message: message.to_owned(),
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                section = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| error("expected ] after the section name"))?
+                    .trim();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected a key = value pair"))?;
+            let key = match section {
+                "" => key.trim().to_owned(),
+                section => format!("{section}.{}", key.trim()),
+            };
+            if values.insert(key, (line_number, value.trim().to_owned())).is_some() {
+                return Err(error("the key is given twice"));
+            }
+        }
+        Ok(Self(values))
+    }
+
+    /// An error at the line of `key`
+    pub(crate) fn error(&self, key: &str, message: &str) -> ParseSceneError {
+        ParseSceneError {
+            line: self.0.get(key).map_or(0, |(line, _)| *line),
+            message: message.to_owned(),
+        }
+    }
+
+    /// Parse a number or boolean value
+    pub(crate) fn get<T: FromStr>(&self, key: &str) -> Result<Option<T>, ParseSceneError> {
+        let Some((_, value)) = self.0.get(key) else {
+            return Ok(None);
+        };
+        value
+            .parse()
+            .map(Some)
+            .map_err(|_| self.error(key, &format!("invalid value for {key}: {value}")))
+    }
+
+    /// Parse a value into `target` if it's there
+    pub(crate) fn read<T: FromStr>(&self, key: &str, target: &mut T) -> Result<(), ParseSceneError> {
+        if let Some(value) = self.get(key)? {
+            *target = value;
+        }
+        Ok(())
+    }
+
+    /// Parse a quoted string value
+    pub(crate) fn get_string(&self, key: &str) -> Result<Option<String>, ParseSceneError> {
+        let Some((_, value)) = self.0.get(key) else {
+            return Ok(None);
+        };
+        let inner = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or_else(|| self.error(key, &format!("expected a quoted string for {key}")))?;
+
+        let mut string = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            string.push(match c {
+                '\\' => match chars.next() {
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == 4)
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error(key, "invalid escape sequence"))?
+                    }
+                    _ => return Err(self.error(key, "invalid escape sequence")),
+                },
+                c => c,
+            });
+        }
+        Ok(Some(string))
+    }
+
+    /// Parse an array of three numbers
+    fn get_array(&self, key: &str) -> Result<Option<[f32; 3]>, ParseSceneError> {
+        let Some((_, value)) = self.0.get(key) else {
+            return Ok(None);
+        };
+        let error = || self.error(key, &format!("expected an array of 3 numbers for {key}"));
+        let items = value
+            .strip_prefix('[')
+            .and_then(|v| v.strip_suffix(']'))
+            .ok_or_else(error)?
+            .split(',')
+            .map(|item| item.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| error())?;
+        items.try_into().map(Some).map_err(|_| error())
+    }
+}
+
+/// Remove a `#` comment from the end of a line, unless it's inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector2::Vector2Big;
+
+    #[test]
+    fn round_trip() {
+        let scene = Scene {
+            camera: CameraInfo::new(
+                Vector2Big::parse(
+                    "-1.76877851831770549490300710526298432862",
+                    "-0.00173889646632983244311033352170716",
+                )
+                .unwrap(),
+                "3.5e-400".parse().unwrap(),
+                0.25,
+            ),
+            max_iter: 20000,
+            exponent: 3.0,
+            fractal_type: FractalType::BurningShip,
+            shading_type: ShadingType::Distance,
+            color_scheme: ColorScheme::OCEAN,
+            palette_speed: 0.125,
+            boundary_width: 2.5,
+            light: Light {
+                angle: -2.0,
+                height: 0.5,
+                specular: 0.75,
+                blend: 0.25,
+            },
+            trap: OrbitTrap {
+                shape: TrapShape::Stalks,
+                center: Vector2d::new(0.5, -0.25),
+                angle: 0.3,
+                size: 0.05,
+                coloring: TrapColoring::Iteration,
+            },
+            averages: Averages {
+                stripe_frequency: 3.0,
+                stripe_strength: 0.5,
+                tia_strength: 2.0,
+                curvature_strength: 0.75,
+            },
+            julia: true,
+            julia_pos: Vector2d::new(-0.8, 0.156),
+            perturbation: true,
+        };
+        let loaded = Scene::from_toml(&scene.to_toml()).unwrap();
+        assert_eq!(loaded.to_toml(), scene.to_toml());
+        assert_eq!(loaded.camera.center.x.to_string(), scene.camera.center.x.to_string());
+        assert_eq!(loaded.fractal_type, FractalType::BurningShip);
+        assert_eq!(loaded.color_scheme, ColorScheme::OCEAN);
+        assert_eq!(loaded.julia_pos, scene.julia_pos);
+    }
+
+    #[test]
+    fn missing_keys_are_defaults() {
+        // A file with only some of the keys, like one written before the others were added
+        let scene = Scene::from_toml(
+            "version = 1\n\
+             [camera]\n\
+             re = \"-0.75\" # the seahorse valley\n\
+             im = \"0.1\"\n\
+             [fractal]\n\
+             max_iter = 500\n\
+             some_future_setting = [1, 2]\n",
+        )
+        .unwrap();
+        let default = Scene::default();
+        assert_eq!(scene.camera.center.to_f64(), Vector2d::new(-0.75, 0.1));
+        assert_eq!(scene.camera.zoom, default.camera.zoom);
+        assert_eq!(scene.max_iter, 500);
+        assert_eq!(scene.fractal_type, default.fractal_type);
+        assert_eq!(scene.color_scheme, default.color_scheme);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = Scene::from_toml("version = 1\n[fractal]\nmax_iter = lots\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        let error = Scene::from_toml("[camera]\nre = \"-0.75\"\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(Scene::from_toml("[fractal]\ntype = \"koch\"\n").is_err());
+        assert!(Scene::from_toml("[color\n").is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for (text, line) in [
+            ("[fractal]\nmax_iter = 0\n", 2),
+            ("[fractal]\nexponent = 3\nmax_iter = -5\n", 3),
+            ("[fractal]\nexponent = inf\n", 2),
+            ("[fractal]\nexponent = NaN\n", 2),
+            ("version = 1\n[color]\npalette_speed = -inf\n", 3),
+            ("[camera]\nzoom = \"0\"\n", 2),
+            ("[camera]\nrotation = inf\n", 2),
+            ("[fractal]\njulia_re = nan\n", 2),
+            ("[color]\nboundary_width = 0\n", 2),
+            ("[color]\nboundary_width = -1\n", 2),
+            ("[color]\npalette_c = [1, nan, 1]\n", 2),
+            ("[trap]\nre = NaN\n", 2),
+            ("[trap]\nsize = 0\n", 2),
+        ] {
+            assert_eq!(Scene::from_toml(text).unwrap_err().line, line, "{text}");
+        }
+        assert!(Scene::from_toml("[fractal]\nmax_iter = 1\nexponent = -2.5\n").is_ok());
+        assert_eq!(Scene::default().validate(), Ok(()));
+        let scene = Scene {
+            julia_pos: Vector2d::new(f64::NAN, 0.0),
+            ..Scene::default()
+        };
+        assert_eq!(
+            scene.validate().unwrap_err().message,
+            "fractal.julia_re: expected a finite number"
+        );
+    }
+
+    /// Strings with quotes and control characters are written on one line and read back unchanged
+    #[test]
+    fn strings_round_trip() {
+        let value = "a \"quoted\" C:\\path\nsecond line\r\tand a bell \u{7}";
+        let text = format!("name = {}\n", quote(value));
+        assert_eq!(text.lines().count(), 1, "{text}");
+        let table = Table::parse(&text).unwrap();
+        assert_eq!(table.get_string("name").unwrap().as_deref(), Some(value));
+        for escape in ["\\u12", "\\uD800", "\\x41"] {
+            let table = Table::parse(&format!("name = \"{escape}\"\n")).unwrap();
+            assert!(table.get_string("name").is_err(), "{escape}");
+        }
+    }
+
+    /// Values that aren't finite are written as their defaults, so the file can always be read back
+    #[test]
+    fn non_finite_values_are_not_written() {
+        let mut scene = Scene {
+            camera: CameraInfo {
+                rotation: f64::NAN,
+                ..Scene::default().camera
+            },
+            exponent: f32::INFINITY,
+            boundary_width: f32::NAN,
+            julia_pos: Vector2d::new(f64::NEG_INFINITY, 0.25),
+            ..Scene::default()
+        };
+        scene.color_scheme.a[1] = f32::NAN;
+        scene.trap.size = f32::INFINITY;
+        let text = scene.to_toml();
+        assert!(!text.contains("inf") && !text.contains("NaN"), "{text}");
+        let restored = Scene::from_toml(&text).unwrap();
+        let default = Scene::default();
+        assert_eq!(restored.camera.rotation, default.camera.rotation);
+        assert_eq!(restored.exponent, default.exponent);
+        assert_eq!(restored.julia_pos, Vector2d::new(default.julia_pos.x, 0.25));
+        assert_eq!(restored.color_scheme.a, default.color_scheme.a);
+        assert_eq!(restored.trap.size, default.trap.size);
+    }
+
+    /// A file name in the temporary directory that no other test or test run uses at the same time
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rust-mandel-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let scene = Scene {
+            camera: CameraInfo::parse(
+                "-0.743643887037158704752191506114774",
+                "0.131825904205311970493132056385139",
+                "1e-30".parse().unwrap(),
+                1.0,
+            )
+            .unwrap(),
+            fractal_type: FractalType::Tricorn,
+            ..Scene::default()
+        };
+        let path = temp_path("png-round-trip.png");
+        crate::export::write_png(&path, 2, 1, &[0; 8], Some(&scene)).unwrap();
+        let loaded = Scene::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().to_toml(), scene.to_toml());
+
+        let plain = temp_path("png-without-scene.png");
+        crate::export::write_png(&plain, 2, 1, &[0; 8], None).unwrap();
+        let loaded = Scene::load(&plain);
+        std::fs::remove_file(&plain).unwrap();
+        assert!(loaded.is_err());
+    }
+}
@@ -1,104 +1,351 @@
+use std::sync::Arc;
+
 use eframe::{
     egui,
     egui_wgpu::{self, RenderState},
     wgpu,
 };
 
-use crate::ColorScheme;
+use crate::floatexp::FloatExp;
+use crate::perturbation::ReferenceOrbit;
+use crate::vector2::Complex;
+use crate::{cpu, ColorScheme, ShadingType};
 
 const SHADER_SOURCE: &str = include_str!("shader.wgsl");
 
 /// Compile and link the shaders
 pub fn init(wgpu_render_state: &RenderState) {
-    let device = &wgpu_render_state.device;
+    let resources = RenderResources::new(&wgpu_render_state.device, wgpu_render_state.target_format);
 
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: Some("fractal"),
-        source: wgpu::ShaderSource::Wgsl(SHADER_SOURCE.into()),
-    });
+    // Because the graphics pipeline must have the same lifetime as the egui render pass,
+    // instead of storing the pipeline in our `Application` struct, we insert it into the
+    // `paint_callback_resources` type map, which is stored alongside the render pass.
+    wgpu_render_state.renderer.write().callback_resources.insert(resources);
+}
 
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("fractal"),
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::FRAGMENT,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            },
-            count: None,
-        }],
-    });
+/// Create a GPU device without a window, for rendering with [`render_image`] on headless machines.
+/// Returns [`None`] if there is no usable adapter.
+#[must_use]
+pub fn headless_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()
+}
 
-    let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("fractal"),
-        size: std::mem::size_of::<RenderCallback>() as u64,
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
+/// The largest tile [`render_image`] draws at once. Big images are split up so a single draw call doesn't run
+/// long enough for the driver to reset the GPU.
+const MAX_TILE_SIZE: u32 = 2048;
 
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some("fractal"),
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
+/// Render a `width` by `height` RGBA image offscreen, with 4 bytes per pixel in rows.
+///
+/// The resolution and offset of `uniforms` are replaced to draw the image in tiles.
+/// The histogram coloring needs the values of the whole image at once, so they are read back and colored on the
+/// CPU with [`cpu::equalize`].
+#[must_use]
+pub fn render_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    uniforms: &RenderCallback,
+    reference_orbits: &[Arc<ReferenceOrbit>],
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    if uniforms.shading_type == ShadingType::Histogram as u32 {
+        let data = render_tiles(device, queue, uniforms, reference_orbits, width, height, VALUES_FORMAT);
+        let values: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect();
+        return cpu::equalize(uniforms, &values);
+    }
+    // Unorm, like the egui surface, so the colors match the screen and the CPU renderer
+    render_tiles(
+        device,
+        queue,
+        uniforms,
+        reference_orbits,
+        width,
+        height,
+        wgpu::TextureFormat::Rgba8Unorm,
+    )
+}
 
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("fractal"),
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: &[],
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
+/// Render a `width` by `height` image offscreen in tiles and return its pixels in rows. With [`VALUES_FORMAT`]
+/// the values of the histogram coloring are rendered, otherwise the colors.
+fn render_tiles(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    uniforms: &RenderCallback,
+    reference_orbits: &[Arc<ReferenceOrbit>],
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> Vec<u8> {
+    let mut resources = RenderResources::new(device, format);
+    let tile_size = MAX_TILE_SIZE.min(device.limits().max_texture_dimension_2d);
+    let pixel_bytes = format.block_copy_size(None).unwrap();
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("export"),
+        size: wgpu::Extent3d {
+            width: tile_size.min(width),
+            height: tile_size.min(height),
+            depth_or_array_layers: 1,
         },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[Some(wgpu_render_state.target_format.into())],
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        multiview: None,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
     });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("fractal"),
-        layout: &bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: uniform_buffer.as_entire_binding(),
-        }],
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // Rows copied out of a texture have to be aligned
+    let padded_row = (tile_size.min(width) * pixel_bytes).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("export"),
+        size: padded_row as u64 * tile_size.min(height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
     });
 
-    // Because the graphics pipeline must have the same lifetime as the egui render pass,
-    // instead of storing the pipeline in our `Application` struct, we insert it into the
-    // `paint_callback_resources` type map, which is stored alongside the render pass.
-    wgpu_render_state
-        .renderer
-        .write()
-        .callback_resources
-        .insert(RenderResources {
-            pipeline,
-            bind_group,
-            uniform_buffer,
-        });
+    let mut image = vec![0; width as usize * height as usize * pixel_bytes as usize];
+    for tile_y in (0..height).step_by(tile_size as usize) {
+        for tile_x in (0..width).step_by(tile_size as usize) {
+            let tile_width = tile_size.min(width - tile_x);
+            let tile_height = tile_size.min(height - tile_y);
+            let callback = PaintCallback {
+                uniforms: RenderCallback {
+                    resolution: [width as f32, height as f32],
+                    // The shader subtracts the offset, so the tile is moved to its place in the image
+                    offset: [-(tile_x as f32), -(tile_y as f32)],
+                    ..*uniforms
+                },
+                reference_orbits: reference_orbits.to_vec(),
+            };
+            resources.prepare(device, queue, &callback);
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("export") });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("export"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                render_pass.set_viewport(0.0, 0.0, tile_width as f32, tile_height as f32, 0.0, 1.0);
+                if format == VALUES_FORMAT {
+                    resources
+                        .histogram
+                        .paint_values(&mut render_pass, &resources.bind_group);
+                } else {
+                    resources.paint(&mut render_pass);
+                }
+            }
+            encoder.copy_texture_to_buffer(
+                texture.as_image_copy(),
+                wgpu::ImageCopyBuffer {
+                    buffer: &readback,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(padded_row),
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: tile_width,
+                    height: tile_height,
+                    depth_or_array_layers: 1,
+                },
+            );
+            queue.submit([encoder.finish()]);
+
+            let slice = readback.slice(..);
+            slice.map_async(wgpu::MapMode::Read, |result| {
+                result.expect("failed to read the exported image");
+            });
+            device.poll(wgpu::Maintain::Wait);
+            {
+                let data = slice.get_mapped_range();
+                let row_bytes = (tile_width * pixel_bytes) as usize;
+                for (y, row) in data.chunks(padded_row as usize).take(tile_height as usize).enumerate() {
+                    let start = ((tile_y as usize + y) * width as usize + tile_x as usize) * pixel_bytes as usize;
+                    image[start..start + row_bytes].copy_from_slice(&row[..row_bytes]);
+                }
+            }
+            readback.unmap();
+        }
+    }
+    image
+}
+
+/// Create a storage buffer that can hold `len` reference orbit iterations
+fn create_orbit_buffer(device: &wgpu::Device, len: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("reference orbit"),
+        size: (len * std::mem::size_of::<[f32; 2]>()) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    orbit_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("fractal"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: orbit_buffer.as_entire_binding(),
+            },
+        ],
+    })
 }
 
 /// The render resources stored with wgpu
 struct RenderResources {
     pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
     uniform_buffer: wgpu::Buffer,
+    orbit_buffer: wgpu::Buffer,
+    /// The reference orbits currently stored in `orbit_buffer`, so they are only uploaded when they change
+    uploaded_orbits: Vec<Arc<ReferenceOrbit>>,
+    histogram: HistogramResources,
 }
 
 impl RenderResources {
-    /// Send the [`RenderCallback`] to the shader
-    fn prepare(&self, _device: &wgpu::Device, queue: &wgpu::Queue, info: &RenderCallback) {
-        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(info));
+    /// Compile the shaders and create the buffers, for drawing into a target with the given format
+    fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("fractal"),
+            source: wgpu::ShaderSource::Wgsl(SHADER_SOURCE.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("fractal"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("fractal"),
+            size: std::mem::size_of::<RenderCallback>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let orbit_buffer = create_orbit_buffer(device, 1);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("fractal"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("fractal"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(target_format.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let bind_group = create_bind_group(device, &bind_group_layout, &uniform_buffer, &orbit_buffer);
+        let histogram = HistogramResources::new(device, &shader, &bind_group_layout, target_format);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+            orbit_buffer,
+            uploaded_orbits: Vec::new(),
+            histogram,
+        }
+    }
+
+    /// Send the [`RenderCallback`] and reference orbits to the shader
+    fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, callback: &PaintCallback) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&callback.uniforms));
+
+        let orbits = &callback.reference_orbits;
+        if orbits.is_empty()
+            || (orbits.len() == self.uploaded_orbits.len()
+                && orbits.iter().zip(&self.uploaded_orbits).all(|(a, b)| Arc::ptr_eq(a, b)))
+        {
+            return;
+        }
+
+        let len: usize = orbits.iter().map(|orbit| orbit.len()).sum();
+        if (len * std::mem::size_of::<[f32; 2]>()) as u64 > self.orbit_buffer.size() {
+            // Grow in powers of two so slowly increasing the iteration count doesn't reallocate every frame
+            self.orbit_buffer = create_orbit_buffer(device, len.next_power_of_two());
+            self.bind_group = create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                &self.orbit_buffer,
+            );
+        }
+        // The orbits are stored one after another, in the order of `RenderCallback::references`
+        let mut start = 0;
+        for orbit in orbits {
+            let offset = (start * std::mem::size_of::<[f32; 2]>()) as u64;
+            queue.write_buffer(&self.orbit_buffer, offset, bytemuck::cast_slice(&orbit.orbit));
+            start += orbit.len();
+        }
+        self.uploaded_orbits.clone_from(orbits);
     }
 
     fn paint<'rp>(&'rp self, render_pass: &mut wgpu::RenderPass<'rp>) {
@@ -108,24 +355,415 @@ impl RenderResources {
     }
 }
 
+/// The number of bins of the histogram coloring, the same as in the shader
+pub const HISTOGRAM_BINS: usize = 4096;
+
+/// The format of the values of the histogram coloring, `[value, glitch state]` like `fs_values` returns them
+const VALUES_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+
+/// The side of the compute shader workgroups
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The resources of the histogram coloring. The values of the pixels are rendered into a texture first, the
+/// histogram of the frame is built from them with compute shaders and the pixels are colored in a second pass.
+struct HistogramResources {
+    values_pipeline: wgpu::RenderPipeline,
+    range_pipeline: wgpu::ComputePipeline,
+    histogram_pipeline: wgpu::ComputePipeline,
+    cdf_pipeline: wgpu::ComputePipeline,
+    color_pipeline: wgpu::RenderPipeline,
+    compute_layout: wgpu::BindGroupLayout,
+    color_layout: wgpu::BindGroupLayout,
+    histogram_buffer: wgpu::Buffer,
+    cdf_buffer: wgpu::Buffer,
+    /// The values of every pixel of the screen, so the color pass reads them at its own fragment coordinates
+    values: wgpu::Texture,
+    compute_bind_group: wgpu::BindGroup,
+    color_bind_group: wgpu::BindGroup,
+}
+
+impl HistogramResources {
+    /// Create the pipelines for the two passes, the second drawing into a target with the given format
+    fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        fractal_layout: &wgpu::BindGroupLayout,
+        target_format: wgpu::TextureFormat,
+    ) -> Self {
+        let (compute_layout, color_layout) = create_histogram_layouts(device);
+
+        let values_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("histogram values"),
+            bind_group_layouts: &[fractal_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("histogram"),
+            bind_group_layouts: &[fractal_layout, &compute_layout],
+            push_constant_ranges: &[],
+        });
+        let color_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("histogram color"),
+            bind_group_layouts: &[fractal_layout, &color_layout],
+            push_constant_ranges: &[],
+        });
+
+        let render_pipeline = |layout, entry_point, format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point,
+                    targets: &[Some(format.into())],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let compute_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&compute_pipeline_layout),
+                module: shader,
+                entry_point,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            })
+        };
+
+        // The smallest and biggest value and the counts of the bins
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("histogram"),
+            size: ((2 + HISTOGRAM_BINS) * std::mem::size_of::<u32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // The smallest and biggest value and the distribution at the start of every bin and the end
+        let cdf_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("histogram cdf"),
+            size: ((3 + HISTOGRAM_BINS) * std::mem::size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let values = create_values_texture(device, [1, 1]);
+        let (compute_bind_group, color_bind_group) = create_histogram_bind_groups(
+            device,
+            &compute_layout,
+            &color_layout,
+            &values,
+            &histogram_buffer,
+            &cdf_buffer,
+        );
+
+        Self {
+            values_pipeline: render_pipeline(&values_layout, "fs_values", VALUES_FORMAT),
+            range_pipeline: compute_pipeline("cs_range"),
+            histogram_pipeline: compute_pipeline("cs_histogram"),
+            cdf_pipeline: compute_pipeline("cs_cdf"),
+            color_pipeline: render_pipeline(&color_pipeline_layout, "fs_histogram", target_format),
+            compute_layout,
+            color_layout,
+            histogram_buffer,
+            cdf_buffer,
+            values,
+            compute_bind_group,
+            color_bind_group,
+        }
+    }
+
+    /// Render the values of the frame and build its histogram, for a screen of `size` pixels
+    fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        fractal_bind_group: &wgpu::BindGroup,
+        uniforms: &RenderCallback,
+        size: [u32; 2],
+    ) -> wgpu::CommandBuffer {
+        if size != [self.values.width(), self.values.height()] {
+            self.values = create_values_texture(device, size);
+            (self.compute_bind_group, self.color_bind_group) = create_histogram_bind_groups(
+                device,
+                &self.compute_layout,
+                &self.color_layout,
+                &self.values,
+                &self.histogram_buffer,
+                &self.cdf_buffer,
+            );
+        }
+        // The smallest value starts at the biggest it can be, so any value is smaller
+        let mut reset = vec![0_u32; 2 + HISTOGRAM_BINS];
+        reset[0] = u32::MAX;
+        queue.write_buffer(&self.histogram_buffer, 0, bytemuck::cast_slice(&reset));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("histogram"),
+        });
+        {
+            let view = self.values.create_view(&wgpu::TextureViewDescriptor::default());
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("histogram values"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            // Only the frame, rounded out to whole pixels so every pixel the color pass draws has a value
+            let [x, y] = uniforms.offset.map(|v| v.floor().max(0.0));
+            let right = (uniforms.offset[0] + uniforms.resolution[0]).ceil().min(size[0] as f32);
+            let bottom = (uniforms.offset[1] + uniforms.resolution[1]).ceil().min(size[1] as f32);
+            if right > x && bottom > y {
+                render_pass.set_viewport(x, y, right - x, bottom - y, 0.0, 1.0);
+                self.paint_values(&mut render_pass, fractal_bind_group);
+            }
+        }
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("histogram"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, fractal_bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.compute_bind_group, &[]);
+            let [groups_x, groups_y] = size.map(|v| v.div_ceil(WORKGROUP_SIZE));
+            compute_pass.set_pipeline(&self.range_pipeline);
+            compute_pass.dispatch_workgroups(groups_x, groups_y, 1);
+            compute_pass.set_pipeline(&self.histogram_pipeline);
+            compute_pass.dispatch_workgroups(groups_x, groups_y, 1);
+            compute_pass.set_pipeline(&self.cdf_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.finish()
+    }
+
+    /// Draw the values of the pixels, the first pass
+    fn paint_values<'rp>(&'rp self, render_pass: &mut wgpu::RenderPass<'rp>, fractal_bind_group: &'rp wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.values_pipeline);
+        render_pass.set_bind_group(0, fractal_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+
+    /// Draw the colors of the pixels from their values and the histogram, the second pass
+    fn paint<'rp>(&'rp self, render_pass: &mut wgpu::RenderPass<'rp>, fractal_bind_group: &'rp wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.color_pipeline);
+        render_pass.set_bind_group(0, fractal_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.color_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
+
+/// Create the texture the values of the histogram coloring are rendered into
+fn create_values_texture(device: &wgpu::Device, size: [u32; 2]) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("histogram values"),
+        size: wgpu::Extent3d {
+            width: size[0].max(1),
+            height: size[1].max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: VALUES_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+/// Create the layouts of the bind groups of the compute shaders and of the color pass of the histogram coloring.
+/// The cumulative distribution is written by the compute shaders but only read when coloring.
+fn create_histogram_layouts(device: &wgpu::Device) -> (wgpu::BindGroupLayout, wgpu::BindGroupLayout) {
+    let values_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    };
+    let storage_entry = |binding, visibility, read_only| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("histogram"),
+        entries: &[
+            values_entry(0),
+            storage_entry(1, wgpu::ShaderStages::COMPUTE, false),
+            storage_entry(2, wgpu::ShaderStages::COMPUTE, false),
+        ],
+    });
+    let color_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("histogram color"),
+        entries: &[values_entry(0), storage_entry(3, wgpu::ShaderStages::FRAGMENT, true)],
+    });
+    (compute_layout, color_layout)
+}
+
+/// Create the bind groups of the compute shaders and of the color pass of the histogram coloring
+fn create_histogram_bind_groups(
+    device: &wgpu::Device,
+    compute_layout: &wgpu::BindGroupLayout,
+    color_layout: &wgpu::BindGroupLayout,
+    values: &wgpu::Texture,
+    histogram_buffer: &wgpu::Buffer,
+    cdf_buffer: &wgpu::Buffer,
+) -> (wgpu::BindGroup, wgpu::BindGroup) {
+    let view = values.create_view(&wgpu::TextureViewDescriptor::default());
+    let compute = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("histogram"),
+        layout: compute_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: histogram_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: cdf_buffer.as_entire_binding(),
+            },
+        ],
+    });
+    let color = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("histogram color"),
+        layout: color_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: cdf_buffer.as_entire_binding(),
+            },
+        ],
+    });
+    (compute, color)
+}
+
 /// The data sent to the GPU. It must match the shader struct exactly
 /// and cannot have any padding. ([`bytemuck::Pod`] prevents it from compiling if it does)
 #[repr(C, align(16))]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct RenderCallback {
-    pub pos: [f32; 2],
-    pub zoom: f32,
-    pub _p0: [u8; 4],
+    /// The center of the screen as double-floats, `[re hi, re lo, im hi, im lo]`, see [`split_f64`]
+    pub center: [f32; 4],
+    /// The mantissa of the zoom as a double-float, `[hi, lo]`
+    pub zoom: [f32; 2],
     pub resolution: [f32; 2],
     pub offset: [f32; 2],
     pub max_iter: i32,
     pub exponent: f32,
     pub fractal_type: u32,
     pub shading_type: u32,
+    /// Always `1.0`, used to stop the shader compiler from optimizing away the double-float error terms
+    pub one: f32,
+    /// The power of two the zoom mantissa is multiplied by, so zooms beyond the range of [`f32`] can be sent
+    pub zoom_exp: i32,
     pub color_scheme: ColorSchemeCallback,
     pub palette_speed: f32,
     pub flags: u32,
     pub julia_pos: [f32; 2],
+    /// The number of valid entries in `references`
+    pub ref_count: u32,
+    /// A pixel is glitched when `|z|^2` drops below this times `|z_ref|^2`, zero turns the detection off
+    pub glitch_tolerance: f32,
+    /// The number of iterations skipped with the series approximation, only for the first reference
+    pub series_skip: u32,
+    pub _p0: u32,
+    /// The series coefficients `[A, B, C]`, see [`pack_complex_exp`]
+    pub series: [[f32; 4]; 3],
+    /// The reference orbits stored one after another in the orbit buffer. Pixels that are glitched with one
+    /// reference are iterated again with the next.
+    pub references: [ReferenceCallback; MAX_REFERENCES],
+    /// The cosine and sine of the camera rotation, see [`Viewport::rotation`](crate::viewport::Viewport::rotation)
+    pub rotation: [f32; 2],
+    /// How far from the set the distance shading darkens the colors, in pixels
+    pub boundary_width: f32,
+    /// How much of the palette color the lighting shading keeps
+    pub light_blend: f32,
+    /// The unit vector pointing at the light, `[x, y, z, specular strength]`, see [`Light`](crate::Light)
+    pub light: [f32; 4],
+    /// The orbit trap, `[center re, center im, cos angle, sin angle]`, see [`OrbitTrap`](crate::OrbitTrap)
+    pub trap: [f32; 4],
+    pub trap_size: f32,
+    pub trap_shape: u32,
+    pub trap_coloring: u32,
+    pub stripe_frequency: f32,
+    /// How far the average colorings move through the palette, see [`Averages`](crate::Averages)
+    pub average_strength: f32,
+    pub _p2: f32,
+    pub _p3: [f32; 2],
+}
+
+/// The most reference orbits a single frame can use
+pub const MAX_REFERENCES: usize = 8;
+
+/// Where a reference orbit is stored in the orbit buffer, and where it is on the screen
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ReferenceCallback {
+    /// The offset from the reference point to the center of the screen, divided by the zoom
+    pub offset: [f32; 2],
+    /// The index of iteration 0 in the orbit buffer
+    pub start: u32,
+    /// The number of valid iterations
+    pub len: u32,
+}
+
+/// Split an [`f64`] into two [`f32`] whose sum is (almost) the original value,
+/// which is how the shader's double-float arithmetic stores numbers
+#[must_use]
+pub fn split_f64(value: f64) -> [f32; 2] {
+    let hi = value as f32;
+    let lo = (value - hi as f64) as f32;
+    [hi, lo]
+}
+
+/// Pack a complex number with an extended exponent the way the shader's `ComplexExp` stores it,
+/// `[re, im, exponent, 0]` where the mantissas share the exponent
+#[must_use]
+pub fn pack_complex_exp(value: Complex<FloatExp>) -> [f32; 4] {
+    let exponent = [value.re, value.im]
+        .into_iter()
+        .filter(|v| *v != FloatExp::ZERO)
+        .map(FloatExp::exponent)
+        .max()
+        .unwrap_or(0);
+    let scale = FloatExp::new(1.0, -exponent);
+    [
+        (value.re * scale).to_f64() as f32,
+        (value.im * scale).to_f64() as f32,
+        exponent as f32,
+        0.0,
+    ]
 }
 
 /// The callback containing the palette data. The extra f32 in each vec3 is for padding.
@@ -154,18 +792,35 @@ impl From<ColorScheme> for ColorSchemeCallback {
     }
 }
 
-impl egui_wgpu::CallbackTrait for RenderCallback {
+/// Everything needed to paint the fractal in an egui paint callback
+#[derive(Debug, Clone)]
+pub struct PaintCallback {
+    pub uniforms: RenderCallback,
+    /// The reference orbits for perturbation rendering. An empty list keeps whatever was uploaded last.
+    pub reference_orbits: Vec<Arc<ReferenceOrbit>>,
+}
+
+impl egui_wgpu::CallbackTrait for PaintCallback {
     fn prepare(
         &self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        _screen_descriptor: &egui_wgpu::ScreenDescriptor,
+        screen_descriptor: &egui_wgpu::ScreenDescriptor,
         _egui_encoder: &mut wgpu::CommandEncoder,
         resources: &mut egui_wgpu::CallbackResources,
     ) -> Vec<wgpu::CommandBuffer> {
-        let resources: &RenderResources = resources.get().unwrap();
+        let resources: &mut RenderResources = resources.get_mut().unwrap();
         resources.prepare(device, queue, self);
-        Vec::new()
+        if self.uniforms.shading_type != ShadingType::Histogram as u32 {
+            return Vec::new();
+        }
+        vec![resources.histogram.prepare(
+            device,
+            queue,
+            &resources.bind_group,
+            &self.uniforms,
+            screen_descriptor.size_in_pixels,
+        )]
     }
 
     fn paint<'a>(
@@ -175,6 +830,10 @@ impl egui_wgpu::CallbackTrait for RenderCallback {
         resources: &'a egui_wgpu::CallbackResources,
     ) {
         let resources: &RenderResources = resources.get().unwrap();
-        resources.paint(render_pass);
+        if self.uniforms.shading_type == ShadingType::Histogram as u32 {
+            resources.histogram.paint(render_pass, &resources.bind_group);
+        } else {
+            resources.paint(render_pass);
+        }
     }
 }
@@ -18,19 +18,101 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 
+// the reference orbits used for perturbation one after another, only read when bit 1 of the flags is set
+@group(0) @binding(1)
+var<storage, read> reference_orbit: array<vec2<f32>>;
+
+// the values of the pixels for the histogram coloring, written by fs_values, see histogram_color
+@group(1) @binding(0)
+var values: texture_2d<f32>;
+
+// the histogram of the escaped values of the frame, built by cs_range and cs_histogram
+@group(1) @binding(1)
+var<storage, read_write> histogram: Histogram;
+
+// the cumulative distribution of the histogram, written by cs_cdf and read by fs_histogram
+@group(1) @binding(2)
+var<storage, read_write> cdf_out: Cdf;
+@group(1) @binding(3)
+var<storage, read> cdf: Cdf;
+
+// the index in uniforms.references of the reference the current pixel is iterated against
+var<private> reference_index: i32;
+// whether the current pixel is glitched with that reference
+var<private> glitched: bool;
+// the derivative of z along the real axis of the normalized coordinates, only tracked if bit 6 of the flags is set
+var<private> derivative: vec2<f32>;
+// z when the pixel escaped, or after the last iteration
+var<private> final_z: vec2<f32>;
+// the distance between pixels in the normalized coordinates
+var<private> pixel_size: f32;
+// the closest the orbit came to the trap so far, TRAP_MISSED if it never did, and the iteration it was at
+var<private> trap_distance: f32;
+var<private> trap_iteration: i32;
+// the sum and count of the terms of the average colorings, and the last term
+var<private> average_sum: f32;
+var<private> average_count: i32;
+var<private> average_last: f32;
+// the average interpolated across the last iteration, set when the pixel escapes
+var<private> average: f32;
+// z of the previous two iterations, for the curvature average
+var<private> previous_z: vec2<f32>;
+var<private> previous_z2: vec2<f32>;
+
 struct Uniforms {
-    pos: vec2<f32>,
-    zoom: f32,
+    // double-float camera, center is (re hi, re lo, im hi, im lo) and zoom is (hi, lo)
+    // the zoom is only the mantissa, the actual zoom is zoom * 2^zoom_exp
+    center: vec4<f32>,
+    zoom: vec2<f32>,
     resolution: vec2<f32>,
     offset: vec2<f32>,
     max_iter: i32,
     exponent: f32,
     fractal_type: u32,
     shading_type: u32,
+    one: f32,
+    zoom_exp: i32,
     color_scheme: ColorScheme,
     palette_speed: f32,
     flags: u32,
     julia_pos: vec2<f32>,
+    ref_count: u32,
+    glitch_tolerance: f32,
+    // iterations skipped by the series approximation, and its coefficients as (re, im, exponent, unused)
+    // only used with the first reference
+    series_skip: u32,
+    _p0: u32,
+    series: array<vec4<f32>, 3>,
+    references: array<Reference, 8>,
+    // cosine and sine of the counterclockwise camera rotation
+    rotation: vec2<f32>,
+    // how far from the set the distance shading darkens the colors, in pixels
+    boundary_width: f32,
+    // how much of the palette color the lighting shading keeps
+    light_blend: f32,
+    // the unit vector pointing at the light, and the specular strength in w
+    light: vec4<f32>,
+    // the orbit trap center, and the cosine and sine of its angle
+    trap: vec4<f32>,
+    trap_size: f32,
+    // 0 point, 1 line, 2 cross, 3 circle, 4 stalks
+    trap_shape: u32,
+    // 0 distance, 1 iteration
+    trap_coloring: u32,
+    // how many stripes the stripe average has per turn around the origin
+    stripe_frequency: f32,
+    // how far the average colorings move through the palette
+    average_strength: f32,
+    _p2: f32,
+    _p3: vec2<f32>,
+}
+
+// a reference orbit in the orbit buffer. the offset is from the reference point to the center of the screen,
+// divided by the zoom
+struct Reference {
+    offset: vec2<f32>,
+    start: u32,
+    len: u32,
 }
 
 // color scheme data
@@ -71,6 +153,8 @@ fn powf_complex(a: vec2<f32>, n: f32) -> vec2<f32> {
     return conjugate_complex(z) / (z.x * z.x + z.y * z.y);
 }
 
+const TAU: f32 = 6.283185307179586;
+
 fn exp_complex(z: vec2<f32>) -> vec2<f32> {
     return exp(z.x) * vec2<f32>(cos(z.y), sin(z.y));
 }
@@ -85,32 +169,418 @@ fn mandellike(pos: vec2<f32>, fractal_type: u32) -> f32 {
         c = pos;
     }
 
-    let escape = 4.0;
+    derivative = start_derivative();
+    return mandellike_loop(z, c, 0, fractal_type);
+}
+
+// the derivative of the starting z, which is the pixel position for julia sets and zero otherwise
+fn start_derivative() -> vec2<f32> {
+    if (uniforms.flags & u32(1)) == u32(1) {
+        return vec2<f32>(ldexp_flush(uniforms.zoom, uniforms.zoom_exp).x, 0.0);
+    }
+    return vec2<f32>(0.0, 0.0);
+}
+
+// the derivative after iterating z, if bit 6 of the flags is set.
+// for the burning ship and the tricorn it's the derivative along the real axis only, they aren't holomorphic
+fn derivative_step(z: vec2<f32>, d: vec2<f32>, fractal_type: u32) -> vec2<f32> {
+    if (uniforms.flags & u32(64)) == u32(0) {
+        return d;
+    }
+    let next = derivative_iter(z, d, fractal_type);
+    if (uniforms.flags & u32(1)) == u32(1) {
+        return next;
+    }
+    // c moves with the pixel
+    return next + vec2<f32>(ldexp_flush(uniforms.zoom, uniforms.zoom_exp).x, 0.0);
+}
+
+// the derivative of mandellike_iter at z without the constant, applied to d. it's linear in d
+fn derivative_iter(z: vec2<f32>, d: vec2<f32>, fractal_type: u32) -> vec2<f32> {
+    switch fractal_type {
+        case u32(0) {
+            return mul_complex(power_derivative(z), d);
+        }
+        case u32(1) {
+            // folding z into the first quadrant flips the directions of the folded components
+            let folded = select(vec2<f32>(1.0), vec2<f32>(-1.0), z < vec2<f32>(0.0)) * d;
+            return mul_complex(power_derivative(abs(z)), folded);
+        }
+        case u32(2) {
+            return conjugate_complex(mul_complex(power_derivative(z), d));
+        }
+        case default {
+            return d;
+        }
+    }
+}
+
+// n z^(n - 1)
+fn power_derivative(z: vec2<f32>) -> vec2<f32> {
+    if uniforms.exponent == 2.0 {
+        return 2.0 * z;
+    }
+    return uniforms.exponent * powf_complex(z, uniforms.exponent - 1.0);
+}
+
+// iterate z from iteration `start` until it escapes or reaches max_iter
+fn mandellike_loop(z_start: vec2<f32>, c: vec2<f32>, start: i32, fractal_type: u32) -> f32 {
+    let escape = escape_radius();
     let escape_sq = escape * escape;
+
+    var z = z_start;
+    for (var i: i32 = start; i < uniforms.max_iter; i = i + 1) {
+        average_step(z, c, i);
+        var zn_sq = z.x * z.x + z.y * z.y;
+        if zn_sq >= escape_sq {
+            final_z = z;
+            return escape_value(i, zn_sq);
+        }
+
+        trap_step(z, i);
+        derivative = derivative_step(z, derivative, fractal_type);
+        z = mandellike_iter(z, c, fractal_type);
+    }
+    final_z = z;
+    return -1.0;
+}
+
+// the value passed to the palette for a point that escaped at iteration i
+fn escape_value(i: i32, zn_sq: f32) -> f32 {
+    let escape = escape_radius();
     let log_escape = log(escape);
     let log_exponent = log(uniforms.exponent);
+    // how far past the escape radius z went, from 0 when it's just outside to 1 when the previous z was
+    let fraction = saturate(log(log(zn_sq) / (2.0 * log_escape)) / log_exponent);
 
-    for (var i: i32 = 0; i < uniforms.max_iter; i = i + 1) {
+    switch uniforms.shading_type {
+        case u32(0) {
+            return max(f32(i) - 2.0, 0.0);
+        }
+        case u32(1), u32(2), u32(3), u32(4), u32(8) {
+            return max(f32(i) - 2.0 - fraction, 0.0);
+        }
+        case u32(5), u32(6), u32(7) {
+            average = average_value(fraction);
+            return max(f32(i) - 2.0 - fraction, 0.0);
+        }
+        case default {
+            return 0.0;
+        }
+    }
+}
+
+// iterate only the difference between this pixel and the reference orbit
+// pos is the low precision position of the pixel and delta is its offset from the reference point
+fn mandellike_perturbed(pos: vec2<f32>, delta: vec2<f32>, fractal_type: u32) -> f32 {
+    var dz = vec2<f32>(0.0, 0.0);
+    var dc = delta;
+    var c = pos;
+    if (uniforms.flags & u32(1)) == u32(1) {
+        dz = delta;
+        dc = vec2<f32>(0.0, 0.0);
+        c = uniforms.julia_pos;
+    }
+
+    derivative = start_derivative();
+    var start: i32 = 0;
+    if uniforms.series_skip > u32(0) && reference_index == 0 {
+        dz = cexp_to_vec2(series_delta(cexp_new(delta, 0)));
+        derivative = cexp_to_vec2(series_derivative(cexp_new(delta, 0)));
+        start = i32(uniforms.series_skip);
+    }
+    return perturbed_loop(dz, dc, c, start, fractal_type);
+}
+
+// continue a perturbed orbit from iteration `start`
+fn perturbed_loop(dz_start: vec2<f32>, dc: vec2<f32>, c: vec2<f32>, start: i32, fractal_type: u32) -> f32 {
+    let escape = escape_radius();
+    let escape_sq = escape * escape;
+    let last = last_reference_iteration();
+
+    var dz = dz_start;
+    var i: i32 = start;
+    var z = vec2<f32>(0.0, 0.0);
+    for (; i < uniforms.max_iter; i = i + 1) {
+        let z_ref = reference_z(i);
+        z = z_ref + dz;
+        average_step(z, c, i);
         var zn_sq = z.x * z.x + z.y * z.y;
         if zn_sq >= escape_sq {
-            var out: f32;
-            switch uniforms.shading_type {
-                case u32(0) {
-                    return max(f32(i) - 2.0, 0.0);
-                }
-                case u32(1) {
-                    return max(f32(i) - 2.0 - saturate(log(log(zn_sq) / (2.0 * log_escape)) / log_exponent), 0.0);
-                }
-                case default {
-                    return 0.0;
-                }
+            final_z = z;
+            return escape_value(i, zn_sq);
+        }
+
+        // pauldelbrot's criterion: once z is much closer to zero than the reference,
+        // dz is too big compared to z for the precision of f32
+        if !glitched && zn_sq < uniforms.glitch_tolerance * dot(z_ref, z_ref) {
+            glitched = true;
+            if reference_index + 1 < i32(uniforms.ref_count) {
+                return GLITCHED;
             }
-            
         }
 
-        z = mandellike_iter(z, c, fractal_type);
+        if i >= last {
+            // finish outside of this loop, some drivers miscompile the nested loop when it is called in here
+            break;
+        }
+
+        trap_step(z, i);
+        derivative = derivative_step(z, derivative, fractal_type);
+        dz = perturb(z_ref, dz, fractal_type) + dc;
     }
-    return -1.0;
+    if i >= uniforms.max_iter {
+        final_z = z;
+        return -1.0;
+    }
+    // the reference escaped before this pixel did, so finish the orbit without it
+    trap_step(z, i);
+    derivative = derivative_step(z, derivative, fractal_type);
+    return mandellike_loop(mandellike_iter(z, c, fractal_type), c, i + 1, fractal_type);
+}
+
+// returned instead of the escape value when the pixel has to be iterated again with the next reference
+const GLITCHED: f32 = -2.0;
+
+// iteration i of the current reference orbit
+fn reference_z(i: i32) -> vec2<f32> {
+    return reference_orbit[uniforms.references[reference_index].start + u32(i)];
+}
+
+// the index of the last usable value in the current reference orbit
+fn last_reference_iteration() -> i32 {
+    return i32(uniforms.references[reference_index].len) - 1;
+}
+
+// f(z_ref + dz) - f(z_ref) for the iteration function f of each fractal, without the constant
+fn perturb(z_ref: vec2<f32>, dz: vec2<f32>, fractal_type: u32) -> vec2<f32> {
+    switch fractal_type {
+        case u32(0) {
+            return perturb_pow(z_ref, dz);
+        }
+        case u32(1) {
+            return perturb_burning_ship(z_ref, dz);
+        }
+        case u32(2) {
+            return perturb_pow(conjugate_complex(z_ref), conjugate_complex(dz));
+        }
+        case default {
+            return dz;
+        }
+    }
+}
+
+// (z_ref + dz)^n - z_ref^n for an integer exponent, expanded with the binomial theorem
+// and evaluated with horner's method so no term suffers from cancellation
+fn perturb_pow(z_ref: vec2<f32>, dz: vec2<f32>) -> vec2<f32> {
+    let n = i32(uniforms.exponent);
+    var acc = vec2<f32>(1.0, 0.0);
+    var z_pow = vec2<f32>(1.0, 0.0);
+    var binomial = 1.0;
+    for (var k: i32 = n - 1; k >= 1; k = k - 1) {
+        z_pow = mul_complex(z_pow, z_ref);
+        binomial = binomial * f32(k + 1) / f32(n - k);
+        acc = mul_complex(acc, dz) + binomial * z_pow;
+    }
+    return mul_complex(acc, dz);
+}
+
+// the burning ship squares (|x|, |y|), so the imaginary part needs the difference of two absolute values
+fn perturb_burning_ship(z_ref: vec2<f32>, dz: vec2<f32>) -> vec2<f32> {
+    let x = (2.0 * z_ref.x + dz.x) * dz.x - (2.0 * z_ref.y + dz.y) * dz.y;
+    let y = 2.0 * diffabs(z_ref.x * z_ref.y, z_ref.x * dz.y + dz.x * z_ref.y + dz.x * dz.y);
+    return vec2<f32>(x, y);
+}
+
+// |c + d| - |c| without catastrophic cancellation
+fn diffabs(c: f32, d: f32) -> f32 {
+    let cd = c + d;
+    if c >= 0.0 {
+        if cd >= 0.0 {
+            return d;
+        }
+        return -(2.0 * c + d);
+    }
+    if cd > 0.0 {
+        return 2.0 * c + d;
+    }
+    return -d;
+}
+
+// extended exponent numbers, for deltas too small for f32. the value is m * 2^e
+// both components share the exponent, which is chosen so the larger one is in [0.5, 1)
+struct ComplexExp {
+    m: vec2<f32>,
+    e: i32,
+}
+
+// the exponent of zero, low enough that adding it to anything leaves the other number unchanged
+const ZERO_EXP: i32 = -1000000;
+
+fn cexp_new(m: vec2<f32>, e: i32) -> ComplexExp {
+    let big = max(abs(m.x), abs(m.y));
+    if big == 0.0 {
+        return ComplexExp(vec2<f32>(0.0, 0.0), ZERO_EXP);
+    }
+    let shift = frexp(big).exp;
+    return ComplexExp(ldexp(m, vec2<i32>(-shift)), e + shift);
+}
+
+// a * 2^e, flushed to zero below the normal range of f32, where ldexp is undefined
+fn ldexp_flush(a: vec2<f32>, e: i32) -> vec2<f32> {
+    if e < -126 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    return ldexp(a, vec2<i32>(min(e, 127)));
+}
+
+fn cexp_to_vec2(a: ComplexExp) -> vec2<f32> {
+    return ldexp_flush(a.m, a.e);
+}
+
+fn cexp_mul(a: ComplexExp, b: ComplexExp) -> ComplexExp {
+    return cexp_new(mul_complex(a.m, b.m), a.e + b.e);
+}
+
+fn cexp_add(a: ComplexExp, b: ComplexExp) -> ComplexExp {
+    // align the smaller number to the exponent of the larger one, unless it is too small to matter
+    let shift = a.e - b.e;
+    if shift > 32 {
+        return a;
+    }
+    if shift < -32 {
+        return b;
+    }
+    if shift >= 0 {
+        return cexp_new(a.m + ldexp(b.m, vec2<i32>(-shift)), a.e);
+    }
+    return cexp_new(ldexp(a.m, vec2<i32>(shift)) + b.m, b.e);
+}
+
+// mandellike_perturbed with extended exponent deltas, for zooms where the pixel spacing is too small for f32.
+// the deltas only stay extended until they are big enough for the normal perturbation loop
+fn mandellike_perturbed_exp(pos: vec2<f32>, delta: ComplexExp, fractal_type: u32) -> f32 {
+    var dz = cexp_new(vec2<f32>(0.0, 0.0), 0);
+    var dc = delta;
+    var c = pos;
+    if (uniforms.flags & u32(1)) == u32(1) {
+        dz = delta;
+        dc = cexp_new(vec2<f32>(0.0, 0.0), 0);
+        c = uniforms.julia_pos;
+    }
+
+    // the derivative is as small as the deltas, so it's extended too
+    let zoom = cexp_new(vec2<f32>(uniforms.zoom.x, 0.0), uniforms.zoom_exp);
+    var d = cexp_new(vec2<f32>(0.0, 0.0), 0);
+    if (uniforms.flags & u32(1)) == u32(1) {
+        d = zoom;
+    }
+    var i: i32 = 0;
+    if uniforms.series_skip > u32(0) && reference_index == 0 {
+        dz = series_delta(delta);
+        d = series_derivative(delta);
+        i = i32(uniforms.series_skip);
+    }
+
+    // while dz is this small, the pixel can't escape before the reference does
+    let last = last_reference_iteration();
+    for (; i < last && dz.e < -64; i = i + 1) {
+        let z = reference_z(i) + cexp_to_vec2(dz);
+        average_step(z, c, i);
+        trap_step(z, i);
+        if (uniforms.flags & u32(64)) == u32(64) {
+            d = cexp_new(derivative_iter(z, d.m, fractal_type), d.e);
+            if (uniforms.flags & u32(1)) == u32(0) {
+                d = cexp_add(d, zoom);
+            }
+        }
+        dz = cexp_add(perturb_exp(reference_z(i), dz, fractal_type), dc);
+    }
+    derivative = cexp_to_vec2(d);
+    return perturbed_loop(cexp_to_vec2(dz), cexp_to_vec2(dc), c, i, fractal_type);
+}
+
+// the delta after the iterations skipped by the series approximation, A d + B d^2 + C d^3
+// where d is dc, or the starting dz for julia sets
+fn series_delta(d: ComplexExp) -> ComplexExp {
+    let a = series_coefficient(0);
+    let b = series_coefficient(1);
+    let c = series_coefficient(2);
+    return cexp_mul(cexp_add(cexp_mul(cexp_add(cexp_mul(c, d), b), d), a), d);
+}
+
+// the derivative of the delta after the skipped iterations along the normalized coordinates, (A + 2B d + 3C d^2) zoom
+fn series_derivative(d: ComplexExp) -> ComplexExp {
+    let a = series_coefficient(0);
+    let b = cexp_mul(series_coefficient(1), cexp_new(vec2<f32>(2.0, 0.0), 0));
+    let c = cexp_mul(series_coefficient(2), cexp_new(vec2<f32>(3.0, 0.0), 0));
+    let zoom = cexp_new(vec2<f32>(uniforms.zoom.x, 0.0), uniforms.zoom_exp);
+    return cexp_mul(cexp_add(cexp_mul(cexp_add(cexp_mul(c, d), b), d), a), zoom);
+}
+
+fn series_coefficient(i: i32) -> ComplexExp {
+    let coefficient = uniforms.series[i];
+    return cexp_new(coefficient.xy, i32(coefficient.z));
+}
+
+// perturb for an extended exponent delta
+fn perturb_exp(z_ref: vec2<f32>, dz: ComplexExp, fractal_type: u32) -> ComplexExp {
+    switch fractal_type {
+        case u32(0) {
+            return perturb_pow_exp(z_ref, dz);
+        }
+        case u32(1) {
+            return perturb_burning_ship_exp(z_ref, dz);
+        }
+        case u32(2) {
+            return perturb_pow_exp(conjugate_complex(z_ref), ComplexExp(conjugate_complex(dz.m), dz.e));
+        }
+        case default {
+            return dz;
+        }
+    }
+}
+
+fn perturb_pow_exp(z_ref: vec2<f32>, dz: ComplexExp) -> ComplexExp {
+    let n = i32(uniforms.exponent);
+    var acc = cexp_new(vec2<f32>(1.0, 0.0), 0);
+    var z_pow = vec2<f32>(1.0, 0.0);
+    var binomial = 1.0;
+    for (var k: i32 = n - 1; k >= 1; k = k - 1) {
+        z_pow = mul_complex(z_pow, z_ref);
+        binomial = binomial * f32(k + 1) / f32(n - k);
+        acc = cexp_add(cexp_mul(acc, dz), cexp_new(binomial * z_pow, 0));
+    }
+    return cexp_mul(acc, dz);
+}
+
+// the terms are computed as mantissas with the exponent of dz
+fn perturb_burning_ship_exp(z_ref: vec2<f32>, dz: ComplexExp) -> ComplexExp {
+    let d = cexp_to_vec2(dz);
+    let x = (2.0 * z_ref.x + d.x) * dz.m.x - (2.0 * z_ref.y + d.y) * dz.m.y;
+    let y = 2.0 * diffabs_exp(z_ref.x * z_ref.y, z_ref.x * dz.m.y + dz.m.x * z_ref.y + dz.m.x * d.y, dz.e);
+    return cexp_new(vec2<f32>(x, y), dz.e);
+}
+
+// diffabs for d * 2^e, returning the mantissa for the same exponent
+fn diffabs_exp(c: f32, d: f32, e: i32) -> f32 {
+    // the sign of c + d, taken from d if it is too small to change c
+    var cd = c + ldexp_flush(vec2<f32>(d, 0.0), e).x;
+    if cd == 0.0 {
+        cd = d;
+    }
+    // 2c only has to be scaled when c + d and c have different signs, so c is not bigger than d
+    let c2 = ldexp_flush(vec2<f32>(2.0 * c, 0.0), -e).x;
+    if c >= 0.0 {
+        if cd >= 0.0 {
+            return d;
+        }
+        return -(c2 + d);
+    }
+    if cd > 0.0 {
+        return c2 + d;
+    }
+    return -d;
 }
 
 fn mandellike_iter(z: vec2<f32>, c: vec2<f32>, fractal_type: u32) -> vec2<f32> {
@@ -146,29 +616,531 @@ fn tricorn(z: vec2<f32>, c: vec2<f32>) -> vec2<f32> {
     return powf_complex(conjugate_complex(z), uniforms.exponent) + c;
 }
 
+// double-float (df64) arithmetic: a value is stored as the unevaluated sum x + y of two f32,
+// which gives about 48 bits of mantissa. complex df64 numbers are stored as (re hi, re lo, im hi, im lo).
+// based on https://andrewthall.org/papers/df64_qf128.pdf
+//
+// shader compilers are allowed to rearrange float math as if it was exact, which folds the rounding error
+// terms below to zero. multiplying by uniforms.one, which the compiler can't know is 1.0, prevents that.
+// fma isn't used for the same reason, it isn't guaranteed to be fused on every backend.
+
+// a + b with the rounding error in y
+fn two_sum(a: f32, b: f32) -> vec2<f32> {
+    let s = a + b;
+    let bb = s * uniforms.one - a;
+    let e = (a - (s - bb)) + (b - bb);
+    return vec2<f32>(s, e);
+}
+
+// a + b with the rounding error in y, requires |a| >= |b|
+fn quick_two_sum(a: f32, b: f32) -> vec2<f32> {
+    let s = a + b;
+    let e = b - (s * uniforms.one - a);
+    return vec2<f32>(s, e);
+}
+
+// split a into two halves with 12 bits of mantissa each, so their products are exact
+fn split(a: f32) -> vec2<f32> {
+    let t = 4097.0 * a;
+    let hi = t * uniforms.one - (t - a);
+    return vec2<f32>(hi, a - hi);
+}
+
+// a * b with the rounding error in y
+fn two_prod(a: f32, b: f32) -> vec2<f32> {
+    let p = a * b;
+    let a_split = split(a);
+    let b_split = split(b);
+    let e = ((a_split.x * b_split.x - p) + a_split.x * b_split.y + a_split.y * b_split.x) + a_split.y * b_split.y;
+    return vec2<f32>(p, e);
+}
+
+fn df_add(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    var s = two_sum(a.x, b.x);
+    let t = two_sum(a.y, b.y);
+    s.y += t.x;
+    s = quick_two_sum(s.x, s.y);
+    s.y += t.y;
+    return quick_two_sum(s.x, s.y);
+}
+
+fn df_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    var p = two_prod(a.x, b.x);
+    p.y += a.x * b.y + a.y * b.x;
+    return quick_two_sum(p.x, p.y);
+}
+
+fn df_abs(a: vec2<f32>) -> vec2<f32> {
+    if a.x < 0.0 {
+        return -a;
+    }
+    return a;
+}
+
+fn add_complex_df(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(df_add(a.xy, b.xy), df_add(a.zw, b.zw));
+}
+
+fn mul_complex_df(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    let re = df_add(df_mul(a.xy, b.xy), -df_mul(a.zw, b.zw));
+    let im = df_add(df_mul(a.xy, b.zw), df_mul(a.zw, b.xy));
+    return vec4<f32>(re, im);
+}
+
+fn square_complex_df(a: vec4<f32>) -> vec4<f32> {
+    let re = df_add(df_mul(a.xy, a.xy), -df_mul(a.zw, a.zw));
+    let im = df_mul(df_mul(a.xy, a.zw), vec2<f32>(2.0, 0.0));
+    return vec4<f32>(re, im);
+}
+
+// raise to a positive integer power
+fn pow_complex_df(a: vec4<f32>, n: i32) -> vec4<f32> {
+    if n == 2 {
+        return square_complex_df(a);
+    }
+    var z = a;
+    for (var i: i32 = 1; i < n; i = i + 1) {
+        z = mul_complex_df(z, a);
+    }
+    return z;
+}
+
+fn mandellike_df(pos: vec4<f32>, fractal_type: u32) -> f32 {
+    var z = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    var c: vec4<f32>;
+    if (uniforms.flags & u32(1)) == u32(1) {
+        z = pos;
+        c = vec4<f32>(uniforms.julia_pos.x, 0.0, uniforms.julia_pos.y, 0.0);
+    } else {
+        c = pos;
+    }
+
+    let escape = escape_radius();
+    let escape_sq = escape * escape;
+    let n = i32(uniforms.exponent);
+
+    derivative = start_derivative();
+    for (var i: i32 = 0; i < uniforms.max_iter; i = i + 1) {
+        average_step(z.xz, c.xz, i);
+        var zn_sq = z.x * z.x + z.z * z.z;
+        if zn_sq >= escape_sq {
+            final_z = z.xz;
+            return escape_value(i, zn_sq);
+        }
+
+        trap_step(z.xz, i);
+        derivative = derivative_step(z.xz, derivative, fractal_type);
+        switch fractal_type {
+            case u32(0) {
+                z = pow_complex_df(z, n);
+            }
+            case u32(1) {
+                z = pow_complex_df(vec4<f32>(df_abs(z.xy), df_abs(z.zw)), n);
+            }
+            case u32(2) {
+                z = pow_complex_df(vec4<f32>(z.xy, -z.zw), n);
+            }
+            case default {}
+        }
+        z = add_complex_df(z, c);
+    }
+    final_z = z.xz;
+    return -1.0;
+}
+
 @fragment
 fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
-    let resolution = uniforms.resolution;
-    let normalized = (frag_coord.xy - resolution * 0.5 - uniforms.offset) / min(resolution.x, resolution.y) * 2.0;
-    let scaled = normalized * uniforms.zoom - uniforms.pos;
+    let res = pixel_value(frag_coord.xy);
+
+    var color = vec3<f32>(0.0, 0.0, 0.0);
+    if (res != res) {
+        color = vec3<f32>(1.0, 0.0, 1.0);
+    } else if (res >= 0) {
+        var t = res * uniforms.palette_speed;
+        if uniforms.shading_type >= u32(5) && uniforms.shading_type <= u32(7) {
+            t += average * uniforms.average_strength;
+        }
+        color = pal(
+            t,
+            uniforms.color_scheme.a,
+            uniforms.color_scheme.b,
+            uniforms.color_scheme.c,
+            uniforms.color_scheme.d,
+        );
+        if uniforms.shading_type == u32(2) && (uniforms.flags & u32(64)) == u32(64) {
+            color *= distance_shade();
+        }
+        if uniforms.shading_type == u32(3) && (uniforms.flags & u32(64)) == u32(64) {
+            color = light_shade(color);
+        }
+    }
+    if (uniforms.flags & u32(128)) == u32(128) && res == res && trap_distance < TRAP_MISSED {
+        color = pal(
+            trap_value(),
+            uniforms.color_scheme.a,
+            uniforms.color_scheme.b,
+            uniforms.color_scheme.c,
+            uniforms.color_scheme.d,
+        );
+    }
 
-    let res = mandellike(scaled, uniforms.fractal_type);
+    return vec4<f32>(glitch_overlay(color, glitch_state()), 1.0);
+}
 
-    if (res < 0) {
-        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+// the glitch state of the pixel, -1 if it's still glitched, otherwise the index of the reference it used
+fn glitch_state() -> f32 {
+    if glitched {
+        return -1.0;
     }
+    return f32(reference_index);
+}
 
-    if (res != res) {
-        return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+// the glitch overlay shows pixels that are still glitched in red,
+// and pixels that needed another reference tinted green
+fn glitch_overlay(color: vec3<f32>, state: f32) -> vec3<f32> {
+    if (uniforms.flags & u32(16)) != u32(16) {
+        return color;
+    }
+    if state < 0.0 {
+        return vec3<f32>(1.0, 0.0, 0.0);
+    } else if state > 0.0 {
+        return mix(color, vec3<f32>(0.0, 1.0, 0.0), 0.5);
     }
+    return color;
+}
+
+// the number of bins of the histogram, the escaped values between the smallest and the biggest are spread
+// evenly over them
+const HISTOGRAM_BINS: u32 = 4096u;
+
+struct Histogram {
+    // the bits of the smallest and the biggest escaped value, which sort like the values because they're positive
+    low: atomic<u32>,
+    high: atomic<u32>,
+    counts: array<atomic<u32>, HISTOGRAM_BINS>,
+}
+
+struct Cdf {
+    low: f32,
+    high: f32,
+    // the fraction of the escaped pixels in the bins before each bin, and 1 at the end, HISTOGRAM_BINS + 1
+    values: array<f32, 4097>,
+}
 
-    let color = pal(
-        res * uniforms.palette_speed,
-        uniforms.color_scheme.a,
-        uniforms.color_scheme.b,
-        uniforms.color_scheme.c,
-        uniforms.color_scheme.d,
+// the first pass of the histogram coloring, the value passed to the palette and the glitch state of the pixel
+@fragment
+fn fs_values(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec2<f32> {
+    let res = pixel_value(frag_coord.xy);
+    return vec2<f32>(res, glitch_state());
+}
+
+// whether the pixel of the values texture is in the frame, the same pixels the fragment shader runs for
+fn in_frame(pixel: vec2<u32>) -> bool {
+    let center = vec2<f32>(pixel) + 0.5;
+    return all(pixel < textureDimensions(values)) && all(center >= uniforms.offset)
+        && all(center < uniforms.offset + uniforms.resolution);
+}
+
+// find the range of the escaped values
+@compute @workgroup_size(8, 8)
+fn cs_range(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_frame(id.xy) {
+        return;
+    }
+    let value = textureLoad(values, id.xy, 0).x;
+    if value >= 0.0 {
+        atomicMin(&histogram.low, bitcast<u32>(value));
+        atomicMax(&histogram.high, bitcast<u32>(value));
+    }
+}
+
+// count the escaped values in each bin, once the range is known
+@compute @workgroup_size(8, 8)
+fn cs_histogram(@builtin(global_invocation_id) id: vec3<u32>) {
+    if !in_frame(id.xy) {
+        return;
+    }
+    let value = textureLoad(values, id.xy, 0).x;
+    if value >= 0.0 {
+        let low = bitcast<f32>(atomicLoad(&histogram.low));
+        let high = bitcast<f32>(atomicLoad(&histogram.high));
+        let bin = min(u32(histogram_position(value, low, high)), HISTOGRAM_BINS - 1u);
+        atomicAdd(&histogram.counts[bin], 1u);
+    }
+}
+
+// sum up the counts into the cumulative distribution, in a single invocation
+@compute @workgroup_size(1)
+fn cs_cdf() {
+    cdf_out.low = bitcast<f32>(atomicLoad(&histogram.low));
+    cdf_out.high = bitcast<f32>(atomicLoad(&histogram.high));
+    var total = 0u;
+    for (var bin = 0u; bin < HISTOGRAM_BINS; bin++) {
+        total += atomicLoad(&histogram.counts[bin]);
+    }
+    var sum = 0u;
+    for (var bin = 0u; bin < HISTOGRAM_BINS; bin++) {
+        cdf_out.values[bin] = f32(sum) / f32(max(total, 1u));
+        sum += atomicLoad(&histogram.counts[bin]);
+    }
+    cdf_out.values[HISTOGRAM_BINS] = 1.0;
+}
+
+// the second pass of the histogram coloring, coloring the value fs_values wrote for the pixel
+@fragment
+fn fs_histogram(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let value = textureLoad(values, vec2<u32>(frag_coord.xy), 0).xy;
+    return vec4<f32>(histogram_color(value), 1.0);
+}
+
+// the color of an escaped value is picked by the fraction of the escaped pixels with a smaller value, so the
+// palette is spread evenly over the frame whatever the iteration counts are
+fn histogram_color(value: vec2<f32>) -> vec3<f32> {
+    var color = vec3<f32>(0.0, 0.0, 0.0);
+    if value.x != value.x {
+        color = vec3<f32>(1.0, 0.0, 1.0);
+    } else if value.x >= 0.0 {
+        color = pal(
+            equalized(value.x),
+            uniforms.color_scheme.a,
+            uniforms.color_scheme.b,
+            uniforms.color_scheme.c,
+            uniforms.color_scheme.d,
+        );
+    }
+    return glitch_overlay(color, value.y);
+}
+
+// the cumulative distribution at an escaped value, interpolated inside its bin
+fn equalized(value: f32) -> f32 {
+    let position = histogram_position(value, cdf.low, cdf.high);
+    let bin = min(u32(position), HISTOGRAM_BINS - 1u);
+    return mix(cdf.values[bin], cdf.values[bin + 1u], position - f32(bin));
+}
+
+// where an escaped value is in the histogram, from 0 at the smallest value to HISTOGRAM_BINS at the biggest
+fn histogram_position(value: f32, low: f32, high: f32) -> f32 {
+    if high <= low {
+        return 0.0;
+    }
+    return clamp((value - low) / (high - low) * f32(HISTOGRAM_BINS), 0.0, f32(HISTOGRAM_BINS));
+}
+
+// how much the color of an escaped pixel is darkened by its distance to the set, 0 on the boundary and 1 from
+// boundary_width pixels away. the distance is estimated as |z| ln|z| / |dz/dc|
+fn distance_shade() -> f32 {
+    let z_abs = length(final_z);
+    let distance = z_abs * log(z_abs) / length(derivative) / pixel_size;
+    if distance != distance {
+        return 1.0;
+    }
+    return saturate(distance / uniforms.boundary_width);
+}
+
+// the blinn-phong exponent, higher makes the highlights smaller
+const SHININESS: f32 = 24.0;
+
+// color lit with lambert and blinn-phong shading. the potential falls towards the set along z / dz, so the
+// surface falls away from the set with the normal tilted that way, turned by the rotation to match the screen
+fn light_shade(color: vec3<f32>) -> vec3<f32> {
+    let slope = mul_complex(final_z, conjugate_complex(mul_complex(derivative, uniforms.rotation)));
+    let normal = normalize(vec3<f32>(normalize(slope), 1.0));
+    if normal.z != normal.z {
+        return color;
+    }
+    let light = uniforms.light.xyz;
+    let diffuse = max(dot(normal, light), 0.0);
+    let halfway = normalize(light + vec3<f32>(0.0, 0.0, 1.0));
+    let specular = uniforms.light.w * pow(max(dot(normal, halfway), 0.0), SHININESS);
+    return mix(vec3<f32>(1.0), color, uniforms.light_blend) * diffuse + specular;
+}
+
+// trap_distance before the orbit comes close to the trap
+const TRAP_MISSED: f32 = 3.0e38;
+
+fn start_trap() {
+    trap_distance = TRAP_MISSED;
+    trap_iteration = 0;
+}
+
+// record how close z at iteration i is to the trap, if bit 7 of the flags is set.
+// the starting z is left out, for the mandelbrot set it's zero for every pixel
+fn trap_step(z: vec2<f32>, i: i32) {
+    if (uniforms.flags & u32(128)) == u32(0) || i == 0 {
+        return;
+    }
+    let distance = distance_to_trap(z);
+    if uniforms.trap_shape == u32(4) && distance >= uniforms.trap_size {
+        return;
+    }
+    if distance < trap_distance {
+        trap_distance = distance;
+        trap_iteration = i;
+    }
+}
+
+fn distance_to_trap(z: vec2<f32>) -> f32 {
+    let offset = z - uniforms.trap.xy;
+    // along the lines of the trap
+    let aligned = mul_complex(offset, conjugate_complex(uniforms.trap.zw));
+    switch uniforms.trap_shape {
+        case u32(1) {
+            return abs(aligned.y);
+        }
+        case u32(2), u32(4) {
+            return min(abs(aligned.x), abs(aligned.y));
+        }
+        case u32(3) {
+            return abs(length(offset) - uniforms.trap_size);
+        }
+        case default {
+            return length(offset);
+        }
+    }
+}
+
+// the value passed to the palette for the closest approach to the trap
+fn trap_value() -> f32 {
+    if uniforms.trap_coloring == u32(1) {
+        return f32(trap_iteration) * uniforms.palette_speed;
+    }
+    return trap_distance / uniforms.trap_size;
+}
+
+// the escape radius, the average colorings need a bigger one to be smooth
+fn escape_radius() -> f32 {
+    if uniforms.shading_type >= u32(5) && uniforms.shading_type <= u32(7) {
+        return AVERAGE_ESCAPE;
+    }
+    return 4.0;
+}
+
+const AVERAGE_ESCAPE: f32 = 100.0;
+
+fn start_average() {
+    average_sum = 0.0;
+    average_count = 0;
+    average_last = 0.0;
+    average = 0.0;
+}
+
+// add the term of z at iteration i to the average coloring of the shading type
+fn average_step(z: vec2<f32>, c: vec2<f32>, i: i32) {
+    switch uniforms.shading_type {
+        case u32(5) {
+            // stripe average: stripes going out from the origin
+            if i >= 1 {
+                add_average(0.5 * sin(uniforms.stripe_frequency * atan2(z.y, z.x)) + 0.5);
+            }
+        }
+        case u32(6) {
+            // triangle inequality average: where |z| is between the smallest and the biggest it can be
+            // for the size of the previous z and c
+            if i >= 2 {
+                let power = length(z - c);
+                let c_abs = length(c);
+                let low = abs(power - c_abs);
+                let high = power + c_abs;
+                if high > low {
+                    add_average((length(z) - low) / (high - low));
+                }
+            }
+        }
+        case u32(7) {
+            // curvature average: how sharply the orbit turns
+            if i >= 2 {
+                let turn = mul_complex(z - previous_z, conjugate_complex(previous_z - previous_z2));
+                if turn.x != 0.0 || turn.y != 0.0 {
+                    add_average(abs(atan2(turn.y, turn.x)) / (0.5 * TAU));
+                }
+            }
+        }
+        case default {
+            return;
+        }
+    }
+    previous_z2 = previous_z;
+    previous_z = z;
+}
+
+fn add_average(term: f32) {
+    average_sum += term;
+    average_count += 1;
+    average_last = term;
+}
+
+// the average of the terms, moving towards the average without the last term as z goes further past the escape
+// radius, so it's continuous across the iteration the pixel escapes at
+fn average_value(fraction: f32) -> f32 {
+    if average_count == 0 {
+        return 0.0;
+    }
+    let current = average_sum / f32(average_count);
+    if average_count == 1 {
+        return current;
+    }
+    let previous = (average_sum - average_last) / f32(average_count - 1);
+    return mix(current, previous, fraction);
+}
+
+// the value passed to the palette for the pixel at frag_coord, negative if it doesn't escape
+fn pixel_value(frag_coord: vec2<f32>) -> f32 {
+    let resolution = uniforms.resolution;
+    // relative to the center of the screen, with the imaginary axis pointing up and rotated with the camera
+    let centered = (frag_coord - resolution * 0.5 - uniforms.offset) / min(resolution.x, resolution.y) * 2.0;
+    var flipped = vec2<f32>(centered.x, -centered.y);
+    pixel_size = 2.0 / min(resolution.x, resolution.y);
+    if (uniforms.flags & u32(32)) == u32(32) {
+        // exponential map: the angle around the center goes along x, one turn over the width, and the distance
+        // shrinks down y from the zoom at the top row, by the same factor per row as a pixel is wide
+        let pixel = frag_coord - uniforms.offset;
+        flipped = exp_complex(vec2<f32>(-pixel.y, pixel.x) * (TAU / resolution.x));
+        pixel_size = length(flipped) * TAU / resolution.x;
+    }
+    start_trap();
+    start_average();
+    let rotation = uniforms.rotation;
+    let normalized = vec2<f32>(
+        flipped.x * rotation.x - flipped.y * rotation.y,
+        flipped.x * rotation.y + flipped.y * rotation.x,
     );
+    let zoom = ldexp_flush(uniforms.zoom, uniforms.zoom_exp);
+    let delta = normalized * zoom.x;
+    let scaled = delta + uniforms.center.xz;
 
-    return vec4<f32>(color, 1.0);
+    var res: f32;
+    if (uniforms.flags & u32(2)) == u32(2) && uniforms.ref_count > u32(0) {
+        // try the references in order until the pixel isn't glitched
+        reference_index = 0;
+        loop {
+            glitched = false;
+            start_trap();
+            start_average();
+            res = mandellike_perturbed_reference(normalized, scaled);
+            if res != GLITCHED {
+                break;
+            }
+            reference_index += 1;
+        }
+    } else if (uniforms.flags & u32(4)) == u32(4) {
+        // offset from the camera in double-float precision before adding the camera center
+        let re = df_add(df_mul(vec2<f32>(normalized.x, 0.0), zoom), uniforms.center.xy);
+        let im = df_add(df_mul(vec2<f32>(normalized.y, 0.0), zoom), uniforms.center.zw);
+        res = mandellike_df(vec4<f32>(re, im), uniforms.fractal_type);
+    } else {
+        res = mandellike(scaled, uniforms.fractal_type);
+    }
+    return res;
+}
+
+// iterate the pixel against the reference at reference_index, with extended exponent deltas if bit 3 of the
+// flags is set
+fn mandellike_perturbed_reference(normalized: vec2<f32>, scaled: vec2<f32>) -> f32 {
+    let offset = normalized + uniforms.references[reference_index].offset;
+    if (uniforms.flags & u32(8)) == u32(8) {
+        // the offset from the reference, keeping the exponent of the zoom
+        let delta_exp = cexp_mul(cexp_new(offset, 0), cexp_new(vec2<f32>(uniforms.zoom.x, 0.0), uniforms.zoom_exp));
+        return mandellike_perturbed_exp(scaled, delta_exp, uniforms.fractal_type);
+    }
+    return mandellike_perturbed(scaled, offset * ldexp_flush(uniforms.zoom, uniforms.zoom_exp).x, uniforms.fractal_type);
 }
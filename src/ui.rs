@@ -1,9 +1,126 @@
-use eframe::egui;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-use crate::{Application, CameraInfo, ColorScheme, FractalType, ShadingType};
+use eframe::{
+    egui::{self, containers::panel::PanelState},
+    egui_wgpu::RenderState,
+};
+
+use crate::bookmarks::{self, Bookmark, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+use crate::floatexp::FloatExp;
+use crate::scene::Scene;
+use crate::{
+    data, persistence, perturbation, Application, CameraInfo, ColorScheme, FractalType, ShadingType, TrapColoring,
+    TrapShape,
+};
+
+/// The text typed into the location fields of the settings panel
+#[derive(Debug, Clone, Default)]
+pub struct LocationInput {
+    re: String,
+    im: String,
+    zoom: String,
+    error: Option<String>,
+}
+
+impl LocationInput {
+    /// Fill the fields with the center of the screen and the zoom of `camera`
+    #[must_use]
+    pub fn from_camera(camera: &CameraInfo) -> Self {
+        let center = &camera.center;
+        Self {
+            re: center.x.to_string(),
+            im: center.y.to_string(),
+            zoom: camera.zoom.to_string(),
+            error: None,
+        }
+    }
+
+    /// Parse the fields into a camera looking at the typed in location, turned by `rotation`
+    fn to_camera(&self, rotation: f64) -> Result<CameraInfo, String> {
+        let zoom = self
+            .zoom
+            .trim()
+            .parse::<FloatExp>()
+            .ok()
+            .filter(|zoom| *zoom > FloatExp::ZERO)
+            .ok_or("Zoom: expected a positive number")?;
+
+        // Keep every typed digit, even if the zoom doesn't need them yet
+        CameraInfo::parse(&self.re, &self.im, zoom, rotation).map_err(|e| format!("Position: {e}"))
+    }
+}
+
+/// The size and file name typed into the export section of the settings panel
+#[derive(Debug)]
+pub struct ExportInput {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) path: String,
+    /// The result of the last export, shown below the button
+    status: Option<Result<String, String>>,
+    /// The thread rendering and saving the current export, so the window keeps responding during large ones
+    job: Option<JoinHandle<Result<String, String>>>,
+}
+
+impl Default for ExportInput {
+    fn default() -> Self {
+        Self {
+            width: 3840,
+            height: 2160,
+            path: "mandelbrot.png".to_owned(),
+            status: None,
+            job: None,
+        }
+    }
+}
+
+/// The file name typed into the scene section of the settings panel
+#[derive(Debug, Clone)]
+pub struct SceneInput {
+    path: String,
+    /// The result of the last save or load, shown below the buttons
+    status: Option<Result<String, String>>,
+}
+
+impl Default for SceneInput {
+    fn default() -> Self {
+        Self {
+            path: "mandelbrot.toml".to_owned(),
+            status: None,
+        }
+    }
+}
+
+/// The file name typed into the import and export section of the bookmarks panel
+#[derive(Debug, Clone)]
+pub struct BookmarksInput {
+    path: String,
+    /// The result of the last import or export, shown below the buttons
+    status: Option<Result<String, String>>,
+}
+
+impl Default for BookmarksInput {
+    fn default() -> Self {
+        Self {
+            path: "bookmarks.toml".to_owned(),
+            status: None,
+        }
+    }
+}
+
+/// A change to the list of bookmarks, made after the list is drawn
+enum BookmarkAction {
+    Open(usize),
+    MoveUp(usize),
+    MoveDown(usize),
+    Delete(usize),
+}
 
 impl eframe::App for Application {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         // Check for if the F11 key is pressed
         // ctx.send_viewport_cmd crashes the program when it is called inside of ctx.input
         let mut do_fullscreen = false;
@@ -16,104 +133,621 @@ impl eframe::App for Application {
             ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(
                 !ctx.input(|i| i.viewport().fullscreen.unwrap_or(false)),
             ));
-        };
+        }
+
+        // Undo and redo, unless a text field is using the keys for its own undo
+        if !ctx.wants_keyboard_input() {
+            // Checked first, the shortcut without shift also matches when shift is held
+            let redo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z);
+            let undo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+            if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+                self.redo();
+            } else if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+                self.undo();
+            }
+        }
+
+        // Open the scene of images and scene files dropped onto the window
+        for file in ctx.input(|i| i.raw.dropped_files.clone()) {
+            self.open_dropped_file(&file);
+        }
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("file_drop")));
+            let screen = ctx.screen_rect();
+            painter.rect_filled(screen, 0.0, egui::Color32::from_black_alpha(192));
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop an exported image or a scene file to open its view",
+                egui::FontId::proportional(24.0),
+                egui::Color32::WHITE,
+            );
+        }
+
+        // Render the bookmarks panel
+        egui::SidePanel::left("bookmarks_panel")
+            .resizable(true)
+            .show(ctx, |ui| self.bookmarks_ui(ui, frame.wgpu_render_state()));
 
         // Render the settings panel
         egui::SidePanel::right("settings_panel")
             .resizable(true)
-            .show(ctx, |ui| {
-                // Double the size of the sliders (the default is 100)
-                ui.spacing_mut().slider_width = 200.0;
-                ui.vertical_centered(|ui| {
-                    if ui.button("Reset camera").clicked() {
-                        self.camera = CameraInfo::default();
-                    }
-                });
-                ui.with_layout(egui::Layout::top_down_justified(egui::Align::Min), |ui| {
-                    ui.label("Max iterations: ");
-                    ui.add(
-                        egui::Slider::new(&mut self.max_iter, 1..=4096)
-                            .logarithmic(true)
-                            .clamp_to_range(false)
-                            .smart_aim(true),
-                    );
-
-                    ui.label("Exponent: ");
-                    ui.add(
-                        egui::Slider::new(&mut self.exponent, 0.0..=6.0)
-                            .clamp_to_range(false)
-                            .smart_aim(true),
-                    );
-
-                    ui.label("Fractal: ");
-                    ui.horizontal_wrapped(|ui| {
-                        ui.radio_value(&mut self.fractal_type, FractalType::Mandelbrot, "Mandelbrot");
-                        ui.radio_value(&mut self.fractal_type, FractalType::BurningShip, "Burning Ship");
-                        ui.radio_value(&mut self.fractal_type, FractalType::Tricorn, "Tricorn");
-                    });
+            .show(ctx, |ui| self.settings_ui(ui, frame.wgpu_render_state()));
 
-                    ui.separator();
+        // Display the main shader and position info
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.columns(2, |columns| {
+                columns[0].vertical_centered(|ui| ui.label(format!("position: {}", self.camera.center)));
 
-                    ui.checkbox(&mut self.julia, "Julia set");
-                    ui.label("Julia position: ");
-                    ui.add(
-                        egui::Slider::new(&mut self.julia_pos.x, -2.0..=2.0)
-                            .clamp_to_range(false)
-                            .smart_aim(true)
-                            .prefix("x: "),
-                    );
-                    ui.add(
-                        egui::Slider::new(&mut self.julia_pos.y, -2.0..=2.0)
-                            .clamp_to_range(false)
-                            .smart_aim(true)
-                            .prefix("y: "),
-                    );
-                    ui.label("Right click on the fractal to set the location of the julia set.");
+                columns[1].vertical_centered(|ui| ui.label(format!("zoom: {}", self.camera.zoom)));
+            });
 
-                    ui.separator();
+            // Make a canvas for the shader
+            egui::Frame::canvas(ui.style()).show(ui, |ui| {
+                self.custom_painting(ui, ctx);
+            });
+        });
 
-                    ui.label("Shading Type: ");
-                    ui.horizontal_wrapped(|ui| {
-                        ui.radio_value(&mut self.shading_type, ShadingType::Normal, "Normal");
-                        ui.radio_value(&mut self.shading_type, ShadingType::Smooth, "Smooth");
-                    });
+        // Changes made while a mouse button is held are one gesture, like a drag or a dragged slider
+        let (time, holding) = ctx.input(|i| (i.time, i.pointer.any_down()));
+        self.history.update(&self.scene(), time, holding);
+    }
 
-                    ui.label("Palette Speed: ");
-                    ui.add(
-                        egui::Slider::new(&mut self.palette_speed, 0.0..=1.0)
-                            .logarithmic(true)
-                            .clamp_to_range(false)
-                            .smart_aim(true),
-                    );
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(bookmarks::STORAGE_KEY, bookmarks::to_text(&self.bookmarks));
+        storage.set_string(persistence::STORAGE_KEY, self.settings_to_text());
+    }
+}
 
-                    ui.separator();
+impl Application {
+    /// The contents of the bookmarks panel, a list of bookmarks with their thumbnails
+    fn bookmarks_ui(&mut self, ui: &mut egui::Ui, render_state: Option<&RenderState>) {
+        ui.heading("Bookmarks");
+        if ui.button("Add bookmark").clicked() {
+            let name = format!("Bookmark {}", self.bookmarks.len() + 1);
+            self.bookmarks.push(Bookmark::new(name, self.scene()));
+        }
+        ui.collapsing("Import and export", |ui| self.bookmarks_file_ui(ui));
+        ui.separator();
+
+        self.update_thumbnails(ui.ctx(), render_state);
 
-                    ui.label("Color Scheme: ");
-                    ui.horizontal_wrapped(|ui| {
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::EARTH, "Earth");
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::SKY, "Sky");
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::CRIMSON, "Crimson");
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::MIDNIGHTAMBER, "Midnight Amber");
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::RAINBOW, "Rainbow");
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::SUNSET, "Sunset");
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::MIDDAY, "Midday");
-                        ui.radio_value(&mut self.color_scheme, ColorScheme::OCEAN, "Ocean");
+        let mut action = None;
+        let count = self.bookmarks.len();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (i, bookmark) in self.bookmarks.iter_mut().enumerate() {
+                ui.push_id(i, |ui| {
+                    let size = egui::vec2(THUMBNAIL_WIDTH as f32, THUMBNAIL_HEIGHT as f32);
+                    if let Some(texture) = &bookmark.thumbnail {
+                        let response = ui.add(egui::ImageButton::new((texture.id(), size)));
+                        if response.on_hover_text("Go to this bookmark").clicked() {
+                            action = Some(BookmarkAction::Open(i));
+                        }
+                    } else {
+                        ui.add_sized(size, egui::Spinner::new());
+                    }
+                    ui.text_edit_singleline(&mut bookmark.name);
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                            action = Some(BookmarkAction::MoveUp(i));
+                        }
+                        if ui.add_enabled(i + 1 < count, egui::Button::new("Down")).clicked() {
+                            action = Some(BookmarkAction::MoveDown(i));
+                        }
+                        if ui.button("Delete").clicked() {
+                            action = Some(BookmarkAction::Delete(i));
+                        }
                     });
+                    ui.separator();
                 });
+            }
+        });
+
+        match action {
+            Some(BookmarkAction::Open(i)) => self.set_scene(self.bookmarks[i].scene.clone()),
+            Some(BookmarkAction::MoveUp(i)) => self.bookmarks.swap(i - 1, i),
+            Some(BookmarkAction::MoveDown(i)) => self.bookmarks.swap(i, i + 1),
+            Some(BookmarkAction::Delete(i)) => {
+                self.bookmarks.remove(i);
+            }
+            None => {}
+        }
+    }
+
+    /// Render the missing thumbnails one at a time in the background, so a long list doesn't freeze the window.
+    /// A spinner is shown in their place until they are done
+    fn update_thumbnails(&mut self, ctx: &egui::Context, render_state: Option<&RenderState>) {
+        let size = [THUMBNAIL_WIDTH as usize, THUMBNAIL_HEIGHT as usize];
+        for bookmark in &mut self.bookmarks {
+            if let Some(job) = bookmark.thumbnail_job.take_if(|job| job.is_finished()) {
+                // A thumbnail that couldn't be rendered stays blank instead of being retried every frame
+                let image = job.join().map_or_else(
+                    |_| egui::ColorImage::new(size, egui::Color32::DARK_GRAY),
+                    |image| egui::ColorImage::from_rgba_unmultiplied(size, &image),
+                );
+                bookmark.thumbnail = Some(ctx.load_texture("bookmark_thumbnail", image, egui::TextureOptions::LINEAR));
+            }
+        }
+
+        if !self.bookmarks.iter().any(|bookmark| bookmark.thumbnail_job.is_some()) {
+            let missing = self.bookmarks.iter_mut().find(|bookmark| bookmark.thumbnail.is_none());
+            if let Some(bookmark) = missing {
+                let copy = Bookmark::new(bookmark.name.clone(), bookmark.scene.clone());
+                let gpu = render_state.map(|state| (Arc::clone(&state.device), Arc::clone(&state.queue)));
+                bookmark.thumbnail_job = Some(thread::spawn(move || {
+                    copy.render_thumbnail(gpu.as_ref().map(|(device, queue)| (&**device, &**queue)))
+                }));
+            }
+        }
+        if self.bookmarks.iter().any(|bookmark| bookmark.thumbnail_job.is_some()) {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+    }
+
+    /// The file name field and the buttons for importing and exporting bookmarks
+    fn bookmarks_file_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("File: ");
+        ui.text_edit_singleline(&mut self.bookmarks_input.path);
+        ui.horizontal(|ui| {
+            let path = self.bookmarks_input.path.clone();
+            if ui.button("Import").clicked() {
+                self.bookmarks_input.status = Some(
+                    bookmarks::load(Path::new(&path))
+                        .map(|loaded| {
+                            let message = format!("Imported {} bookmarks from {path}", loaded.len());
+                            self.bookmarks.extend(loaded);
+                            message
+                        })
+                        .map_err(|e| e.to_string()),
+                );
+            }
+            if ui.button("Export").clicked() {
+                self.bookmarks_input.status = Some(
+                    bookmarks::save(&self.bookmarks, Path::new(&path))
+                        .map(|()| format!("Exported {} bookmarks to {path}", self.bookmarks.len()))
+                        .map_err(|e| e.to_string()),
+                );
+            }
+        });
+        status_ui(ui, self.bookmarks_input.status.as_ref());
+    }
+
+    /// The contents of the settings panel
+    fn settings_ui(&mut self, ui: &mut egui::Ui, render_state: Option<&RenderState>) {
+        // Double the size of the sliders (the default is 100)
+        ui.spacing_mut().slider_width = 200.0;
+        self.navigation_ui(ui);
+        ui.collapsing("Location", |ui| self.location_ui(ui));
+        ui.collapsing("Export image", |ui| self.export_ui(ui, render_state));
+        ui.collapsing("Scene", |ui| self.scene_ui(ui));
+        ui.with_layout(egui::Layout::top_down_justified(egui::Align::Min), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Rotation: ");
+                let mut rotation = self.camera.rotation as f32;
+                if ui.drag_angle(&mut rotation).changed() {
+                    self.camera.rotation = rotation as f64;
+                }
             });
+            ui.checkbox(&mut self.exponential_map, "Exponential map").on_hover_text(
+                "Show the angle around the center along x and the distance from it shrinking down y, \
+                 exported images are maps of any depth for rebuilding zoom videos with mandel-render. \
+                 The mouse doesn't move the view while it's shown",
+            );
 
-        // Display the main shader and position info
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.columns(2, |columns| {
-                columns[0].vertical_centered(|ui| ui.label(format!("position: {}", self.camera.pos)));
+            ui.label("Max iterations: ");
+            ui.add(
+                egui::Slider::new(&mut self.max_iter, 1..=4096)
+                    .logarithmic(true)
+                    .clamp_to_range(false)
+                    .smart_aim(true),
+            );
 
-                columns[1].vertical_centered(|ui| ui.label(format!("zoom: {}", self.camera.zoom)));
+            ui.label("Exponent: ");
+            ui.add(
+                egui::Slider::new(&mut self.exponent, 0.0..=6.0)
+                    .clamp_to_range(false)
+                    .smart_aim(true),
+            );
+
+            ui.label("Fractal: ");
+            ui.horizontal_wrapped(|ui| {
+                ui.radio_value(&mut self.fractal_type, FractalType::Mandelbrot, "Mandelbrot");
+                ui.radio_value(&mut self.fractal_type, FractalType::BurningShip, "Burning Ship");
+                ui.radio_value(&mut self.fractal_type, FractalType::Tricorn, "Tricorn");
             });
 
-            // Make a canvas for the shader
-            egui::Frame::canvas(ui.style()).show(ui, |ui| {
-                self.custom_painting(ui, ctx);
+            self.perturbation_ui(ui);
+
+            ui.separator();
+
+            ui.checkbox(&mut self.julia, "Julia set");
+            ui.label("Julia position: ");
+            ui.add(
+                egui::Slider::new(&mut self.julia_pos.x, -2.0..=2.0)
+                    .clamp_to_range(false)
+                    .smart_aim(true)
+                    .prefix("x: "),
+            );
+            ui.add(
+                egui::Slider::new(&mut self.julia_pos.y, -2.0..=2.0)
+                    .clamp_to_range(false)
+                    .smart_aim(true)
+                    .prefix("y: "),
+            );
+            ui.label("Right click on the fractal to set the location of the julia set.");
+
+            ui.separator();
+
+            self.shading_ui(ui);
+
+            ui.label("Palette Speed: ");
+            ui.add_enabled(
+                self.shading_type != ShadingType::Histogram,
+                egui::Slider::new(&mut self.palette_speed, 0.0..=1.0)
+                    .logarithmic(true)
+                    .clamp_to_range(false)
+                    .smart_aim(true),
+            );
+
+            ui.separator();
+
+            ui.label("Color Scheme: ");
+            ui.horizontal_wrapped(|ui| {
+                for (name, scheme) in ColorScheme::PRESETS {
+                    ui.radio_value(&mut self.color_scheme, scheme, name);
+                }
             });
         });
     }
+
+    /// The back, forward and reset buttons, and why the last run couldn't be restored
+    fn navigation_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            if ui
+                .add_enabled(self.history.can_undo(), egui::Button::new("Back"))
+                .on_hover_text("Undo the last change (Ctrl+Z)")
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(self.history.can_redo(), egui::Button::new("Forward"))
+                .on_hover_text("Redo the last undone change (Ctrl+Shift+Z)")
+                .clicked()
+            {
+                self.redo();
+            }
+            if ui.button("Reset camera").clicked() {
+                self.camera = CameraInfo::default();
+                self.location_input = LocationInput::from_camera(&self.camera);
+            }
+            if ui
+                .button("Reset to defaults")
+                .on_hover_text("Reset the view, every setting and the panel sizes, the bookmarks are kept")
+                .clicked()
+            {
+                self.reset_to_defaults();
+                ui.ctx().data_mut(|data| {
+                    for panel in ["bookmarks_panel", "settings_panel"] {
+                        data.remove::<PanelState>(egui::Id::new(panel));
+                    }
+                });
+            }
+        });
+        if let Some(error) = &self.restore_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+            if ui.button("Dismiss").clicked() {
+                self.restore_error = None;
+            }
+        }
+    }
+
+    /// The shading type and the parameters of the chosen one
+    fn shading_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Shading Type: ");
+        let unsupported_text = "Needs an exponent above 1, and the Mandelbrot set or a Julia set";
+        ui.horizontal_wrapped(|ui| {
+            ui.radio_value(&mut self.shading_type, ShadingType::Normal, "Normal");
+            ui.radio_value(&mut self.shading_type, ShadingType::Smooth, "Smooth");
+            let derivative_supported = ShadingType::Distance.is_supported(self.fractal_type, self.exponent, self.julia);
+            ui.add_enabled_ui(derivative_supported, |ui| {
+                ui.radio_value(&mut self.shading_type, ShadingType::Distance, "Distance")
+                    .on_hover_text("Darken the colors near the set, so thin filaments show up as lines")
+                    .on_disabled_hover_text(unsupported_text);
+                ui.radio_value(&mut self.shading_type, ShadingType::Lighting, "Lighting")
+                    .on_hover_text("Light the fractal as a surface falling away from the set")
+                    .on_disabled_hover_text(unsupported_text);
+            });
+            ui.radio_value(&mut self.shading_type, ShadingType::OrbitTrap, "Orbit trap")
+                .on_hover_text("Color by how close the orbits come to a shape, inside the set too");
+            ui.radio_value(&mut self.shading_type, ShadingType::Stripe, "Stripe")
+                .on_hover_text("Shift the colors by the average of stripes around the origin over the orbit");
+            ui.radio_value(&mut self.shading_type, ShadingType::TriangleInequality, "TIA")
+                .on_hover_text("Shift the colors by the triangle inequality average of the orbit");
+            ui.radio_value(&mut self.shading_type, ShadingType::Curvature, "Curvature")
+                .on_hover_text("Shift the colors by the average of how sharply the orbit turns");
+            ui.radio_value(&mut self.shading_type, ShadingType::Histogram, "Histogram")
+                .on_hover_text("Spread the palette evenly over the iteration counts on screen");
+        });
+        // The shading stays selected when the fractal changes under it, so it comes back with a fractal it works for
+        if !self
+            .shading_type
+            .is_supported(self.fractal_type, self.exponent, self.julia)
+        {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!("{unsupported_text}, smooth shading is shown instead"),
+            );
+        }
+        ui.label("Boundary width: ");
+        ui.add_enabled(
+            self.shading_type == ShadingType::Distance,
+            egui::Slider::new(&mut self.boundary_width, 0.1..=16.0)
+                .logarithmic(true)
+                .clamp_to_range(false)
+                .suffix(" px"),
+        );
+        ui.add_enabled_ui(self.shading_type == ShadingType::Lighting, |ui| self.light_ui(ui));
+        ui.add_enabled_ui(self.shading_type == ShadingType::OrbitTrap, |ui| self.trap_ui(ui));
+        self.averages_ui(ui);
+    }
+
+    /// The direction, height and strength of the light of the lighting shading
+    fn light_ui(&mut self, ui: &mut egui::Ui) {
+        let light = &mut self.light;
+        ui.horizontal(|ui| {
+            ui.label("Light direction: ");
+            ui.drag_angle(&mut light.angle);
+        });
+        ui.label("Light height: ");
+        ui.add(egui::Slider::new(&mut light.height, 0.0..=4.0).smart_aim(true));
+        ui.label("Specular: ");
+        ui.add(egui::Slider::new(&mut light.specular, 0.0..=1.0).clamp_to_range(false));
+        ui.label("Palette blend: ")
+            .on_hover_text("How much of the palette color is kept, lower values light the fractal in gray");
+        ui.add(egui::Slider::new(&mut light.blend, 0.0..=1.0));
+    }
+
+    /// The shape, geometry and coloring of the orbit trap
+    fn trap_ui(&mut self, ui: &mut egui::Ui) {
+        let trap = &mut self.trap;
+        ui.label("Trap shape: ");
+        ui.horizontal_wrapped(|ui| {
+            ui.radio_value(&mut trap.shape, TrapShape::Point, "Point");
+            ui.radio_value(&mut trap.shape, TrapShape::Line, "Line");
+            ui.radio_value(&mut trap.shape, TrapShape::Cross, "Cross");
+            ui.radio_value(&mut trap.shape, TrapShape::Circle, "Circle");
+            ui.radio_value(&mut trap.shape, TrapShape::Stalks, "Stalks")
+                .on_hover_text("Pickover stalks, pixels that never come close get the smooth coloring");
+        });
+        ui.label("Trap center: ")
+            .on_hover_text("Drag the handle on the fractal to move it");
+        for (value, prefix) in [(&mut trap.center.x, "x: "), (&mut trap.center.y, "y: ")] {
+            ui.add(
+                egui::Slider::new(value, -2.0..=2.0)
+                    .clamp_to_range(false)
+                    .smart_aim(true)
+                    .prefix(prefix),
+            );
+        }
+        ui.horizontal(|ui| {
+            ui.label("Trap angle: ");
+            ui.drag_angle(&mut trap.angle);
+        });
+        ui.label("Trap size: ")
+            .on_hover_text("The radius of the circle, the width of the stalks and how far the palette cycles");
+        ui.add(
+            egui::Slider::new(&mut trap.size, 0.001..=2.0)
+                .logarithmic(true)
+                .clamp_to_range(false),
+        );
+        ui.label("Color by: ");
+        ui.horizontal_wrapped(|ui| {
+            ui.radio_value(&mut trap.coloring, TrapColoring::Distance, "Distance")
+                .on_hover_text("How close the orbit came to the trap");
+            ui.radio_value(&mut trap.coloring, TrapColoring::Iteration, "Iteration")
+                .on_hover_text("When the orbit came closest to the trap");
+        });
+    }
+
+    /// The parameters of the average colorings, each only enabled while it's the shading
+    fn averages_ui(&mut self, ui: &mut egui::Ui) {
+        let averages = &mut self.averages;
+        let strength = |ui: &mut egui::Ui, label: &str, value: &mut f32| {
+            ui.label(label)
+                .on_hover_text("How far the average moves through the palette");
+            ui.add(egui::Slider::new(value, 0.0..=4.0).clamp_to_range(false));
+        };
+        ui.add_enabled_ui(self.shading_type == ShadingType::Stripe, |ui| {
+            ui.label("Stripe frequency: ")
+                .on_hover_text("The number of stripes per turn around the origin");
+            ui.add(egui::Slider::new(&mut averages.stripe_frequency, 1.0..=16.0).clamp_to_range(false));
+            strength(ui, "Stripe strength: ", &mut averages.stripe_strength);
+        });
+        ui.add_enabled_ui(self.shading_type == ShadingType::TriangleInequality, |ui| {
+            strength(ui, "TIA strength: ", &mut averages.tia_strength);
+        });
+        ui.add_enabled_ui(self.shading_type == ShadingType::Curvature, |ui| {
+            strength(ui, "Curvature strength: ", &mut averages.curvature_strength);
+        });
+    }
+
+    /// The perturbation settings and the series approximation
+    fn perturbation_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add_enabled(
+            perturbation::is_supported(self.fractal_type, self.exponent),
+            egui::Checkbox::new(&mut self.perturbation, "Perturbation"),
+        )
+        .on_hover_text("Render deep zooms relative to a high precision reference orbit")
+        .on_disabled_hover_text(
+            "Perturbation needs an integer exponent of at least 2, or exactly 2 for the Burning Ship",
+        );
+
+        let series_supported =
+            self.use_perturbation() && perturbation::series_is_supported(self.fractal_type, self.exponent);
+        ui.add_enabled_ui(series_supported, |ui| {
+            ui.checkbox(&mut self.series_approximation, "Series approximation")
+                .on_hover_text("Skip the first iterations of every pixel with a polynomial in its offset")
+                .on_disabled_hover_text(
+                    "The series approximation needs perturbation and the exponent 2 Mandelbrot set",
+                );
+            ui.label("Series tolerance: ");
+            ui.add_enabled(
+                self.series_approximation,
+                egui::Slider::new(&mut self.series_tolerance, 1e-12..=1e-1).logarithmic(true),
+            );
+            ui.label(format!(
+                "Skipped iterations: {}",
+                self.series.map_or(0, |series| series.skip)
+            ));
+        });
+
+        ui.add_enabled_ui(self.use_perturbation(), |ui| {
+            ui.checkbox(&mut self.glitch_correction, "Glitch correction")
+                .on_hover_text("Render glitched pixels again with extra reference orbits placed inside them");
+            ui.checkbox(&mut self.show_glitches, "Show glitches").on_hover_text(
+                "Highlight pixels that are still glitched in red, and pixels that needed an extra reference in green",
+            );
+            ui.label(format!("Reference orbits: {}", self.references().len()));
+        });
+    }
+
+    /// The text fields for typing in a location
+    fn location_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Real: ");
+        ui.text_edit_singleline(&mut self.location_input.re);
+        ui.label("Imaginary: ");
+        ui.text_edit_singleline(&mut self.location_input.im);
+        ui.label("Zoom: ");
+        ui.text_edit_singleline(&mut self.location_input.zoom);
+        ui.horizontal(|ui| {
+            if ui.button("Go").clicked() {
+                match self.location_input.to_camera(self.camera.rotation) {
+                    Ok(camera) => {
+                        self.camera = camera;
+                        self.location_input.error = None;
+                    }
+                    Err(error) => self.location_input.error = Some(error),
+                }
+            }
+            if ui.button("Use current").clicked() {
+                self.location_input = LocationInput::from_camera(&self.camera);
+            }
+        });
+        if let Some(error) = &self.location_input.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+
+    /// The size and file name fields and the button for exporting the current view
+    fn export_ui(&mut self, ui: &mut egui::Ui, render_state: Option<&RenderState>) {
+        ui.horizontal(|ui| {
+            ui.label("Size: ");
+            ui.add(egui::DragValue::new(&mut self.export_input.width).range(1..=32768));
+            ui.label("x");
+            ui.add(egui::DragValue::new(&mut self.export_input.height).range(1..=32768));
+        });
+        ui.label("File: ");
+        ui.text_edit_singleline(&mut self.export_input.path).on_hover_text(
+            "A PNG, or a .npy or .mdat file for the raw iteration counts, z and derivatives of every pixel",
+        );
+
+        if let Some(job) = self.export_input.job.take_if(|job| job.is_finished()) {
+            self.export_input.status = Some(job.join().unwrap_or_else(|_| Err("The export failed".to_owned())));
+        }
+        let exporting = self.export_input.job.is_some();
+        if ui.add_enabled(!exporting, egui::Button::new("Export")).clicked() {
+            self.start_export(render_state);
+        }
+        if exporting {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Exporting...");
+            });
+            // Check for the end of the export without waiting for input
+            ui.ctx().request_repaint_after(Duration::from_millis(100));
+        } else {
+            status_ui(ui, self.export_input.status.as_ref());
+        }
+    }
+
+    /// Export the current view with the size and file name of the export fields on another thread
+    fn start_export(&mut self, render_state: Option<&RenderState>) {
+        let input = &self.export_input;
+        let (path, width, height) = (PathBuf::from(&input.path), input.width, input.height);
+        let mut exporter = self.exporter();
+        let gpu = render_state.map(|state| (Arc::clone(&state.device), Arc::clone(&state.queue)));
+        self.export_input.job = Some(thread::spawn(move || {
+            let result = if data::is_data_path(&path) {
+                exporter.export_data(&path, width, height).map_err(|e| e.to_string())
+            } else {
+                let gpu = gpu.as_ref().map(|(device, queue)| (&**device, &**queue));
+                exporter
+                    .export_image(&path, width, height, gpu)
+                    .map_err(|e| e.to_string())
+            };
+            result.map(|()| format!("Saved {width}x{height} to {}", path.display()))
+        }));
+    }
+
+    /// The file name field and the buttons for saving and loading the settings as a scene
+    fn scene_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("File: ");
+        ui.text_edit_singleline(&mut self.scene_input.path);
+        ui.horizontal(|ui| {
+            let path = self.scene_input.path.clone();
+            if ui.button("Save").clicked() {
+                self.scene_input.status = Some(
+                    self.scene()
+                        .save(Path::new(&path))
+                        .map(|()| format!("Saved to {path}"))
+                        .map_err(|e| e.to_string()),
+                );
+            }
+            if ui.button("Load").clicked() {
+                self.scene_input.status = Some(
+                    Scene::load(Path::new(&path))
+                        .map(|scene| {
+                            self.set_scene(scene);
+                            format!("Loaded {path}")
+                        })
+                        .map_err(|e| e.to_string()),
+                );
+            }
+        });
+        status_ui(ui, self.scene_input.status.as_ref());
+    }
+}
+
+impl Application {
+    /// Restore the scene of an exported PNG or a scene file dropped onto the window
+    fn open_dropped_file(&mut self, file: &egui::DroppedFile) {
+        // Native windows give a path, the web gives the contents
+        let (name, result) = match (&file.path, &file.bytes) {
+            (Some(path), _) => (path.display().to_string(), Scene::load(path)),
+            (None, Some(bytes)) => (file.name.clone(), Scene::from_bytes(bytes)),
+            (None, None) => return,
+        };
+        self.scene_input.status = Some(
+            result
+                .map(|scene| {
+                    self.set_scene(scene);
+                    format!("Opened {name}")
+                })
+                .map_err(|e| format!("{name}: {e}")),
+        );
+    }
+}
+
+/// Show the result of the last export, save or load, if there was one
+fn status_ui(ui: &mut egui::Ui, status: Option<&Result<String, String>>) {
+    match status {
+        Some(Ok(message)) => {
+            ui.label(message);
+        }
+        Some(Err(error)) => {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        None => {}
+    }
 }
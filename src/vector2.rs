@@ -2,10 +2,14 @@ use eframe::egui::{Pos2, Vec2, Vec2b};
 
 use std::fmt::Display;
 
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use num_traits::{Float, Signed};
+
+use crate::bigfloat::BigFloat;
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Vector2<T: Clone> {
     pub x: T,
     pub y: T,
@@ -19,6 +23,8 @@ pub type Vector2i = Vector2<i32>;
 
 pub type Vector2b = Vector2<bool>;
 
+pub type Vector2Big = Vector2<BigFloat>;
+
 impl<T, Rhs, W> Add<Vector2<Rhs>> for Vector2<T>
 where
     T: Clone + Add<Rhs, Output = W>,
@@ -115,6 +121,18 @@ where
     }
 }
 
+impl<T, W> Neg for Vector2<T>
+where
+    T: Clone + Neg<Output = W>,
+    W: Clone,
+{
+    type Output = Vector2<W>;
+
+    fn neg(self) -> Self::Output {
+        Self::Output::new(-self.x, -self.y)
+    }
+}
+
 impl<T: Clone + Default> Default for Vector2<T> {
     fn default() -> Self {
         Self::new(Default::default(), Default::default())
@@ -131,6 +149,104 @@ impl<T: Clone> Vector2<T> {
     pub const fn new(x: T, y: T) -> Self {
         Self { x, y }
     }
+
+    /// Multiply each component by the same component of `rhs`
+    #[must_use]
+    pub fn mul_elementwise<Rhs, W>(self, rhs: Vector2<Rhs>) -> Vector2<W>
+    where
+        T: Mul<Rhs, Output = W>,
+        Rhs: Clone,
+        W: Clone,
+    {
+        Vector2::new(self.x * rhs.x, self.y * rhs.y)
+    }
+
+    /// Divide each component by the same component of `rhs`
+    #[must_use]
+    pub fn div_elementwise<Rhs, W>(self, rhs: Vector2<Rhs>) -> Vector2<W>
+    where
+        T: Div<Rhs, Output = W>,
+        Rhs: Clone,
+        W: Clone,
+    {
+        Vector2::new(self.x / rhs.x, self.y / rhs.y)
+    }
+}
+
+impl<T: Clone + Add<Output = T> + Sub<Output = T> + Mul<Output = T>> Vector2<T> {
+    #[must_use]
+    pub fn dot(self, rhs: Self) -> T {
+        self.x * rhs.x + self.y * rhs.y
+    }
+
+    /// The z component of the 3D cross product, positive if `rhs` is counterclockwise from `self`
+    #[must_use]
+    pub fn cross(self, rhs: Self) -> T {
+        self.x * rhs.y - self.y * rhs.x
+    }
+
+    /// The squared length, which unlike [`Vector2::length`] needs no square root
+    #[must_use]
+    pub fn length_sqr(self) -> T {
+        self.clone().dot(self)
+    }
+
+    /// Linear interpolation, `self` at `t = 0` and `rhs` at `t = 1`
+    #[must_use]
+    pub fn lerp(self, rhs: Self, t: T) -> Self
+    where
+        T: Copy,
+    {
+        self + (rhs - self) * t
+    }
+}
+
+impl<T: Clone + PartialOrd> Vector2<T> {
+    /// The smaller value of each component
+    #[must_use]
+    pub fn min(self, rhs: Self) -> Self {
+        Self::new(
+            if rhs.x < self.x { rhs.x } else { self.x },
+            if rhs.y < self.y { rhs.y } else { self.y },
+        )
+    }
+
+    /// The larger value of each component
+    #[must_use]
+    pub fn max(self, rhs: Self) -> Self {
+        Self::new(
+            if rhs.x > self.x { rhs.x } else { self.x },
+            if rhs.y > self.y { rhs.y } else { self.y },
+        )
+    }
+}
+
+impl<T: Clone + Signed> Vector2<T> {
+    /// The absolute value of each component
+    #[must_use]
+    pub fn abs(self) -> Self {
+        Self::new(self.x.abs(), self.y.abs())
+    }
+}
+
+impl<T: Float> Vector2<T> {
+    #[must_use]
+    pub fn length(self) -> T {
+        self.x.hypot(self.y)
+    }
+
+    /// The vector with the same direction and a length of 1, or NaN for the zero vector
+    #[must_use]
+    pub fn normalize(self) -> Self {
+        self / self.length()
+    }
+
+    /// Rotate counterclockwise by `angle` radians
+    #[must_use]
+    pub fn rotate(self, angle: T) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
 }
 
 impl<T: Clone> From<Vector2<T>> for (T, T) {
@@ -157,6 +273,46 @@ impl<T: Clone> From<[T; 2]> for Vector2<T> {
     }
 }
 
+impl From<Vector2f> for Vector2d {
+    fn from(value: Vector2f) -> Self {
+        Self::new(value.x.into(), value.y.into())
+    }
+}
+
+impl From<Vector2i> for Vector2d {
+    fn from(value: Vector2i) -> Self {
+        Self::new(value.x.into(), value.y.into())
+    }
+}
+
+/// Rounds to the nearest [`f32`]
+impl From<Vector2d> for Vector2f {
+    fn from(value: Vector2d) -> Self {
+        Self::new(value.x as f32, value.y as f32)
+    }
+}
+
+/// Rounds to the nearest [`f32`] if the components don't fit exactly
+impl From<Vector2i> for Vector2f {
+    fn from(value: Vector2i) -> Self {
+        Self::new(value.x as f32, value.y as f32)
+    }
+}
+
+/// Truncates towards zero and saturates like `as`
+impl From<Vector2f> for Vector2i {
+    fn from(value: Vector2f) -> Self {
+        Self::new(value.x as i32, value.y as i32)
+    }
+}
+
+/// Truncates towards zero and saturates like `as`
+impl From<Vector2d> for Vector2i {
+    fn from(value: Vector2d) -> Self {
+        Self::new(value.x as i32, value.y as i32)
+    }
+}
+
 impl From<Pos2> for Vector2f {
     fn from(value: Pos2) -> Self {
         Self::new(value.x, value.y)
@@ -186,3 +342,444 @@ impl From<Vec2b> for Vector2b {
         Self::new(value.x, value.y)
     }
 }
+
+/// A complex number `re + im * i`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Complex<T: Clone> {
+    pub re: T,
+    pub im: T,
+}
+
+pub type Complexf = Complex<f32>;
+
+pub type Complexd = Complex<f64>;
+
+impl<T: Clone> Complex<T> {
+    pub const fn new(re: T, im: T) -> Self {
+        Self { re, im }
+    }
+}
+
+impl<T: Clone + Neg<Output = T>> Complex<T> {
+    #[must_use]
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+}
+
+impl<T: Clone + Add<Output = T> + Mul<Output = T>> Complex<T> {
+    /// `|z|^2`, which unlike [`Complex::abs`] needs no square root
+    #[must_use]
+    pub fn norm_sqr(self) -> T {
+        self.re.clone() * self.re + self.im.clone() * self.im
+    }
+}
+
+impl<T: Float> Complex<T> {
+    /// The imaginary unit
+    #[must_use]
+    pub fn i() -> Self {
+        Self::new(T::zero(), T::one())
+    }
+
+    #[must_use]
+    pub fn from_polar(r: T, theta: T) -> Self {
+        Self::new(r * theta.cos(), r * theta.sin())
+    }
+
+    /// `(|z|, arg z)`
+    #[must_use]
+    pub fn to_polar(self) -> (T, T) {
+        (self.abs(), self.arg())
+    }
+
+    #[must_use]
+    pub fn abs(self) -> T {
+        self.re.hypot(self.im)
+    }
+
+    /// The angle to the positive real axis in `(-pi, pi]`
+    #[must_use]
+    pub fn arg(self) -> T {
+        self.im.atan2(self.re)
+    }
+
+    /// `1 / z`
+    #[must_use]
+    pub fn inv(self) -> Self {
+        self.conj() / self.norm_sqr()
+    }
+
+    /// `z^n` by repeated squaring, which is exact for small integer results unlike [`Complex::powf`]
+    #[must_use]
+    pub fn powi(self, n: i32) -> Self {
+        let mut base = if n < 0 { self.inv() } else { self };
+        let mut exponent = n.unsigned_abs();
+        let mut result = Self::new(T::one(), T::zero());
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result *= base;
+            }
+            base = base * base;
+            exponent >>= 1;
+        }
+        result
+    }
+
+    /// `z^n` for a real exponent, on the principal branch. `0^0` is one like [`Complex::powi`]
+    #[must_use]
+    pub fn powf(self, n: T) -> Self {
+        if n.is_zero() {
+            return Self::new(T::one(), T::zero());
+        }
+        if self.re.is_zero() && self.im.is_zero() {
+            return self;
+        }
+        let (r, theta) = self.to_polar();
+        Self::from_polar(r.powf(n), theta * n)
+    }
+
+    /// `z^w` for a complex exponent, on the principal branch. `0^0` is one like [`Complex::powi`]
+    #[must_use]
+    pub fn powc(self, w: Self) -> Self {
+        if w.re.is_zero() && w.im.is_zero() {
+            return Self::new(T::one(), T::zero());
+        }
+        if self.re.is_zero() && self.im.is_zero() {
+            return self;
+        }
+        (self.ln() * w).exp()
+    }
+
+    #[must_use]
+    pub fn exp(self) -> Self {
+        Self::from_polar(self.re.exp(), self.im)
+    }
+
+    /// The natural logarithm on the principal branch, the imaginary part is in `(-pi, pi]`
+    #[must_use]
+    pub fn ln(self) -> Self {
+        let (r, theta) = self.to_polar();
+        Self::new(r.ln(), theta)
+    }
+
+    #[must_use]
+    pub fn sqrt(self) -> Self {
+        self.powf(T::from(0.5).unwrap())
+    }
+
+    #[must_use]
+    pub fn sin(self) -> Self {
+        Self::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+
+    #[must_use]
+    pub fn cos(self) -> Self {
+        Self::new(self.re.cos() * self.im.cosh(), -(self.re.sin() * self.im.sinh()))
+    }
+}
+
+impl<T: Clone + Add<Output = T>> Add for Complex<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl<T: Clone + AddAssign> AddAssign for Complex<T> {
+    fn add_assign(&mut self, rhs: Self) {
+        self.re += rhs.re;
+        self.im += rhs.im;
+    }
+}
+
+impl<T: Clone + Sub<Output = T>> Sub for Complex<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl<T: Clone + SubAssign> SubAssign for Complex<T> {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.re -= rhs.re;
+        self.im -= rhs.im;
+    }
+}
+
+impl<T> Mul for Complex<T>
+where
+    T: Clone + Add<Output = T> + Sub<Output = T> + Mul<Output = T>,
+{
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.re.clone() * rhs.re.clone() - self.im.clone() * rhs.im.clone(),
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl<T> MulAssign for Complex<T>
+where
+    T: Clone + Add<Output = T> + Sub<Output = T> + Mul<Output = T>,
+{
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = self.clone() * rhs;
+    }
+}
+
+impl<T: Clone + Mul<Output = T>> Mul<T> for Complex<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: T) -> Self::Output {
+        Self::new(self.re * rhs.clone(), self.im * rhs)
+    }
+}
+
+impl<T> Div for Complex<T>
+where
+    T: Clone + Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Div<Output = T> + Neg<Output = T>,
+{
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        let norm_sqr = rhs.clone().norm_sqr();
+        self * rhs.conj() / norm_sqr
+    }
+}
+
+impl<T> DivAssign for Complex<T>
+where
+    T: Clone + Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Div<Output = T> + Neg<Output = T>,
+{
+    fn div_assign(&mut self, rhs: Self) {
+        *self = self.clone() / rhs;
+    }
+}
+
+impl<T: Clone + Div<Output = T>> Div<T> for Complex<T> {
+    type Output = Self;
+
+    fn div(self, rhs: T) -> Self::Output {
+        Self::new(self.re / rhs.clone(), self.im / rhs)
+    }
+}
+
+impl<T: Clone + Neg<Output = T>> Neg for Complex<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self::new(-self.re, -self.im)
+    }
+}
+
+impl<T: Clone + Default> Default for Complex<T> {
+    fn default() -> Self {
+        Self::new(Default::default(), Default::default())
+    }
+}
+
+impl<T: Clone + Display> Display for Complex<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} + {}i", self.re, self.im)
+    }
+}
+
+impl<T: Clone> From<Vector2<T>> for Complex<T> {
+    fn from(value: Vector2<T>) -> Self {
+        Self::new(value.x, value.y)
+    }
+}
+
+impl<T: Clone> From<Complex<T>> for Vector2<T> {
+    fn from(value: Complex<T>) -> Self {
+        Self::new(value.re, value.im)
+    }
+}
+
+impl From<Pos2> for Complexf {
+    fn from(value: Pos2) -> Self {
+        Self::new(value.x, value.y)
+    }
+}
+
+impl From<Complexf> for Pos2 {
+    fn from(value: Complexf) -> Self {
+        Self::new(value.re, value.im)
+    }
+}
+
+impl From<Vec2> for Complexf {
+    fn from(value: Vec2) -> Self {
+        Self::new(value.x, value.y)
+    }
+}
+
+impl From<Complexf> for Vec2 {
+    fn from(value: Complexf) -> Self {
+        Self::new(value.re, value.im)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::f64::consts::{E, FRAC_PI_2, PI};
+
+    use super::*;
+
+    fn assert_vector_close(a: Vector2d, b: Vector2d) {
+        assert!((a - b).length() < 1e-12, "{a} != {b}");
+    }
+
+    #[test]
+    fn dot_and_cross_products() {
+        let a = Vector2d::new(2.0, 1.0);
+        let b = Vector2d::new(-1.0, 3.0);
+        assert_eq!(a.dot(b), 1.0);
+        assert_eq!(a.cross(b), 7.0);
+        assert_eq!(b.cross(a), -7.0);
+        assert_eq!(Vector2i::new(3, 4).length_sqr(), 25);
+    }
+
+    #[test]
+    fn length_and_normalize() {
+        let v = Vector2d::new(3.0, -4.0);
+        assert_eq!(v.length(), 5.0);
+        assert_vector_close(v.normalize(), Vector2d::new(0.6, -0.8));
+        assert!(Vector2d::default().normalize().x.is_nan());
+    }
+
+    #[test]
+    fn rotate_and_lerp() {
+        assert_vector_close(Vector2d::new(1.0, 0.0).rotate(FRAC_PI_2), Vector2d::new(0.0, 1.0));
+        assert_vector_close(Vector2d::new(1.0, 2.0).rotate(PI).rotate(-PI), Vector2d::new(1.0, 2.0));
+        let a = Vector2d::new(0.0, 10.0);
+        let b = Vector2d::new(4.0, -2.0);
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.25), Vector2d::new(1.0, 7.0));
+    }
+
+    #[test]
+    fn component_operations() {
+        let a = Vector2i::new(-3, 5);
+        let b = Vector2i::new(2, -7);
+        assert_eq!(a.min(b), Vector2i::new(-3, -7));
+        assert_eq!(a.max(b), Vector2i::new(2, 5));
+        assert_eq!(a.abs(), Vector2i::new(3, 5));
+        assert_eq!(-a, Vector2i::new(3, -5));
+        assert_eq!(a.mul_elementwise(b), Vector2i::new(-6, -35));
+        assert_eq!(
+            Vector2d::new(6.0, 1.0).div_elementwise(Vector2d::new(2.0, 4.0)),
+            Vector2d::new(3.0, 0.25)
+        );
+    }
+
+    #[test]
+    fn conversions_between_component_types() {
+        let i = Vector2i::new(-2, 7);
+        assert_eq!(Vector2i::from(Vector2d::from(i)), i);
+        assert_eq!(Vector2i::from(Vector2f::from(i)), i);
+        assert_eq!(Vector2d::from(Vector2f::new(0.5, -1.5)), Vector2d::new(0.5, -1.5));
+        assert_eq!(Vector2i::from(Vector2d::new(1.9, -1.9)), Vector2i::new(1, -1));
+        let set: HashSet<Vector2i> = [i, i, -i].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    fn assert_close(a: Complexd, b: Complexd) {
+        assert!((a - b).abs() < 1e-12, "{a} != {b}");
+    }
+
+    #[test]
+    fn i_squared_is_minus_one() {
+        assert_eq!(Complexd::i() * Complexd::i(), Complexd::new(-1.0, 0.0));
+        assert_eq!(Complexd::i().powi(2), Complexd::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn division_undoes_multiplication() {
+        let a = Complexd::new(1.5, -2.0);
+        let b = Complexd::new(-0.25, 3.0);
+        assert_close(a * b / b, a);
+        assert_close(b.inv() * b, Complexd::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn conjugate_product_is_norm() {
+        let z = Complexd::new(3.0, 4.0);
+        assert_eq!(z * z.conj(), Complexd::new(z.norm_sqr(), 0.0));
+        assert_eq!(z.abs(), 5.0);
+        assert_eq!(Complexd::new(0.0, 2.0).arg(), FRAC_PI_2);
+    }
+
+    #[test]
+    fn eulers_identity() {
+        assert_close((Complexd::i() * PI).exp(), Complexd::new(-1.0, 0.0));
+        assert_close(Complexd::new(1.0, 0.0).exp(), Complexd::new(E, 0.0));
+    }
+
+    #[test]
+    fn exp_and_ln_are_inverse() {
+        let z = Complexd::new(0.5, -1.25);
+        assert_close(z.ln().exp(), z);
+        assert_close(z.exp().ln(), z);
+        assert_close(Complexd::new(-1.0, 0.0).ln(), Complexd::i() * PI);
+    }
+
+    #[test]
+    fn integer_powers_match_repeated_multiplication() {
+        let z = Complexd::new(0.75, 0.5);
+        assert_close(z.powi(5), z * z * z * z * z);
+        assert_close(z.powi(-3), (z * z * z).inv());
+        assert_eq!(z.powi(0), Complexd::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn real_and_complex_powers() {
+        let z = Complexd::new(-2.0, 1.0);
+        assert_close(z.powf(3.0), z.powi(3));
+        assert_close(z.sqrt() * z.sqrt(), z);
+        assert_close(z.powc(Complexd::new(3.0, 0.0)), z.powi(3));
+        // i^i is real
+        assert_close(
+            Complexd::i().powc(Complexd::i()),
+            Complexd::new((-FRAC_PI_2).exp(), 0.0),
+        );
+        assert_eq!(Complexd::default().powf(2.0), Complexd::default());
+    }
+
+    /// A zero exponent gives one for every base, zero included, the same as [`Complex::powi`]
+    #[test]
+    fn zero_exponent_is_one() {
+        let one = Complexd::new(1.0, 0.0);
+        for z in [Complexd::default(), Complexd::new(-2.0, 1.0), Complexd::i()] {
+            assert_eq!(z.powi(0), one);
+            assert_eq!(z.powf(0.0), one, "{z:?}");
+            assert_eq!(z.powc(Complexd::default()), one, "{z:?}");
+        }
+        assert_eq!(Complexd::default().powc(Complexd::new(2.0, 1.0)), Complexd::default());
+    }
+
+    #[test]
+    fn pythagorean_identity() {
+        let z = Complexd::new(1.2, -0.7);
+        let one = z.sin() * z.sin() + z.cos() * z.cos();
+        assert_close(one, Complexd::new(1.0, 0.0));
+        assert_close((Complexd::i() * z).exp(), z.cos() + Complexd::i() * z.sin());
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let z = Complexf::new(1.0, -2.0);
+        assert_eq!(Complexf::from(Vector2f::from(z)), z);
+        assert_eq!(Complexf::from(Pos2::from(z)), z);
+        assert_eq!(Complexf::from(Vec2::from(z)), z);
+        assert_eq!(Vector2f::from(z), Vector2f::new(1.0, -2.0));
+    }
+}
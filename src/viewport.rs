@@ -0,0 +1,192 @@
+//! The mapping between the pixels on the screen and points in the complex plane.
+
+use eframe::egui::{Pos2, Rect, Vec2};
+
+use crate::bigfloat::BigFloat;
+use crate::vector2::{Vector2Big, Vector2d, Vector2f};
+use crate::CameraInfo;
+
+/// Where the fractal is drawn on the screen and which part of the complex plane it shows.
+///
+/// Points on the screen are in egui points, with y pointing down. In the complex plane the imaginary axis points
+/// up. In between are normalized coordinates, which are centered on the screen, have the imaginary axis pointing
+/// up and rotated with the camera, and are scaled so the shorter side of the screen goes from -1 to 1.
+/// The shader computes the same normalized coordinates from the uniforms made by [`Viewport::resolution`],
+/// [`Viewport::offset`] and [`Viewport::rotation`].
+#[derive(Debug, Clone)]
+pub struct Viewport {
+    /// The area of the screen the fractal is drawn in, in points
+    pub rect: Rect,
+    /// The number of physical pixels per point
+    pub pixels_per_point: f32,
+    pub camera: CameraInfo,
+}
+
+impl Viewport {
+    #[must_use]
+    pub fn new(rect: Rect, pixels_per_point: f32, camera: CameraInfo) -> Self {
+        Self {
+            rect,
+            pixels_per_point,
+            camera,
+        }
+    }
+
+    /// The number of points from the center of the screen to the closest edge
+    #[must_use]
+    pub fn scale(&self) -> f32 {
+        self.rect.width().min(self.rect.height()) / 2.0
+    }
+
+    /// The size of the screen area in physical pixels, which the shader calls the resolution
+    #[must_use]
+    pub fn resolution(&self) -> [f32; 2] {
+        (self.rect.size() * self.pixels_per_point).into()
+    }
+
+    /// The top left corner of the screen area in physical pixels
+    #[must_use]
+    pub fn offset(&self) -> [f32; 2] {
+        (self.rect.min.to_vec2() * self.pixels_per_point).into()
+    }
+
+    /// The cosine and sine of the camera rotation, as the shader takes it
+    #[must_use]
+    pub fn rotation(&self) -> [f32; 2] {
+        let (sin, cos) = self.camera.rotation.sin_cos();
+        [cos as f32, sin as f32]
+    }
+
+    /// Convert a direction on the screen in points to normalized coordinates
+    #[must_use]
+    pub fn screen_vector_to_normalized(&self, vector: Vec2) -> Vector2d {
+        let vector = Vector2d::from(Vector2f::from(vector)) / self.scale() as f64;
+        Vector2d::new(vector.x, -vector.y).rotate(self.camera.rotation)
+    }
+
+    /// Convert a direction in normalized coordinates to points on the screen
+    #[must_use]
+    pub fn normalized_vector_to_screen(&self, vector: Vector2d) -> Vec2 {
+        let vector = vector.rotate(-self.camera.rotation) * self.scale() as f64;
+        Vec2::new(vector.x as f32, -vector.y as f32)
+    }
+
+    /// Convert a position on the screen to normalized coordinates
+    #[must_use]
+    pub fn screen_to_normalized(&self, pos: Pos2) -> Vector2d {
+        self.screen_vector_to_normalized(pos - self.rect.center())
+    }
+
+    /// Convert normalized coordinates to a position on the screen
+    #[must_use]
+    pub fn normalized_to_screen(&self, normalized: Vector2d) -> Pos2 {
+        self.rect.center() + self.normalized_vector_to_screen(normalized)
+    }
+
+    /// The point in the complex plane that is drawn at `pos` on the screen
+    #[must_use]
+    pub fn screen_to_complex(&self, pos: Pos2) -> Vector2Big {
+        let normalized = self.screen_to_normalized(pos);
+        let precision = BigFloat::precision_for(self.camera.zoom).max(self.camera.center.precision());
+        self.camera.center.clone().with_precision(precision)
+            + Vector2Big::new(
+                BigFloat::from_float_exp(self.camera.zoom * normalized.x),
+                BigFloat::from_float_exp(self.camera.zoom * normalized.y),
+            )
+    }
+
+    /// The position on the screen where `point` in the complex plane is drawn
+    #[must_use]
+    pub fn complex_to_screen(&self, point: &Vector2Big) -> Pos2 {
+        let offset = (point.clone() - self.camera.center.clone()).to_float_exp();
+        let normalized = Vector2d::new(
+            (offset.x / self.camera.zoom).to_f64(),
+            (offset.y / self.camera.zoom).to_f64(),
+        );
+        self.normalized_to_screen(normalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::floatexp::FloatExp;
+
+    fn viewport(rotation: f64) -> Viewport {
+        let camera = CameraInfo {
+            center: Vector2Big::from_f64(Vector2d::new(-0.75, 0.1)).unwrap(),
+            zoom: FloatExp::from_f64(0.5),
+            rotation,
+        };
+        Viewport::new(
+            Rect::from_min_size(Pos2::new(10.0, 20.0), Vec2::new(400.0, 300.0)),
+            2.0,
+            camera,
+        )
+    }
+
+    fn assert_close(a: Pos2, b: Pos2) {
+        assert!(a.distance(b) < 1e-3, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn center_of_the_screen_is_the_camera_center() {
+        let viewport = viewport(0.3);
+        let center = viewport.screen_to_complex(viewport.rect.center()).to_f64();
+        assert_eq!(center, Vector2d::new(-0.75, 0.1));
+    }
+
+    #[test]
+    fn shorter_side_spans_twice_the_zoom() {
+        let viewport = viewport(0.0);
+        let top = viewport.screen_to_complex(Pos2::new(210.0, 20.0)).to_f64();
+        let right = viewport.screen_to_complex(Pos2::new(410.0, 170.0)).to_f64();
+        assert_eq!(top, Vector2d::new(-0.75, 0.6));
+        assert_eq!(right, Vector2d::new(-0.75 + 0.5 * 400.0 / 300.0, 0.1));
+    }
+
+    #[test]
+    fn imaginary_axis_points_up() {
+        let viewport = viewport(0.0);
+        let above = viewport
+            .screen_to_complex(viewport.rect.center() - Vec2::new(0.0, 10.0))
+            .to_f64();
+        assert!(above.y > 0.1);
+    }
+
+    #[test]
+    fn rotation_is_counterclockwise() {
+        let viewport = viewport(FRAC_PI_2);
+        // With a quarter turn, the right edge of the screen shows what was above the center
+        let right = viewport.screen_to_normalized(viewport.rect.center() + Vec2::new(150.0, 0.0));
+        assert!((right - Vector2d::new(0.0, 1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn pixels_round_trip() {
+        for rotation in [0.0, 0.7, -2.5] {
+            let viewport = viewport(rotation);
+            for pos in [Pos2::new(10.0, 20.0), Pos2::new(123.5, 271.25), Pos2::new(410.0, 320.0)] {
+                assert_close(viewport.complex_to_screen(&viewport.screen_to_complex(pos)), pos);
+                assert_close(viewport.normalized_to_screen(viewport.screen_to_normalized(pos)), pos);
+            }
+        }
+    }
+
+    #[test]
+    fn deep_zooms_round_trip() {
+        let mut viewport = viewport(1.0);
+        viewport.camera.zoom = "1e-500".parse().unwrap();
+        let pos = Pos2::new(42.0, 99.0);
+        assert_close(viewport.complex_to_screen(&viewport.screen_to_complex(pos)), pos);
+    }
+
+    #[test]
+    fn physical_pixels() {
+        let viewport = viewport(0.0);
+        assert_eq!(viewport.resolution(), [800.0, 600.0]);
+        assert_eq!(viewport.offset(), [20.0, 40.0]);
+    }
+}